@@ -0,0 +1,4 @@
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    haml::build::compile_dir("templates", &out_dir).unwrap();
+}
@@ -0,0 +1,6 @@
+//! The templates in `templates`, compiled by `haml::build` from the build
+//! script.
+
+pub mod templates {
+    include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+}
@@ -0,0 +1,16 @@
+use hamlrs_build_test::templates;
+
+#[test]
+fn generated_functions() {
+    let mut context = haml::Context::new();
+    context.insert("name", "jon");
+    assert_eq!("<p>Hello</p>", templates::index(&context));
+    assert_eq!(
+        "<p class='z a'></p>\n<p class='a jon z'></p>",
+        templates::classes(&context)
+    );
+    assert_eq!("<p>jon</p>", templates::r#type(&context));
+    assert_eq!("<h1>Users</h1>", templates::users(&context));
+    assert_eq!("<p>jon</p>", templates::users::show_all(&context));
+    assert_eq!("<p>404</p>", templates::users::_404(&context));
+}
@@ -0,0 +1,182 @@
+//! Haml templates checked and compiled into Rust code along with the crate
+//! using them, so rendering never parses anything.
+//!
+//! `#[derive(HamlTemplate)]` implements [`haml::HamlTemplate`] for a struct
+//! whose fields are the variables of the template:
+//!
+//! ```
+//! use haml::HamlTemplate;
+//! use haml_derive::HamlTemplate;
+//!
+//! #[derive(HamlTemplate)]
+//! #[template(source = "%h1= title\n- items.each do |item|\n  %p= item")]
+//! struct List {
+//!     title: String,
+//!     items: Vec<u32>,
+//! }
+//!
+//! let list = List { title: "Numbers".to_string(), items: vec![1, 2] };
+//! assert_eq!("<h1>Numbers</h1>\n<p>1</p>\n<p>2</p>", list.render());
+//! ```
+//!
+//! `#[template(path = "templates/list.haml")]` reads the template from a
+//! file relative to the crate's `Cargo.toml` instead, and `haml!` renders a
+//! template with the variables in scope:
+//!
+//! ```
+//! use haml_derive::haml;
+//!
+//! let name = "Jon";
+//! assert_eq!("<p>Jon</p>", haml!("%p= name"));
+//! ```
+//!
+//! Errors in the template are compiler errors. See `haml::codegen` for how
+//! the code of a template is translated to Rust.
+
+use std::path::PathBuf;
+
+use haml::codegen::{self, Variables};
+use haml::{Format, HamlOptions};
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput, LitBool, LitStr};
+
+/// Implements `haml::HamlTemplate` for a struct using the template given by
+/// its `#[template]` attribute, which takes:
+///
+/// - `path`, the template file relative to the crate's `Cargo.toml`, or
+///   `source`, the template itself,
+/// - `format`, one of `html4`, `html5`, `xhtml` and `xml`, defaulting to
+///   `html5`,
+/// - `escape_html`, whether the result of `=` is escaped, defaulting to
+///   false.
+#[proc_macro_derive(HamlTemplate, attributes(template))]
+pub fn derive_haml_template(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match derive(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Renders a template to a `String` with the variables in scope where it is
+/// used.
+#[proc_macro]
+pub fn haml(input: TokenStream) -> TokenStream {
+    let source = parse_macro_input!(input as LitStr);
+    let block = match block(
+        &source.value(),
+        &HamlOptions::new(),
+        Variables::Locals(),
+        &source,
+    ) {
+        Ok(block) => block,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let out = out();
+    quote!({
+        let mut __haml_html = ::std::string::String::new();
+        let #out: &mut dyn ::std::fmt::Write = &mut __haml_html;
+        // writing to a String never fails
+        let _ = (|| -> ::std::fmt::Result #block)();
+        __haml_html
+    })
+    .into()
+}
+
+fn derive(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut path: Option<LitStr> = None;
+    let mut source: Option<LitStr> = None;
+    let mut options = HamlOptions::new();
+    let attribute = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("template"))
+        .ok_or_else(|| {
+            syn::Error::new(
+                Span::call_site(),
+                "expected #[template(path = \"...\")] or #[template(source = \"...\")]",
+            )
+        })?;
+    attribute.parse_nested_meta(|meta| {
+        if meta.path.is_ident("path") {
+            path = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("source") {
+            source = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("format") {
+            let format: LitStr = meta.value()?.parse()?;
+            options.format = match format.value().as_str() {
+                "html4" => Format::Html4(),
+                "html5" => Format::Html5(),
+                "xhtml" => Format::XHtml(),
+                "xml" => Format::Xml(),
+                _ => return Err(meta.error("expected html4, html5, xhtml or xml")),
+            };
+        } else if meta.path.is_ident("escape_html") {
+            let escape_html: LitBool = meta.value()?.parse()?;
+            options.escape_html = escape_html.value;
+        } else {
+            return Err(meta.error("unknown template option"));
+        }
+        Ok(())
+    })?;
+
+    let (block, tracked) = match (path, source) {
+        (Some(path), None) => {
+            let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+            let file = PathBuf::from(root).join(path.value());
+            let haml = std::fs::read_to_string(&file).map_err(|err| {
+                let message = format!("could not read {}: {}", file.display(), err);
+                syn::Error::new(path.span(), message)
+            })?;
+            let block = block(&haml, &options, Variables::Fields(), &path)?;
+            // including the file makes cargo build again when it changes
+            let file = file.to_string_lossy().to_string();
+            (block, Some(file))
+        }
+        (None, Some(source)) => (
+            block(&source.value(), &options, Variables::Fields(), &source)?,
+            None,
+        ),
+        _ => {
+            let message = "expected exactly one of path and source";
+            return Err(syn::Error::new_spanned(attribute, message));
+        }
+    };
+    let tracked = tracked.map(|file| {
+        quote!(
+            const _: &str = ::std::include_str!(#file);
+        )
+    });
+    let out = out();
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::haml::HamlTemplate for #name #type_generics #where_clause {
+            fn render_to(&self, #out: &mut dyn ::std::fmt::Write) -> ::std::fmt::Result {
+                #tracked
+                #block
+            }
+        }
+    })
+}
+
+fn out() -> proc_macro2::Ident {
+    proc_macro2::Ident::new(codegen::OUT, Span::call_site())
+}
+
+/// The Rust code of a template, or its errors pointing at `origin`.
+fn block(
+    haml: &str,
+    options: &HamlOptions,
+    variables: Variables,
+    origin: &LitStr,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let code = codegen::rust_block(haml, options, variables)
+        .map_err(|err| syn::Error::new(origin.span(), err.to_string()))?;
+    code.parse().map_err(|err| {
+        let message = format!("could not compile the template: {}", err);
+        syn::Error::new(origin.span(), message)
+    })
+}
@@ -0,0 +1,93 @@
+use haml::HamlTemplate;
+use haml_derive::{haml, HamlTemplate};
+
+struct User {
+    name: String,
+    admin: bool,
+}
+
+#[derive(HamlTemplate)]
+#[template(path = "tests/templates/users.haml")]
+struct Users {
+    title: &'static str,
+    users: Vec<User>,
+}
+
+#[test]
+fn template_file() {
+    let users = Users {
+        title: "Users",
+        users: vec![
+            User {
+                name: "Jon".to_string(),
+                admin: true,
+            },
+            User {
+                name: "Ann".to_string(),
+                admin: false,
+            },
+        ],
+    };
+    let html = "<!DOCTYPE html>\n<html>\n<head>\n<title>Users</title>\n</head>\n<body>\n<p>2 users</p>\n<ul>\n<li class='user-0'>\nJon\n</li>\n<li class='user-1'>\nAnn\n<span class='role'>member</span>\n</li>\n</ul>\n</body>\n</html>";
+    assert_eq!(html, users.render());
+
+    let empty = Users {
+        title: "Users",
+        users: vec![],
+    };
+    assert!(empty.render().contains("<body>\n<p>No users</p>\n</body>"));
+}
+
+#[derive(HamlTemplate)]
+#[template(source = "%p= text\n%p&= text\n%br", format = "xhtml")]
+struct Escaped<'a> {
+    text: &'a str,
+}
+
+#[test]
+fn template_options() {
+    let escaped = Escaped { text: "<b>" };
+    assert_eq!("<p><b></p>\n<p>&lt;b&gt;</p>\n<br />", escaped.render());
+}
+
+#[derive(HamlTemplate)]
+#[template(source = "- pairs.each do |key, value|\n  %dt= key\n  %dd= value")]
+struct Pairs {
+    pairs: Vec<(String, i32)>,
+}
+
+#[test]
+fn template_loops() {
+    let pairs = Pairs {
+        pairs: vec![("a".to_string(), 1), ("b".to_string(), 2)],
+    };
+    let html = "<dt>a</dt>\n<dd>1</dd>\n<dt>b</dt>\n<dd>2</dd>";
+    assert_eq!(html, pairs.render());
+}
+
+#[derive(HamlTemplate)]
+#[template(
+    source = "%input{type: 'checkbox', checked: true, disabled: false, title: nil, value: count}"
+)]
+struct Checkbox {
+    count: u32,
+}
+
+#[test]
+fn template_attributes() {
+    assert_eq!(
+        "<input checked type='checkbox' value='2'>",
+        Checkbox { count: 2 }.render()
+    );
+}
+
+#[test]
+fn haml_macro() {
+    let name = "Jon";
+    let count = 3;
+    assert_eq!("<p>Jon</p>", haml!("%p= name"));
+    assert_eq!(
+        "<div class='count-3'>\n<span>more</span>\n</div>",
+        haml!("%div{:class => \"count-#{count}\"}\n  - if count > 2\n    %span more")
+    );
+}
@@ -1,24 +1,31 @@
-use crate::formatter::HtmlFormatter;
 use crate::parser::Haml;
+use crate::span::Span;
 
+/// Every node of a document, stored flat and linked by index. The root is
+/// always at index 0.
 #[derive(Debug)]
 pub struct Arena {
     items: Vec<ArenaItem>,
 }
 
+/// A node of the document along with the indices of its parent and children.
 #[derive(Debug)]
 pub struct ArenaItem {
     pub value: Haml,
     pub parent: usize,
     pub children: Vec<usize>,
+    /// Where the node was written in the template, including any attribute
+    /// lines or filter body belonging to it but not its nested children.
+    pub span: Span,
 }
 
 impl ArenaItem {
-    pub fn new(value: Haml, parent: usize) -> ArenaItem {
+    pub fn new(value: Haml, parent: usize, span: Span) -> ArenaItem {
         ArenaItem {
             value,
             parent,
             children: vec![],
+            span,
         }
     }
 }
@@ -26,12 +33,12 @@ impl ArenaItem {
 impl Arena {
     pub fn new() -> Arena {
         Arena {
-            items: vec![ArenaItem::new(Haml::Root(), 0)],
+            items: vec![ArenaItem::new(Haml::Root(), 0, Span::default())],
         }
     }
 
-    pub fn insert(&mut self, haml: Haml, parent: usize) -> usize {
-        self.items.push(ArenaItem::new(haml, parent));
+    pub fn insert(&mut self, haml: Haml, parent: usize, span: Span) -> usize {
+        self.items.push(ArenaItem::new(haml, parent, span));
         let idx: usize = self.items.len() - 1;
         if idx > 0 {
             self.items[parent].children.push(idx);
@@ -39,6 +46,10 @@ impl Arena {
         idx
     }
 
+    pub(crate) fn set_span(&mut self, i: usize, span: Span) {
+        self.items[i].span = span;
+    }
+
     pub fn parent(&self, i: usize) -> usize {
         self.items[i].parent
     }
@@ -55,143 +66,19 @@ impl Arena {
         &self.items[0]
     }
 
-    pub fn from_whitespace(&self, start_index: usize, ws: usize) -> usize {
-        let mut idx = start_index;
-        let mut parent = start_index;
-        
-        loop {
-            let i = &self.items[idx];
-            match &i.value {
-                Haml::Element(ref el) => {
-                    if el.whitespace == 0 && ws == 0 {
-                        parent = 0;
-                        break;
-                    }
-                    if el.whitespace < ws {
-                        parent = idx;
-                        break;
-                    }
-                }
-                Haml::SilentComment(whitespace) => {
-                    if *whitespace == 0 as usize && ws == 0 {
-                        break;
-                    }
-                    if *whitespace < ws {
-                        parent = idx;
-                        break;
-                    }
-                },
-                Haml::ConditionalComment(whitespace, _) => {
-                    if *whitespace == 0 as usize && ws == 0 {
-                        break;
-                    }
-                    if *whitespace < ws {
-                        parent = idx;
-                        break;
-                    }
-                }
-                _ => idx = i.parent,
-            }
-        }
-        parent
+    /// The number of items, including the root.
+    pub fn len(&self) -> usize {
+        self.items.len()
     }
 
-    // pub fn to_html(&self) -> String {
-    //     self.formatter.generate(self)
-    //     let mut html = String::new();
-    //     let root = self.root();
-    //     for child in root.children.iter() {
-    //         let item = self.item(*child);
-    //         match item.value {
-    //             Haml::SilentComment(_) => (),
-    //             _ => html.push_str(&self.formatter.generate(item))
-    //         }
-    //         // html.push_str(&self.item_to_html(self.item(*child)));
-    //     }
-    //     html.trim().to_owned()
-    // }
-
-    // fn item_to_html(&self, item: &ArenaItem) -> String {
-    //     match &item.value {
-    //         Haml::Text(text) => format!("{}\n",text.to_owned()),
-    //         Haml::Comment(comment) => self.comment_to_html(&item),
-    //         Haml::Element(_) => self.element_to_html(&item),
-    //         Haml::InnerText(text) => text.to_owned(),
-    //         Haml::Prolog(Some(prolog)) => prolog.to_owned(),
-    //         _ => String::new(),
-    //     }
-    // }
-
-    // fn comment_to_html(&self, item: &ArenaItem) -> String {
-    //     let mut html = String::new();
-    //     if let Haml::Comment(line) = &item.value {
-    //         html.push_str(&format!("<!--{}", line));
-    //     }
-    //     if item.children.len() > 0 {
-    //         html.push('\n');
-    //     } else {
-    //         html.push(' ');
-    //     }
-    //     for child in item.children.iter() {
-    //         let item = self.item(*child);
-    //         html.push_str(&self.item_to_html(item));
-    //     }
-    //     html.push_str("-->");
-    //     html
-    // }
-    // fn element_to_html(&self, item: &ArenaItem) -> String {
-    //     if let Haml::Element(el) = &item.value {
-
-    //         html.push_str(&format!("<{}", el.name().unwrap()));
-    //         for key in el.attributes().iter() {
-    //             if let Some(value) = el.get_attribute(key) {
-    //                 // this needs to be separated eventuallyas this is html5 specific
-    //                 if key.trim() == "checked" {
-    //                     match &self.format {
-    //                         Format::Html5() => if value == "true" {
-    //                             html.push_str(" checked");
-    //                         },
-    //                         _ => match value.as_ref() {
-    //                             "true" => html.push_str(" checked='checked'"),
-    //                             _ => html.push_str(&format!(" checked='{}'", value)),
-    //                         }
-    //                     }
-    //                 } else {
-    //                     html.push_str(&format!(" {}='{}'", key, value));
-    //                 }
-    //             }
-    //         }
+    /// Always false, as an arena contains at least the root.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
 
-    //         if Some("input".to_owned()) == el.name() || Some("meta".to_owned()) == el.name() {
-    //             match self.format {
-    //                 Format::XHtml() => html.push_str(" />"),
-    //                 _ => html.push('>'),
-    //             }
-    //         } else {
-    //             html.push('>');
-    //         }
-    //         if !el.self_close {
-    //         if let Some(text) = &el.inline_text {
-    //             html.push_str(&format!("{}", text.trim()));
-    //         }
-    //         if item.children.len() > 0 {
-    //             let mut index = 0;
-    //             if Some("pre".to_owned()) != el.name() && Some("textarea".to_owned()) != el.name() {
-    //                 html.push('\n');
-    //             }
-    //             for c in item.children.iter() {
-    //                 let i = self.item(*c);
-    //                 html.push_str(&self.item_to_html(i));
-    //             }
-    //         }
-    //         if Some("pre".to_owned()) == el.name() || Some("textarea".to_owned()) == el.name() {
-    //             html  =html.trim_end().to_owned();
-    //         }
-    //         if Some("input".to_owned()) != el.name() {
-    //         html.push_str(&format!("</{}>\n", el.name().unwrap()));
-    //         }
-    //         }
-    //     }
-    //     html
-    // }
+impl Default for Arena {
+    fn default() -> Arena {
+        Arena::new()
+    }
 }
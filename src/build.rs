@@ -0,0 +1,196 @@
+//! Compiles a directory of templates into Rust code from a build script, for
+//! crates that can't use the `haml_derive` proc macros.
+//!
+//! ```no_run
+//! // in build.rs
+//! let out_dir = std::env::var("OUT_DIR").unwrap();
+//! haml::build::compile_dir("templates", &out_dir).unwrap();
+//! ```
+//!
+//! The templates are then included in the crate as a module, with one
+//! function per template rendering it with a [`Context`](crate::Context).
+//! `templates/users/show.haml` becomes `templates::users::show`, and a
+//! template named after a keyword, like `type.haml`, becomes `r#type`. Two
+//! templates in a directory that make the same name, like `a-b.haml` and
+//! `a_b.haml`, are an error:
+//!
+//! ```ignore
+//! mod templates {
+//!     include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+//! }
+//!
+//! let html = templates::users::show(&context);
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::codegen::{self, Variables};
+use crate::error::{Error, ErrorKind, Result};
+use crate::options::HamlOptions;
+use crate::span::Span;
+
+/// The name of the file written into the output directory.
+pub const FILE_NAME: &str = "templates.rs";
+
+/// Compiles every `.haml` file under `dir` with the default options and
+/// writes the module to [`FILE_NAME`] in `out_dir`, returning its path.
+pub fn compile_dir<P: AsRef<Path>, Q: AsRef<Path>>(dir: P, out_dir: Q) -> Result<PathBuf> {
+    compile_dir_with_options(dir, out_dir, &HamlOptions::new())
+}
+
+/// Compiles every `.haml` file under `dir` with `options`. Prints a
+/// `cargo:rerun-if-changed` line for every file and directory read, so that
+/// cargo runs the build script again when a template is changed or added.
+pub fn compile_dir_with_options<P: AsRef<Path>, Q: AsRef<Path>>(
+    dir: P,
+    out_dir: Q,
+    options: &HamlOptions,
+) -> Result<PathBuf> {
+    let dir = dir.as_ref();
+    let mut code = format!(
+        "// Generated by haml::build from {}. Do not edit.\n",
+        dir.display()
+    );
+    module(dir, dir, options, &mut code)?;
+    let path = out_dir.as_ref().join(FILE_NAME);
+    fs::write(&path, code).map_err(|err| io_error(&path, err))?;
+    Ok(path)
+}
+
+/// Writes the functions of the templates in `dir` and a module for each
+/// directory in it.
+fn module(root: &Path, dir: &Path, options: &HamlOptions, code: &mut String) -> Result<()> {
+    println!("cargo:rerun-if-changed={}", dir.display());
+    let entries = fs::read_dir(dir).and_then(|entries| {
+        entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()
+    });
+    let mut entries = entries.map_err(|err| io_error(dir, err))?;
+    entries.sort();
+    // functions and modules don't share a namespace, so `users.haml` and
+    // `users/` may sit side by side
+    let mut functions = HashMap::new();
+    let mut modules = HashMap::new();
+    for path in entries {
+        let name = match path.file_stem().map(|stem| stem.to_string_lossy()) {
+            Some(stem) if !stem.starts_with('.') => identifier(&stem),
+            _ => continue,
+        };
+        let is_template = path
+            .extension()
+            .is_some_and(|extension| extension == "haml");
+        let names = match (path.is_dir(), is_template) {
+            (true, _) => &mut modules,
+            (false, true) => &mut functions,
+            (false, false) => continue,
+        };
+        let name = name.map_err(|name| {
+            let reason = format!("{} can't be used as a Rust name", name);
+            name_error(&path, reason)
+        })?;
+        if let Some(other) = names.insert(name.clone(), path.clone()) {
+            let reason = format!(
+                "{} and {} are both named {}",
+                other.display(),
+                path.display(),
+                name
+            );
+            return Err(name_error(&path, reason));
+        }
+        if path.is_dir() {
+            let _ = writeln!(code, "\n#[allow(clippy::all)]\npub mod {} {{", name);
+            module(root, &path, options, code)?;
+            code.push_str("}\n");
+        } else {
+            println!("cargo:rerun-if-changed={}", path.display());
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let template = relative.with_extension("");
+            let template = template.to_string_lossy();
+            let haml = fs::read_to_string(&path).map_err(|err| io_error(&path, err))?;
+            let block = codegen::rust_block(&haml, options, Variables::Context())
+                .map_err(|err| err.in_template(&template))?;
+            let _ = write!(
+                code,
+                "\n/// Renders `{}`.\n\
+                 #[allow(clippy::all)]\n\
+                 pub fn {}(context: &::haml::Context) -> ::std::string::String {{\n\
+                 let mut html = ::std::string::String::new();\n\
+                 let {}: &mut dyn ::std::fmt::Write = &mut html;\n\
+                 // writing to a String never fails\n\
+                 let _ = (|| -> ::std::fmt::Result {})();\n\
+                 html\n\
+                 }}\n",
+                relative.display(),
+                name,
+                codegen::OUT,
+                block
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Keywords that can only be used as names when written as raw identifiers.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+/// Names that can't be used at all, not even as raw identifiers.
+const RESERVED: &[&str] = &["_", "crate", "self", "Self", "super"];
+
+/// A Rust identifier for a file name, with anything but letters, digits and
+/// underscores replaced by underscores, and keywords written as raw
+/// identifiers. Fails with the identifier when it can't be used as a name.
+fn identifier(name: &str) -> std::result::Result<String, String> {
+    let mut identifier: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    if !identifier.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        identifier.insert(0, '_');
+    }
+    if RESERVED.contains(&identifier.as_str()) {
+        return Err(identifier);
+    }
+    if KEYWORDS.contains(&identifier.as_str()) {
+        identifier.insert_str(0, "r#");
+    }
+    Ok(identifier)
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> Error {
+    let reason = format!("{}: {}", path.display(), err);
+    Error::new(ErrorKind::Io(reason), Span::default(), "")
+}
+
+fn name_error(path: &Path, reason: String) -> Error {
+    let reason = format!("{}: {}", path.display(), reason);
+    Error::new(ErrorKind::InvalidTemplateName(reason), Span::default(), "")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(Ok("show".to_string()), identifier("show"));
+        assert_eq!(Ok("user_list".to_string()), identifier("user-list"));
+        assert_eq!(Ok("_404".to_string()), identifier("404"));
+        assert_eq!(Ok("r#type".to_string()), identifier("type"));
+        assert_eq!(Ok("r#async".to_string()), identifier("async"));
+        assert_eq!(Err("self".to_string()), identifier("self"));
+        assert_eq!(Err("_".to_string()), identifier("-"));
+    }
+}
@@ -0,0 +1,704 @@
+//! Turns a template into Rust code writing its HTML, for templates compiled
+//! along with the crate using them. Used by the `haml_derive` crate and by
+//! [`crate::build`].
+//!
+//! The generated code writes the HTML of everything that does not depend on
+//! the variables as string literals. With [`Variables::Fields`] and
+//! [`Variables::Locals`], the rest is written with Rust expressions
+//! translated from the code of the template: `user.name` reads the field
+//! `name`, `items.size` calls `items.len()` and `- items.each do |item|`
+//! loops over `items.iter()`, so using a variable the wrong way is a
+//! compiler error rather than something found when rendering. Conditions
+//! must be `bool`s, `nil`, arrays and hashes are not supported, and an
+//! attribute set to code is written with `Display`, so only a literal
+//! `true`, `false` or `nil` makes it boolean or leaves it out. With
+//! [`Variables::Context`], the code is evaluated against a [`Context`]
+//! as it is when rendering a [`crate::Template`]. Partials and layouts are
+//! not supported either way.
+//!
+//! [`Context`]: crate::Context
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::arena::{Arena, ArenaItem};
+use crate::context::{Context, Scope};
+use crate::error::{Error, ErrorKind, Result};
+use crate::expr::{Comparison, Expr, Segment};
+use crate::formatter::{self, is_tight, HtmlFormatter, Precomputed};
+use crate::options::{HamlOptions, Newline};
+use crate::parse_with_options;
+use crate::parser::element::{AttributeValue, Element};
+use crate::parser::{Code, Each, Filter, Haml, Interpolated};
+use crate::Format;
+use serde_json::Value;
+
+/// How the generated code reaches the variables of a template.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variables {
+    /// As fields of `self`, for a struct the template belongs to.
+    Fields(),
+    /// As variables in scope where the code is inserted.
+    Locals(),
+    /// As the variables of a `&haml::Context` called `context`.
+    Context(),
+}
+
+/// The name of the `&mut dyn std::fmt::Write` the generated code writes
+/// into.
+pub const OUT: &str = "__haml_out";
+
+// the scope the code of the template is evaluated in with
+// `Variables::Context`
+const SCOPE: &str = "__haml_scope";
+
+const VALUE: &str = "::haml::runtime::Value";
+
+/// Generates a Rust block writing the HTML of `haml` into [`OUT`], which
+/// must be in scope. The block evaluates to a `std::fmt::Result`.
+pub fn rust_block(haml: &str, options: &HamlOptions, variables: Variables) -> Result<String> {
+    let document = parse_with_options(haml, options)?;
+    let arena = document.arena();
+    let formatter = formatter::get_formatter(options);
+    let mut generator = Generator {
+        haml,
+        arena,
+        formatter: Precomputed::new(formatter, arena, HashMap::new())?,
+        variables,
+        locals: vec![],
+        siblings: 0,
+        codes: 0,
+        code: String::new(),
+    };
+    generator.line("{");
+    if variables == Variables::Context() {
+        generator.line(&format!(
+            "let {} = &::haml::runtime::Scope::new(context);",
+            SCOPE
+        ));
+    }
+    if options.newline != Newline::Lf() {
+        generator.line(&format!(
+            "let {} = &mut ::haml::runtime::NewlineWriter::new({}, {:?});",
+            OUT,
+            OUT,
+            options.newline.as_str()
+        ));
+    }
+    generator.children_block(arena.root(), false)?;
+    generator.line("::std::result::Result::Ok(())");
+    generator.line("}");
+    Ok(generator.code)
+}
+
+struct Generator<'a> {
+    haml: &'a str,
+    arena: &'a Arena,
+    formatter: Precomputed,
+    variables: Variables,
+    // the block variables of the loops around the code being generated
+    locals: Vec<String>,
+    // the number of `Siblings` declared so far, used to name the next one
+    siblings: usize,
+    // the number of expressions declared so far with `Variables::Context`
+    codes: usize,
+    code: String,
+}
+
+impl Generator<'_> {
+    fn line(&mut self, line: &str) {
+        self.code.push_str(line);
+        self.code.push('\n');
+    }
+
+    fn write_str(&mut self, html: &str) {
+        if !html.is_empty() {
+            self.line(&format!(
+                "::std::fmt::Write::write_str({}, {:?})?;",
+                OUT, html
+            ));
+        }
+    }
+
+    fn error(&self, item: &ArenaItem, kind: ErrorKind) -> Error {
+        let snippet = self.haml.lines().nth(item.span.line.saturating_sub(1));
+        Error::new(kind, item.span, snippet.unwrap_or_default())
+    }
+
+    fn unsupported(&self, item: &ArenaItem, what: &str) -> Error {
+        let reason = format!("{} can't be used in a compiled template", what);
+        self.error(item, ErrorKind::InvalidExpression(reason))
+    }
+
+    /// Declares the siblings of the children of `item` and writes them,
+    /// as the formatter does for an element or the root.
+    fn children_block(&mut self, item: &ArenaItem, leading_newline: bool) -> Result<String> {
+        let siblings = format!("__haml_siblings_{}", self.siblings);
+        self.siblings += 1;
+        self.line(&format!(
+            "#[allow(unused_mut)] let mut {} = ::haml::runtime::Siblings::new({});",
+            siblings, leading_newline
+        ));
+        self.children(item, &siblings)?;
+        Ok(siblings)
+    }
+
+    fn children(&mut self, item: &ArenaItem, siblings: &str) -> Result<()> {
+        let mut children = item.children.iter().peekable();
+        while let Some(index) = children.next() {
+            let child = self.arena.item(*index);
+            match &child.value {
+                Haml::If(code) | Haml::Unless(code) => {
+                    let condition = self.condition(child, code)?;
+                    match &child.value {
+                        Haml::If(_) => self.line(&format!("if {} {{", condition)),
+                        _ => self.line(&format!("if !({}) {{", condition)),
+                    }
+                    self.children(child, siblings)?;
+                    while let Some(branch) = children.peek().map(|next| self.arena.item(**next)) {
+                        match &branch.value {
+                            Haml::ElsIf(code) => {
+                                let condition = self.condition(branch, code)?;
+                                self.line(&format!("}} else if {} {{", condition));
+                            }
+                            Haml::Else() => self.line("} else {"),
+                            _ => break,
+                        }
+                        children.next();
+                        self.children(branch, siblings)?;
+                    }
+                    self.line("}");
+                }
+                Haml::Each(each) => self.each(each, child, siblings)?,
+                Haml::Partial(_) => return Err(self.unsupported(child, "render")),
+                Haml::Yield(_) => return Err(self.unsupported(child, "yield")),
+                Haml::ContentFor(_) => return Err(self.unsupported(child, "content_for")),
+                _ => self.item(*index, child, siblings)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn each(&mut self, each: &Each, item: &ArenaItem, siblings: &str) -> Result<()> {
+        let collection = self.expression(item, &each.code)?;
+        if self.variables == Variables::Context() {
+            let variables: Vec<String> =
+                each.variables.iter().map(|v| format!("{:?}", v)).collect();
+            self.line(&format!(
+                "for __haml_locals in ::haml::runtime::loop_locals({}.into_owned(), &[{}], {}) {{",
+                collection,
+                variables.join(", "),
+                each.with_index
+            ));
+            self.line(&format!("let {} = &{}.child(__haml_locals);", SCOPE, SCOPE));
+            self.children(item, siblings)?;
+            self.line("}");
+            return Ok(());
+        }
+        let mut variables = each.variables.clone();
+        let index = match each.with_index {
+            true => variables.pop(),
+            false => None,
+        };
+        let mut pattern = match variables.len() {
+            1 => variables[0].clone(),
+            _ => format!("({})", variables.join(", ")),
+        };
+        let mut iterator = format!("({}).iter()", collection);
+        if let Some(index) = &index {
+            pattern = format!("({}, {})", index, pattern);
+            iterator.push_str(".enumerate()");
+        }
+        self.line(&format!("for {} in {} {{", pattern, iterator));
+        let scoped = self.locals.len();
+        self.locals.extend(each.variables.iter().cloned());
+        self.children(item, siblings)?;
+        self.locals.truncate(scoped);
+        self.line("}");
+        Ok(())
+    }
+
+    fn item(&mut self, index: usize, item: &ArenaItem, siblings: &str) -> Result<()> {
+        if !self.formatter.renders(item) {
+            return Ok(());
+        }
+        self.line(&format!(
+            "{}.separate({}, {})?;",
+            siblings,
+            is_tight(item),
+            OUT
+        ));
+        if let Some(html) = self.formatter.static_html(index) {
+            let html = html.to_string();
+            self.write_str(&html);
+            return Ok(());
+        }
+        let escape_html = self.formatter.options().escape_html;
+        match &item.value {
+            Haml::Element(el) => self.element(el, item),
+            Haml::Text(text) => self.text(item, text, escape_html),
+            Haml::Filter(filter) => self.filter(item, filter),
+            Haml::Script(script) => {
+                let escape = script.escape.unwrap_or(escape_html);
+                self.value(item, &script.code, escape)
+            }
+            Haml::Comment(_) => {
+                self.write_str("<!--\n");
+                self.nested(item, false)?;
+                self.line("}");
+                self.write_str("\n-->");
+                Ok(())
+            }
+            Haml::ConditionalComment(_, value) => {
+                self.write_str(&format!("<!--[{}]>\n", value));
+                self.nested(item, false)?;
+                self.line("}");
+                self.write_str("\n<![endif]-->");
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Opens a block writing the children of `item` and returns the name of
+    /// their siblings. The caller closes the block.
+    fn nested(&mut self, item: &ArenaItem, leading_newline: bool) -> Result<String> {
+        self.line("{");
+        let siblings = self.children_block(item, leading_newline)?;
+        Ok(siblings)
+    }
+
+    fn element(&mut self, el: &Element, item: &ArenaItem) -> Result<()> {
+        let name = el.name().unwrap_or_default();
+        self.write_str(&format!("<{}", name));
+        let options = self.formatter.options().clone();
+        let context = Context::new();
+        let scope = Scope::new(&context);
+        for key in el.attributes() {
+            let values = match el.attributes.get(key) {
+                Some(values) => values,
+                None => continue,
+            };
+            if values.iter().all(AttributeValue::is_static) {
+                let mut html = String::new();
+                let value = formatter::attribute_value(key, values, &scope);
+                // writing to a String never fails
+                let _ = self.formatter.attribute_to_html(key, &value, &mut html);
+                self.write_str(&html);
+                continue;
+            }
+            let value = match values.as_slice() {
+                [value] => self.attribute_value(item, value, &scope)?,
+                values => {
+                    let mut items = vec![];
+                    for value in values {
+                        items.push(self.attribute_value(item, value, &scope)?);
+                    }
+                    format!(
+                        "::haml::runtime::merge_values({:?}, ::std::vec![{}])",
+                        key,
+                        items.join(", ")
+                    )
+                }
+            };
+            let mut boolean = String::new();
+            let _ = self.formatter.boolean_attribute_to_html(key, &mut boolean);
+            self.line(&format!(
+                "::haml::runtime::write_attribute({}, {:?}, &{}, {:?}, {:?}, {})?;",
+                OUT, key, value, boolean, options.attr_wrapper, options.escape_attrs
+            ));
+        }
+        if (el.self_close || self.formatter.is_self_closing(&name))
+            && el.inline_text.is_none()
+            && el.inline_script.is_none()
+            && item.children.is_empty()
+        {
+            let end = self.formatter.self_closing_end().to_string();
+            self.write_str(&end);
+            return Ok(());
+        }
+        self.write_str(">");
+        if let Some(text) = &el.inline_text {
+            self.text(item, text, options.escape_html)?;
+        } else if let Some(script) = &el.inline_script {
+            let escape = script.escape.unwrap_or(options.escape_html);
+            self.value(item, &script.code, escape)?;
+        } else if !item.children.is_empty() {
+            let preserve = el.whitespace_removal_inside || el.preserve;
+            let siblings = self.nested(item, !preserve)?;
+            if !preserve {
+                self.line(&format!(
+                    "if {}.trailing_newline() {{ ::std::fmt::Write::write_char({}, '\\n')?; }}",
+                    siblings, OUT
+                ));
+            }
+            self.line("}");
+        }
+        self.write_str(&format!("</{}>", name));
+        Ok(())
+    }
+
+    fn text(&mut self, item: &ArenaItem, text: &Interpolated, escape: bool) -> Result<()> {
+        for segment in text.segments() {
+            match segment {
+                Segment::Text(text) => self.write_str(text),
+                Segment::Code(code) => {
+                    let value = self.expression(item, code)?;
+                    self.write_value(&value, escape);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, item: &ArenaItem, code: &Code, escape: bool) -> Result<()> {
+        let value = self.expression(item, code)?;
+        self.write_value(&value, escape);
+        Ok(())
+    }
+
+    fn write_value(&mut self, value: &str, escape: bool) {
+        let function = match self.variables {
+            Variables::Context() => "write_json",
+            _ => "write_value",
+        };
+        self.line(&format!(
+            "::haml::runtime::{}({}, &{}, {})?;",
+            function, OUT, value, escape
+        ));
+    }
+
+    /// Writes a filter whose body has interpolation. Only the standard
+    /// filters are available when rendering, and only the format and the
+    /// attribute wrapper change what they write.
+    fn filter(&mut self, item: &ArenaItem, filter: &Filter) -> Result<()> {
+        let options = self.formatter.options().clone();
+        if !options.filters.is_standard(&filter.name) {
+            let what = format!("the filter \"{}\" with interpolation", filter.name);
+            return Err(self.unsupported(item, &what));
+        }
+        let body = self.interpolated(item, &filter.body, options.escape_html)?;
+        let format = match options.effective_format() {
+            Format::Html4() => "Html4",
+            Format::Html5() => "Html5",
+            Format::XHtml() => "XHtml",
+            Format::Xml() => "Xml",
+        };
+        self.line(&format!(
+            "::haml::runtime::write_filter({}, {:?}, &{}, ::haml::Format::{}(), {:?})?;",
+            OUT, filter.name, body, format, options.attr_wrapper
+        ));
+        Ok(())
+    }
+
+    /// The Rust code of a string with `#{}` interpolation, whose values are
+    /// escaped when `escape` is set.
+    fn interpolated(
+        &mut self,
+        item: &ArenaItem,
+        text: &Interpolated,
+        escape: bool,
+    ) -> Result<String> {
+        let mut format = String::new();
+        let mut arguments = String::new();
+        for segment in text.segments() {
+            format.push_str("{}");
+            let argument = match segment {
+                Segment::Text(text) => format!("{:?}", text),
+                Segment::Code(code) => {
+                    let mut value = self.expression(item, code)?;
+                    if self.variables == Variables::Context() {
+                        value = format!("::haml::runtime::to_text(&{})", value);
+                    }
+                    match escape {
+                        true => format!("::haml::runtime::escaped(&{})", value),
+                        false => value,
+                    }
+                }
+            };
+            let _ = write!(arguments, ", {}", argument);
+        }
+        Ok(format!("::std::format!({:?}{})", format, arguments))
+    }
+
+    /// The Rust code of a `Value` holding an attribute value. Code is
+    /// evaluated against the context with `Variables::Context` and
+    /// otherwise written with `Display`.
+    fn attribute_value(
+        &mut self,
+        item: &ArenaItem,
+        value: &AttributeValue,
+        scope: &Scope,
+    ) -> Result<String> {
+        if value.is_static() {
+            return Ok(value_code(&value.evaluate(scope)));
+        }
+        Ok(match value {
+            AttributeValue::Interpolated(text) => {
+                format!(
+                    "{}::String({})",
+                    VALUE,
+                    self.interpolated(item, text, false)?
+                )
+            }
+            AttributeValue::List(items) => {
+                let mut codes = vec![];
+                for item_value in items {
+                    codes.push(self.attribute_value(item, item_value, scope)?);
+                }
+                format!("{}::Array(::std::vec![{}])", VALUE, codes.join(", "))
+            }
+            AttributeValue::Hash(entries) => {
+                let mut codes = vec![];
+                for (key, entry) in entries {
+                    let entry = self.attribute_value(item, entry, scope)?;
+                    codes.push(format!("({:?}.to_string(), {})", key, entry));
+                }
+                format!(
+                    "{}::Object(::std::vec![{}].into_iter().collect())",
+                    VALUE,
+                    codes.join(", ")
+                )
+            }
+            AttributeValue::Expression(code) => {
+                let value = self.expression(item, code)?;
+                match self.variables {
+                    Variables::Context() => format!("{}.into_owned()", value),
+                    _ => format!(
+                        "{}::String(::std::string::ToString::to_string(&{}))",
+                        VALUE, value
+                    ),
+                }
+            }
+            // the other values are static
+            value => value_code(&value.evaluate(scope)),
+        })
+    }
+
+    /// The Rust code of the condition of an `if`, `elsif` or `unless`.
+    fn condition(&mut self, item: &ArenaItem, code: &Code) -> Result<String> {
+        let condition = self.expression(item, code)?;
+        Ok(match self.variables {
+            Variables::Context() => format!("::haml::runtime::truthy(&{})", condition),
+            _ => condition,
+        })
+    }
+
+    /// The Rust code of the template code `code` belonging to `item`. With
+    /// `Variables::Context`, the code is declared as a static so that it is
+    /// parsed only once.
+    fn expression(&mut self, item: &ArenaItem, code: &Code) -> Result<String> {
+        if self.variables != Variables::Context() {
+            return self
+                .rust(code.expr())
+                .map_err(|what| self.unsupported(item, &what));
+        }
+        let name = format!("__HAML_CODE_{}", self.codes);
+        self.codes += 1;
+        self.line(&format!(
+            "static {}: ::haml::runtime::Code = ::haml::runtime::Code::new({:?});",
+            name,
+            code.source()
+        ));
+        Ok(format!("{}.evaluate({})", name, SCOPE))
+    }
+
+    /// Translates an expression to Rust, or returns what in it has no Rust
+    /// equivalent.
+    fn rust(&self, code: &Expr) -> std::result::Result<String, String> {
+        let identifier = |name: &str| match name.ends_with(['?', '!']) {
+            true => Err(format!("'{}'", name)),
+            false => Ok(name.to_string()),
+        };
+        Ok(match code {
+            Expr::Literal(Value::String(value)) => format!("{:?}", value),
+            Expr::Literal(Value::Number(number)) => number.to_string(),
+            Expr::Literal(Value::Bool(value)) => value.to_string(),
+            Expr::Literal(_) => return Err("nil".to_string()),
+            Expr::Variable(name) if self.locals.contains(name) => name.to_string(),
+            Expr::Variable(name) => match self.variables {
+                Variables::Fields() => format!("self.{}", identifier(name)?),
+                _ => identifier(name)?,
+            },
+            Expr::Call(receiver, name) => {
+                let receiver = self.rust(receiver)?;
+                match name.as_str() {
+                    "size" | "length" | "count" => format!("{}.len()", receiver),
+                    "empty?" => format!("{}.is_empty()", receiver),
+                    "any?" => format!("!{}.is_empty()", receiver),
+                    "nil?" => format!("{}.is_none()", receiver),
+                    "to_s" => format!("{}.to_string()", receiver),
+                    "upcase" => format!("{}.to_uppercase()", receiver),
+                    "downcase" => format!("{}.to_lowercase()", receiver),
+                    "strip" => format!("{}.trim()", receiver),
+                    // these are nil for an empty array, which Rust has no
+                    // value for
+                    "first" | "last" => return Err(format!("'{}'", name)),
+                    name => format!("{}.{}", receiver, identifier(name)?),
+                }
+            }
+            Expr::Index(receiver, index) => {
+                let receiver = self.rust(receiver)?;
+                match &**index {
+                    Expr::Literal(Value::String(name)) => {
+                        format!("{}.{}", receiver, identifier(name)?)
+                    }
+                    Expr::Literal(Value::Number(n)) if n.is_u64() => format!("{}[{}]", receiver, n),
+                    Expr::Literal(Value::Number(n)) => return Err(format!("the index {}", n)),
+                    index => format!("{}[{}]", receiver, self.rust(index)?),
+                }
+            }
+            Expr::Interpolated(text) => {
+                let mut format = String::new();
+                let mut arguments = String::new();
+                for segment in text.segments() {
+                    format.push_str("{}");
+                    let argument = match segment {
+                        Segment::Text(text) => format!("{:?}", text),
+                        Segment::Code(code) => self.rust(code.expr())?,
+                    };
+                    let _ = write!(arguments, ", {}", argument);
+                }
+                format!("::std::format!({:?}{})", format, arguments)
+            }
+            Expr::Not(code) => format!("(!{})", self.rust(code)?),
+            Expr::And(left, right) => format!("({} && {})", self.rust(left)?, self.rust(right)?),
+            Expr::Or(left, right) => format!("({} || {})", self.rust(left)?, self.rust(right)?),
+            Expr::Compare(comparison, left, right) => {
+                let operator = match comparison {
+                    Comparison::Equal() => "==",
+                    Comparison::NotEqual() => "!=",
+                    Comparison::Less() => "<",
+                    Comparison::LessOrEqual() => "<=",
+                    Comparison::Greater() => ">",
+                    Comparison::GreaterOrEqual() => ">=",
+                };
+                format!("({} {} {})", self.rust(left)?, operator, self.rust(right)?)
+            }
+            Expr::Hash(_) => return Err("a hash".to_string()),
+            Expr::Array(_) => return Err("an array".to_string()),
+        })
+    }
+}
+
+/// The Rust code building `value`.
+fn value_code(value: &Value) -> String {
+    match value {
+        Value::Null => format!("{}::Null", VALUE),
+        Value::Bool(value) => format!("{}::Bool({})", VALUE, value),
+        Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(n), _) => format!("{}::from({}i64)", VALUE, n),
+            (None, Some(n)) => format!("{}::from({}u64)", VALUE, n),
+            _ => format!(
+                "{}::from({:?}f64)",
+                VALUE,
+                number.as_f64().unwrap_or_default()
+            ),
+        },
+        Value::String(text) => format!("{}::String({:?}.to_string())", VALUE, text),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(value_code).collect();
+            format!("{}::Array(::std::vec![{}])", VALUE, items.join(", "))
+        }
+        Value::Object(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("({:?}.to_string(), {})", key, value_code(value)))
+                .collect();
+            format!(
+                "{}::Object(::std::vec![{}].into_iter().collect())",
+                VALUE,
+                entries.join(", ")
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filters::Command;
+
+    fn block(haml: &str, variables: Variables) -> Result<String> {
+        rust_block(haml, &HamlOptions::new(), variables)
+    }
+
+    #[test]
+    fn variables() {
+        let code = block("%p= user.name\n%p= items.size", Variables::Fields()).unwrap();
+        assert!(code.contains("&self.user.name, false"));
+        assert!(code.contains("&self.items.len(), false"));
+        let code = block("%p= user[:name]", Variables::Locals()).unwrap();
+        assert!(code.contains("&user.name, false"));
+        let code = block("- items.each do |item|\n  = item", Variables::Fields()).unwrap();
+        assert!(code.contains("for item in (self.items).iter() {"));
+        assert!(code.contains("&item, false"));
+    }
+
+    #[test]
+    fn context() {
+        let code = block("- if user\n  %p= user.name", Variables::Context()).unwrap();
+        assert!(code.contains("let __haml_scope = &::haml::runtime::Scope::new(context);"));
+        assert!(code.contains(
+            r#"static __HAML_CODE_0: ::haml::runtime::Code = ::haml::runtime::Code::new("user");"#
+        ));
+        assert!(
+            code.contains("if ::haml::runtime::truthy(&__HAML_CODE_0.evaluate(__haml_scope)) {")
+        );
+        assert!(
+            code.contains("write_json(__haml_out, &__HAML_CODE_1.evaluate(__haml_scope), false)")
+        );
+        let code = block("- items.each do |a, b|\n  = a", Variables::Context()).unwrap();
+        assert!(code.contains(r#"::haml::runtime::loop_locals(__HAML_CODE_0.evaluate(__haml_scope).into_owned(), &["a", "b"], false)"#));
+        assert!(block("= nil", Variables::Context()).is_ok());
+    }
+
+    #[test]
+    fn static_html() {
+        let code = block("%div\n  %p Hello", Variables::Fields()).unwrap();
+        assert!(code.contains(r#""<div>\n<p>Hello</p>\n</div>""#));
+        assert!(!code.contains("write_value"));
+
+        let code = block(
+            "%input{checked: true, title: nil, max: 2}",
+            Variables::Fields(),
+        )
+        .unwrap();
+        assert!(code.contains(r#""<input checked max='2'>""#));
+        let code = block("%input{checked: on, max: 2}", Variables::Context()).unwrap();
+        assert!(code.contains(r#"write_attribute(__haml_out, "checked", &__HAML_CODE_0.evaluate(__haml_scope).into_owned(), " checked", '\'', true)"#));
+        assert!(code.contains(r#"" max='2'""#));
+    }
+
+    #[test]
+    fn errors() {
+        let err = block("%p\n  %p= 1 +", Variables::Fields()).unwrap_err();
+        assert_eq!(2, err.line());
+        let err = block("%p\n= render 'form'", Variables::Fields()).unwrap_err();
+        assert_eq!(2, err.line());
+        assert_eq!(
+            &ErrorKind::InvalidExpression(
+                "render can't be used in a compiled template".to_string()
+            ),
+            err.kind()
+        );
+        let err = block("- if user.nil?\n  %p\n= nil", Variables::Fields()).unwrap_err();
+        assert_eq!(3, err.line());
+        assert!(block("= empty?", Variables::Fields()).is_err());
+
+        let shout = Command::new("css", "tr").args(&["a-z", "A-Z"]);
+        let options = HamlOptions::new().filter(shout);
+        let err = rust_block(":css\n  a #{b}", &options, Variables::Fields()).unwrap_err();
+        assert_eq!(
+            &ErrorKind::InvalidExpression(
+                "the filter \"css\" with interpolation can't be used in a compiled template"
+                    .to_string()
+            ),
+            err.kind()
+        );
+        assert!(rust_block(":css\n  a", &options, Variables::Fields()).is_ok());
+        let options = HamlOptions::new().mime_type("text/xml");
+        let code = rust_block(":css\n  a #{b}", &options, Variables::Fields()).unwrap();
+        assert!(code.contains("::haml::Format::XHtml()"));
+    }
+}
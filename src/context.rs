@@ -0,0 +1,160 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use crate::error::{Error, ErrorKind, Result};
+use crate::span::Span;
+use crate::template::Template;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// The variables available to the code of a template when it is rendered.
+///
+/// ```
+/// let mut context = haml::Context::new();
+/// context.insert("name", "world");
+/// let document = haml::parse("%p Hello #{name}").unwrap();
+/// assert_eq!("<p>Hello world</p>", document.render(&context));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Context {
+    values: Map<String, Value>,
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context { values: Map::new() }
+    }
+
+    /// Builds a context from anything that serializes to a map, such as a
+    /// struct deriving `Serialize`, a `HashMap` or a `serde_json` object.
+    pub fn from_serialize<T: Serialize + ?Sized>(value: &T) -> Result<Context> {
+        let invalid =
+            |reason: String| Error::new(ErrorKind::InvalidContext(reason), Span::default(), "");
+        match serde_json::to_value(value) {
+            Ok(Value::Object(values)) => Ok(Context { values }),
+            Ok(Value::Null) => Ok(Context::new()),
+            Ok(_) => Err(invalid("the context must serialize to a map".to_string())),
+            Err(err) => Err(invalid(err.to_string())),
+        }
+    }
+
+    /// Sets the variable `name`, replacing any previous value.
+    pub fn insert<V: Into<Value>>(&mut self, name: &str, value: V) {
+        self.values.insert(name.to_string(), value.into());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+}
+
+impl From<Map<String, Value>> for Context {
+    fn from(values: Map<String, Value>) -> Context {
+        Context { values }
+    }
+}
+
+/// The variables visible at one point of a template: the block variables of
+/// the loops around it, innermost first, and then those of the context.
+/// While a layout is rendered, it also holds the page that `yield` renders.
+pub struct Scope<'a> {
+    context: &'a Context,
+    locals: Vec<(String, Value)>,
+    parent: Option<&'a Scope<'a>>,
+    page: Option<&'a Template>,
+    // where the first failure of a render that can fail is kept
+    failure: Option<&'a RefCell<Option<Error>>>,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(context: &'a Context) -> Scope<'a> {
+        Scope {
+            context,
+            locals: vec![],
+            parent: None,
+            page: None,
+            failure: None,
+        }
+    }
+
+    /// A scope for rendering a layout around `page`.
+    pub fn with_page(context: &'a Context, page: &'a Template) -> Scope<'a> {
+        Scope {
+            page: Some(page),
+            ..Scope::new(context)
+        }
+    }
+
+    /// A scope whose render stops at the first failure, which is kept in
+    /// `failure`.
+    pub(crate) fn failing_into(self, failure: &'a RefCell<Option<Error>>) -> Scope<'a> {
+        Scope {
+            failure: Some(failure),
+            ..self
+        }
+    }
+
+    /// Reports a failure of the part being rendered. A render that can fail
+    /// stops there, and any other goes on without the part.
+    pub(crate) fn fail(&self, error: Error) -> fmt::Result {
+        match self.failure {
+            Some(failure) => {
+                failure.borrow_mut().get_or_insert(error);
+                Err(fmt::Error)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Records that the failure stopping the render, if any, happened in
+    /// the template called `name`.
+    pub(crate) fn failed_in(&self, name: &str) {
+        if let Some(failure) = self.failure {
+            let mut failure = failure.borrow_mut();
+            *failure = failure.take().map(|error| error.in_template(name));
+        }
+    }
+
+    pub fn context(&self) -> &'a Context {
+        self.context
+    }
+
+    /// The page rendered in the layout, if a layout is being rendered.
+    pub fn page(&self) -> Option<&'a Template> {
+        self.page
+    }
+
+    /// A scope nested in this one with the block variables of one iteration
+    /// of a loop.
+    pub fn child(&'a self, locals: Vec<(String, Value)>) -> Scope<'a> {
+        Scope {
+            context: self.context,
+            locals,
+            parent: Some(self),
+            page: self.page,
+            failure: self.failure,
+        }
+    }
+
+    /// A scope for a partial, with the context of this scope and the locals
+    /// given to the partial but none of the block variables around it.
+    pub fn partial(&self, locals: Vec<(String, Value)>) -> Scope<'a> {
+        Scope {
+            context: self.context,
+            locals,
+            parent: None,
+            page: self.page,
+            failure: self.failure,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self.locals.iter().find(|(local, _)| local == name) {
+            Some((_, value)) => Some(value),
+            None => match self.parent {
+                Some(parent) => parent.get(name),
+                None => self.context.get(name),
+            },
+        }
+    }
+}
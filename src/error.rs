@@ -0,0 +1,158 @@
+use crate::span::Span;
+use std::error;
+use std::fmt;
+
+/// The different ways a Haml template can fail to compile.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A token that is not allowed at this point, e.g. `%` without a tag name.
+    UnexpectedToken(String),
+    /// Indentation that is inconsistent with the lines before it.
+    BadIndentation,
+    /// A `(` or `{` attribute list that is never closed.
+    UnterminatedAttributes,
+    /// Nested content under a line that cannot have any.
+    IllegalNesting(String),
+    /// Code that is not one of the supported expressions.
+    InvalidExpression(String),
+    /// A render context that does not serialize to a map.
+    InvalidContext(String),
+    /// An `elsif` or `else` line that does not follow the branch it belongs
+    /// to.
+    NoPrecedingIf(String),
+    /// A template name that does not match any template.
+    TemplateNotFound(String),
+    /// A template file that could not be read.
+    Io(String),
+    /// A template file name that can't be made into a Rust function name,
+    /// or that makes the same name as another template's.
+    InvalidTemplateName(String),
+    /// A partial that renders itself, along with the partials rendered on
+    /// the way back to it. Partials are compiled along with the template
+    /// rendering them, so this is an error even when the partial would only
+    /// render itself under some condition.
+    PartialCycle(Vec<String>),
+    /// A `:name` line for a filter that is not registered.
+    UnknownFilter(String),
+    /// A filter that could not render its body, along with why.
+    FilterFailed(String),
+    /// A filter that runs a command while commands are not allowed.
+    FilterDisabled(String),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnexpectedToken(token) => write!(f, "Unexpected token '{}'", token),
+            ErrorKind::BadIndentation => write!(f, "Inconsistent indentation"),
+            ErrorKind::UnterminatedAttributes => write!(f, "Unterminated attribute list"),
+            ErrorKind::IllegalNesting(reason) => write!(f, "Illegal nesting: {}", reason),
+            ErrorKind::InvalidExpression(reason) => write!(f, "Invalid expression: {}", reason),
+            ErrorKind::InvalidContext(reason) => write!(f, "Invalid context: {}", reason),
+            ErrorKind::NoPrecedingIf(keyword) => {
+                write!(f, "Got \"{}\" with no preceding \"if\"", keyword)
+            }
+            ErrorKind::TemplateNotFound(name) => write!(f, "Template '{}' not found", name),
+            ErrorKind::Io(reason) => write!(f, "Could not read template: {}", reason),
+            ErrorKind::InvalidTemplateName(reason) => {
+                write!(f, "Invalid template name: {}", reason)
+            }
+            ErrorKind::PartialCycle(chain) => {
+                write!(f, "Partial renders itself: {}", chain.join(" -> "))
+            }
+            ErrorKind::UnknownFilter(name) => write!(f, "Filter \"{}\" is not defined", name),
+            ErrorKind::FilterFailed(reason) => write!(f, "Filter failed: {}", reason),
+            ErrorKind::FilterDisabled(name) => {
+                write!(
+                    f,
+                    "Filter \"{}\" runs a command, which is not allowed",
+                    name
+                )
+            }
+        }
+    }
+}
+
+/// An error in a Haml template along with where it happened.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    span: Span,
+    snippet: String,
+    template: Option<String>,
+}
+
+impl Error {
+    pub(crate) fn new(kind: ErrorKind, span: Span, snippet: &str) -> Error {
+        Error {
+            kind,
+            span,
+            snippet: snippet.to_string(),
+            template: None,
+        }
+    }
+
+    /// Records the name of the template the error was found in.
+    pub(crate) fn in_template(mut self, name: &str) -> Error {
+        self.template.get_or_insert_with(|| name.to_string());
+        self
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// The 1-based line of the template the error was found on, or 0 when
+    /// the error is not about the template itself.
+    pub fn line(&self) -> usize {
+        self.span.line
+    }
+
+    /// The 1-based column, counted in characters, of the error.
+    pub fn column(&self) -> usize {
+        self.span.column
+    }
+
+    /// Where in the template the error was found.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The full template line the error was found on.
+    pub fn snippet(&self) -> &str {
+        &self.snippet
+    }
+
+    /// The name of the template the error was found in, when it was loaded
+    /// by name from a [`TemplateRegistry`](crate::TemplateRegistry).
+    pub fn template(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(template) = &self.template {
+            write!(f, "In template '{}': ", template)?;
+        }
+        // errors that are not about the template have no position
+        if self.line() == 0 {
+            return write!(f, "{}", self.kind);
+        }
+        write!(
+            f,
+            "{} at line {}, column {}\n{}\n{:>width$}",
+            self.kind,
+            self.line(),
+            self.column(),
+            self.snippet,
+            "^",
+            width = self.column()
+        )
+    }
+}
+
+impl error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,1056 @@
+use crate::context::Scope;
+use crate::formatter::write_escaped;
+use crate::parser::{Code, Interpolated};
+use serde_json::{Number, Value};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt::{self, Write};
+
+/// The Ruby code of a script line or an interpolation. Only the subset
+/// needed to read values out of the render context is understood: literals,
+/// variables, `.key` lookups, `[index]` lookups and a few common methods.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    Literal(Value),
+    /// A variable of the context or a block variable. A leading `@` is ignored so that templates
+    /// written for Rails keep working.
+    Variable(String),
+    /// `receiver.name`, either a key of a hash or one of a few methods.
+    Call(Box<Expr>, String),
+    /// `receiver[index]` on an array or a hash.
+    Index(Box<Expr>, Box<Expr>),
+    /// A double quoted string containing `#{}`.
+    Interpolated(Interpolated),
+    /// `!a` or `not a`.
+    Not(Box<Expr>),
+    /// `a && b` or `a and b`, which like Ruby is `a` when it is false and
+    /// `b` otherwise.
+    And(Box<Expr>, Box<Expr>),
+    /// `a || b` or `a or b`, which like Ruby is `a` when it is true and `b`
+    /// otherwise.
+    Or(Box<Expr>, Box<Expr>),
+    /// `a == b`, `a != b`, `a < b`, `a <= b`, `a > b` or `a >= b`.
+    Compare(Comparison, Box<Expr>, Box<Expr>),
+    /// `{ key: value }` or `{ :key => value }`. Keys are always strings.
+    Hash(Vec<(String, Expr)>),
+    /// `[a, b]`.
+    Array(Vec<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Comparison {
+    Equal(),
+    NotEqual(),
+    Less(),
+    LessOrEqual(),
+    Greater(),
+    GreaterOrEqual(),
+}
+
+impl Comparison {
+    /// The operators longest first, so that `<=` is not read as `<`.
+    const OPERATORS: &'static [(&'static str, Comparison)] = &[
+        ("==", Comparison::Equal()),
+        ("!=", Comparison::NotEqual()),
+        ("<=", Comparison::LessOrEqual()),
+        (">=", Comparison::GreaterOrEqual()),
+        ("<", Comparison::Less()),
+        (">", Comparison::Greater()),
+    ];
+
+    fn apply(self, left: &Value, right: &Value) -> bool {
+        let ordering = match (left, right) {
+            (Value::Number(a), Value::Number(b)) => a
+                .as_f64()
+                .zip(b.as_f64())
+                .and_then(|(a, b)| a.partial_cmp(&b)),
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            _ => None,
+        };
+        match (self, ordering) {
+            (Comparison::Equal(), Some(ordering)) => ordering.is_eq(),
+            (Comparison::Equal(), None) => left == right,
+            (Comparison::NotEqual(), Some(ordering)) => ordering.is_ne(),
+            (Comparison::NotEqual(), None) => left != right,
+            (Comparison::Less(), Some(ordering)) => ordering.is_lt(),
+            (Comparison::LessOrEqual(), Some(ordering)) => ordering.is_le(),
+            (Comparison::Greater(), Some(ordering)) => ordering.is_gt(),
+            (Comparison::GreaterOrEqual(), Some(ordering)) => ordering.is_ge(),
+            (_, None) => false,
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+/// One argument of a method call, either `value` or `key: value`.
+struct Argument<'a> {
+    key: Option<String>,
+    /// The whole argument, including its key.
+    text: &'a str,
+    /// The code of the value.
+    code: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, ch: char) -> bool {
+        self.skip_whitespace();
+        match self.peek() == Some(ch) {
+            true => {
+                self.position += ch.len_utf8();
+                true
+            }
+            false => false,
+        }
+    }
+
+    /// Consumes `word` when it is the next word, e.g. `and` but not `android`.
+    fn eat_keyword(&mut self, word: &str) -> bool {
+        self.skip_whitespace();
+        let boundary = match self.rest().strip_prefix(word) {
+            Some(after) => !after.starts_with(|c: char| c.is_alphanumeric() || c == '_'),
+            None => false,
+        };
+        if boundary {
+            self.position += word.len();
+        }
+        boundary
+    }
+
+    fn eat_operator(&mut self, operator: &str) -> bool {
+        self.skip_whitespace();
+        match self.rest().starts_with(operator) {
+            true => {
+                self.position += operator.len();
+                true
+            }
+            false => false,
+        }
+    }
+
+    fn expect(&mut self, ch: char) -> Result<(), String> {
+        match self.eat(ch) {
+            true => Ok(()),
+            false => Err(format!("expected '{}'", ch)),
+        }
+    }
+
+    /// An identifier, optionally ending in `?` or `!` like Ruby methods.
+    fn identifier(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let mut end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if end == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        if rest[end..].starts_with('?')
+            || (rest[end..].starts_with('!') && !rest[end..].starts_with("!="))
+        {
+            end += 1;
+        }
+        self.position += end;
+        Some(&rest[..end])
+    }
+
+    /// A quoted string. Double-quoted strings unescape `\\n`, `\\t` and any
+    /// other escaped character, and have `#{}` interpolation unless the `#`
+    /// is escaped. Single-quoted strings only unescape the quote and `\\`.
+    fn string(&mut self, quote: char) -> Result<Expr, String> {
+        self.quoted(quote, quote == '"')
+    }
+
+    /// A quoted string with the escapes of its quote, with `#{}`
+    /// interpolation when `interpolate` is set.
+    fn quoted(&mut self, quote: char, interpolate: bool) -> Result<Expr, String> {
+        self.position += 1;
+        let text = self.rest();
+        let mut segments = vec![];
+        let mut value = String::new();
+        let mut offset = 0;
+        while let Some(ch) = text[offset..].chars().next() {
+            offset += ch.len_utf8();
+            match ch {
+                c if c == quote => {
+                    self.position += offset;
+                    if segments.is_empty() {
+                        return Ok(Expr::Literal(Value::String(value)));
+                    }
+                    if !value.is_empty() {
+                        segments.push(Segment::Text(value));
+                    }
+                    let text = &text[..offset - 1];
+                    return Ok(Expr::Interpolated(Interpolated::from_segments(
+                        text, segments,
+                    )));
+                }
+                '#' if interpolate && text[offset..].starts_with('{') => {
+                    let code = &text[offset + 1..];
+                    let end = interpolation_end(code).ok_or("unterminated interpolation")?;
+                    let code = &code[..end];
+                    let parsed = Code::parse(code)
+                        .map_err(|reason| format!("{} in '#{{{}}}'", reason, code))?;
+                    if !value.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut value)));
+                    }
+                    segments.push(Segment::Code(parsed));
+                    offset += end + 2;
+                }
+                '\\' => {
+                    let escaped = match text[offset..].chars().next() {
+                        Some(escaped) => escaped,
+                        None => break,
+                    };
+                    offset += escaped.len_utf8();
+                    match (quote, escaped) {
+                        ('"', 'n') => value.push('\n'),
+                        ('"', 't') => value.push('\t'),
+                        ('"', c) => value.push(c),
+                        (_, c) if c == quote || c == '\\' => value.push(c),
+                        (_, c) => {
+                            value.push('\\');
+                            value.push(c);
+                        }
+                    }
+                }
+                c => value.push(c),
+            }
+        }
+        Err("unterminated string".to_string())
+    }
+
+    /// The key of a hash entry, `key:`, `:key =>`, `"key" =>` or `"key":`,
+    /// or None without consuming anything when there is none.
+    fn key(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let start = self.position;
+        let key = match self.peek() {
+            Some(quote @ '\'') | Some(quote @ '"') => match self.string(quote) {
+                Ok(Expr::Literal(Value::String(key)))
+                    if self.eat_operator("=>") || self.eat(':') =>
+                {
+                    Some(key)
+                }
+                _ => None,
+            },
+            Some(':') => {
+                self.position += 1;
+                let key = match self.peek() {
+                    // a quoted symbol such as :"data-id"
+                    Some(quote @ '\'') | Some(quote @ '"') => match self.string(quote) {
+                        Ok(Expr::Literal(Value::String(key))) => Some(key),
+                        _ => None,
+                    },
+                    _ => self.identifier().map(str::to_string),
+                };
+                key.filter(|_| self.eat_operator("=>"))
+            }
+            _ => match self.identifier() {
+                Some(key) if self.rest().starts_with(':') && !self.rest().starts_with("::") => {
+                    self.position += 1;
+                    Some(key.to_string())
+                }
+                _ => None,
+            },
+        };
+        if key.is_none() {
+            self.position = start;
+        }
+        key
+    }
+
+    fn hash(&mut self) -> Result<Expr, String> {
+        self.position += 1;
+        let mut entries = vec![];
+        while !self.eat('}') {
+            match self.key() {
+                Some(key) => entries.push((key, self.expression()?)),
+                None => return Err("expected a hash key".to_string()),
+            }
+            if !self.eat(',') {
+                self.expect('}')?;
+                break;
+            }
+        }
+        Ok(Expr::Hash(entries))
+    }
+
+    fn array(&mut self) -> Result<Expr, String> {
+        self.position += 1;
+        let mut items = vec![];
+        while !self.eat(']') {
+            items.push(self.expression()?);
+            if !self.eat(',') {
+                self.expect(']')?;
+                break;
+            }
+        }
+        Ok(Expr::Array(items))
+    }
+
+    /// The arguments of a method call without parentheses, up to the end of
+    /// the code or a closing parenthesis.
+    fn arguments(&mut self) -> Result<Vec<Argument<'a>>, String> {
+        let mut arguments = vec![];
+        self.skip_whitespace();
+        while !self.rest().is_empty() && !self.rest().starts_with(')') {
+            let start = self.position;
+            let key = self.key();
+            self.skip_whitespace();
+            let code_start = self.position;
+            self.expression()?;
+            arguments.push(Argument {
+                key,
+                text: &self.source[start..self.position],
+                code: &self.source[code_start..self.position],
+            });
+            if !self.eat(',') {
+                break;
+            }
+            self.skip_whitespace();
+        }
+        Ok(arguments)
+    }
+
+    fn number(&mut self) -> Result<Expr, String> {
+        let rest = self.rest();
+        let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let sign = usize::from(rest.starts_with('-'));
+        let mut end = sign + digits(&rest[sign..]);
+        let float = rest[end..].starts_with('.')
+            && rest[end + 1..].starts_with(|c: char| c.is_ascii_digit());
+        if float {
+            end += 1 + digits(&rest[end + 1..]);
+        }
+        self.position += end;
+        let text = &rest[..end];
+        let number = match float {
+            true => text.parse::<f64>().ok().and_then(Number::from_f64),
+            false => text.parse::<i64>().ok().map(Number::from),
+        };
+        number
+            .map(|n| Expr::Literal(Value::Number(n)))
+            .ok_or_else(|| format!("invalid number '{}'", text))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(quote @ '\'') | Some(quote @ '"') => self.string(quote),
+            Some(c) if c.is_ascii_digit() => self.number(),
+            Some('-') if self.rest()[1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                self.number()
+            }
+            Some(':') => {
+                self.position += 1;
+                match self.identifier() {
+                    Some(name) => Ok(Expr::Literal(Value::String(name.to_string()))),
+                    None => Err("expected a symbol name".to_string()),
+                }
+            }
+            Some('{') => self.hash(),
+            Some('[') => self.array(),
+            Some('(') => {
+                self.position += 1;
+                let expr = self.expression()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some('@') => {
+                self.position += 1;
+                match self.identifier() {
+                    Some(name) => Ok(Expr::Variable(name.to_string())),
+                    None => Err("expected a variable name".to_string()),
+                }
+            }
+            Some(_) => match self.identifier() {
+                Some("true") => Ok(Expr::Literal(Value::Bool(true))),
+                Some("false") => Ok(Expr::Literal(Value::Bool(false))),
+                Some("nil") => Ok(Expr::Literal(Value::Null)),
+                Some(name) => Ok(Expr::Variable(name.to_string())),
+                None => Err(format!("unexpected '{}'", self.rest())),
+            },
+            None => Err("expected an expression".to_string()),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat('.') {
+                match self.identifier() {
+                    Some(name) => expr = Expr::Call(Box::new(expr), name.to_string()),
+                    None => return Err("expected a method name".to_string()),
+                }
+            } else if self.eat('[') {
+                let index = self.expression()?;
+                self.expect(']')?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.postfix()?;
+        for (operator, comparison) in Comparison::OPERATORS {
+            if self.eat_operator(operator) {
+                let right = self.postfix()?;
+                return Ok(Expr::Compare(*comparison, Box::new(left), Box::new(right)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        let negated = match self.rest().starts_with('!') && !self.rest().starts_with("!=") {
+            true => self.eat_operator("!"),
+            false => self.eat_keyword("not"),
+        };
+        match negated {
+            true => Ok(Expr::Not(Box::new(self.not()?))),
+            false => self.comparison(),
+        }
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.eat_operator("&&") || self.eat_keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat_operator("||") || self.eat_keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+}
+
+/// Parses the code of a script line, returning why it could not be parsed
+/// on failure.
+pub(crate) fn parse(code: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        source: code,
+        position: 0,
+    };
+    let expr = parser.expression()?;
+    parser.skip_whitespace();
+    match parser.rest() {
+        "" => Ok(expr),
+        rest => Err(format!("unexpected '{}'", rest)),
+    }
+}
+
+/// The quoted value of an HTML style attribute such as `'a'` or `"#{b}"`,
+/// with the escapes of its quote and `#{}` interpolation in either quotes.
+pub(crate) fn parse_attribute_value(code: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        source: code,
+        position: 0,
+    };
+    let expr = match parser.peek() {
+        Some(quote @ '\'') | Some(quote @ '"') => parser.quoted(quote, true)?,
+        _ => return Err(format!("expected a quoted value at '{}'", code)),
+    };
+    match parser.rest() {
+        "" => Ok(expr),
+        rest => Err(format!("unexpected '{}'", rest)),
+    }
+}
+
+/// The entries of an attribute hash such as `{class: "a", :id => b}`, in
+/// order: the key, the code of the value and the value parsed.
+pub(crate) fn parse_attribute_hash(code: &str) -> Result<Vec<(String, &str, Expr)>, String> {
+    let mut parser = Parser {
+        source: code,
+        position: 0,
+    };
+    parser.expect('{')?;
+    let mut entries = vec![];
+    while !parser.eat('}') {
+        let key = match parser.key() {
+            Some(key) => key,
+            None => return Err(format!("expected a hash key at '{}'", parser.rest().trim())),
+        };
+        parser.skip_whitespace();
+        let start = parser.position;
+        let value = parser.expression()?;
+        entries.push((key, &code[start..parser.position], value));
+        if !parser.eat(',') {
+            parser.expect('}')?;
+            break;
+        }
+    }
+    parser.skip_whitespace();
+    match parser.rest() {
+        "" => Ok(entries),
+        rest => Err(format!("unexpected '{}'", rest)),
+    }
+}
+
+/// The items of an array such as `['a', b]` in an attribute hash, in order:
+/// the code of the item and the item parsed.
+pub(crate) fn parse_attribute_array(code: &str) -> Result<Vec<(&str, Expr)>, String> {
+    let mut parser = Parser {
+        source: code,
+        position: 0,
+    };
+    parser.expect('[')?;
+    let mut items = vec![];
+    while !parser.eat(']') {
+        parser.skip_whitespace();
+        let start = parser.position;
+        let item = parser.expression()?;
+        items.push((&code[start..parser.position], item));
+        if !parser.eat(',') {
+            parser.expect(']')?;
+            break;
+        }
+    }
+    parser.skip_whitespace();
+    match parser.rest() {
+        "" => Ok(items),
+        rest => Err(format!("unexpected '{}'", rest)),
+    }
+}
+
+/// Whether `code` calls `method`, as in `render "shared/header"`.
+pub(crate) fn is_call(code: &str, method: &str) -> bool {
+    code.strip_prefix(method).is_some_and(|rest| {
+        rest.is_empty() || rest.starts_with(|c: char| c.is_whitespace() || c == '(')
+    })
+}
+
+/// The name given to a call taking an optional name, such as `yield` or
+/// `yield :sidebar`. `code` starts after the method name.
+pub(crate) fn parse_name_argument(code: &str) -> Result<Option<String>, String> {
+    let mut parser = Parser {
+        source: code,
+        position: 0,
+    };
+    let parenthesized = parser.eat('(');
+    let arguments = parser.arguments()?;
+    if parenthesized {
+        parser.expect(')')?;
+    }
+    parser.skip_whitespace();
+    if !parser.rest().is_empty() {
+        return Err(format!("unexpected '{}'", parser.rest()));
+    }
+    match arguments.as_slice() {
+        [] => Ok(None),
+        [argument] if argument.key.is_none() => match parse(argument.code) {
+            Ok(Expr::Literal(Value::String(name))) => Ok(Some(name)),
+            _ => Err(format!("expected a name, not '{}'", argument.code)),
+        },
+        _ => Err(format!("too many arguments in '{}'", code.trim())),
+    }
+}
+
+/// The partial name and the code of the locals of `render "name"`,
+/// `render "name", key: value`, `render "name", { key: value }` or
+/// `render partial: "name", locals: { key: value }`.
+pub(crate) fn parse_render(code: &str) -> Result<(String, Option<String>), String> {
+    let mut parser = Parser {
+        source: code,
+        position: "render".len(),
+    };
+    let parenthesized = parser.eat('(');
+    let arguments = parser.arguments()?;
+    if parenthesized {
+        parser.expect(')')?;
+    }
+    parser.skip_whitespace();
+    if !parser.rest().is_empty() {
+        return Err(format!("unexpected '{}'", parser.rest()));
+    }
+    let name = |code: &str| match parse(code) {
+        Ok(Expr::Literal(Value::String(name))) => Ok(name),
+        _ => Err(format!(
+            "the name of a partial must be a string, not '{}'",
+            code
+        )),
+    };
+    let (name, locals) = match arguments.split_first() {
+        Some((first, rest)) if first.key.is_none() => {
+            let locals = match rest {
+                [] => None,
+                [locals] if locals.key.is_none() => Some(locals.code.to_string()),
+                pairs if pairs.iter().all(|pair| pair.key.is_some()) => {
+                    let pairs: Vec<&str> = pairs.iter().map(|pair| pair.text).collect();
+                    Some(format!("{{ {} }}", pairs.join(", ")))
+                }
+                _ => return Err(format!("too many arguments in '{}'", code)),
+            };
+            (name(first.code)?, locals)
+        }
+        _ => {
+            let (mut partial, mut locals) = (None, None);
+            for argument in &arguments {
+                match argument.key.as_deref() {
+                    Some("partial") => partial = Some(name(argument.code)?),
+                    Some("locals") => locals = Some(argument.code.to_string()),
+                    _ => return Err(format!("unexpected argument '{}'", argument.text)),
+                }
+            }
+            match partial {
+                Some(partial) => (partial, locals),
+                None => return Err("expected the name of a partial".to_string()),
+            }
+        }
+    };
+    Ok((name, locals))
+}
+
+/// Applies a lookup to a value that is either borrowed from the context or
+/// was computed during evaluation.
+fn project<'a>(value: Cow<'a, Value>, lookup: impl Fn(&Value) -> Option<&Value>) -> Cow<'a, Value> {
+    match value {
+        Cow::Borrowed(value) => lookup(value).map_or(Cow::Owned(Value::Null), Cow::Borrowed),
+        Cow::Owned(value) => Cow::Owned(lookup(&value).cloned().unwrap_or(Value::Null)),
+    }
+}
+
+/// The Ruby methods that may be called on a value when it has no key of
+/// the same name.
+fn method(value: &Value, name: &str) -> Value {
+    match (name, value) {
+        ("size", _) | ("length", _) | ("count", _) => match value {
+            Value::Array(items) => Value::from(items.len()),
+            Value::Object(map) => Value::from(map.len()),
+            Value::String(s) => Value::from(s.chars().count()),
+            _ => Value::Null,
+        },
+        ("first", Value::Array(items)) => items.first().cloned().unwrap_or(Value::Null),
+        ("last", Value::Array(items)) => items.last().cloned().unwrap_or(Value::Null),
+        ("empty?", _) => Value::Bool(match value {
+            Value::Array(items) => items.is_empty(),
+            Value::Object(map) => map.is_empty(),
+            Value::String(s) => s.is_empty(),
+            _ => false,
+        }),
+        ("any?", _) => Value::Bool(match value {
+            Value::Array(items) => items.iter().any(truthy),
+            Value::Object(map) => !map.is_empty(),
+            _ => false,
+        }),
+        ("nil?", _) => Value::Bool(value.is_null()),
+        ("to_s", _) => Value::String(to_text(value).into_owned()),
+        ("upcase", Value::String(s)) => Value::String(s.to_uppercase()),
+        ("downcase", Value::String(s)) => Value::String(s.to_lowercase()),
+        ("strip", Value::String(s)) => Value::String(s.trim().to_string()),
+        _ => Value::Null,
+    }
+}
+
+impl Expr {
+    pub(crate) fn evaluate<'a>(&'a self, scope: &'a Scope) -> Cow<'a, Value> {
+        match self {
+            Expr::Literal(value) => Cow::Borrowed(value),
+            Expr::Variable(name) => scope
+                .get(name)
+                .map_or(Cow::Owned(Value::Null), Cow::Borrowed),
+            Expr::Call(receiver, name) => {
+                let receiver = receiver.evaluate(scope);
+                match receiver.get(name.as_str()) {
+                    Some(_) => project(receiver, |value| value.get(name.as_str())),
+                    None => Cow::Owned(method(&receiver, name)),
+                }
+            }
+            Expr::Not(expr) => Cow::Owned(Value::Bool(!truthy(&expr.evaluate(scope)))),
+            Expr::And(left, right) => match left.evaluate(scope) {
+                left if truthy(&left) => right.evaluate(scope),
+                left => left,
+            },
+            Expr::Or(left, right) => match left.evaluate(scope) {
+                left if truthy(&left) => left,
+                _ => right.evaluate(scope),
+            },
+            Expr::Compare(comparison, left, right) => Cow::Owned(Value::Bool(
+                comparison.apply(&left.evaluate(scope), &right.evaluate(scope)),
+            )),
+            Expr::Hash(entries) => Cow::Owned(Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.evaluate(scope).into_owned()))
+                    .collect(),
+            )),
+            Expr::Array(items) => Cow::Owned(Value::Array(
+                items
+                    .iter()
+                    .map(|item| item.evaluate(scope).into_owned())
+                    .collect(),
+            )),
+            Expr::Interpolated(text) => Cow::Owned(Value::String(text.value(scope))),
+            Expr::Index(receiver, index) => {
+                let index = index.evaluate(scope);
+                project(receiver.evaluate(scope), |value| match (value, &*index) {
+                    (Value::Array(items), Value::Number(n)) => {
+                        let n = n.as_i64()?;
+                        let i = if n < 0 { items.len() as i64 + n } else { n };
+                        items.get(usize::try_from(i).ok()?)
+                    }
+                    (Value::Object(map), Value::String(key)) => map.get(key),
+                    _ => None,
+                })
+            }
+        }
+    }
+}
+
+/// Whether a value counts as true in a condition. As in Ruby, only `nil`
+/// and `false` are false; `0`, empty strings and empty arrays are true.
+pub fn truthy(value: &Value) -> bool {
+    !matches!(value, Value::Null | Value::Bool(false))
+}
+
+/// How a value is written into the document: `nil` is empty, strings are
+/// written as is and anything else in its JSON form.
+pub(crate) fn to_text(value: &Value) -> Cow<'_, str> {
+    match value {
+        Value::Null => Cow::Borrowed(""),
+        Value::String(s) => Cow::Borrowed(s),
+        value => Cow::Owned(value.to_string()),
+    }
+}
+
+/// The length of the code of an interpolation starting right after its
+/// `#{`, skipping over nested braces and quoted strings.
+fn interpolation_end(code: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut chars = code.char_indices();
+    while let Some((offset, ch)) = chars.next() {
+        match (ch, quote) {
+            ('\\', Some(_)) => {
+                chars.next();
+            }
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => (),
+            ('\'', None) | ('"', None) => quote = Some(ch),
+            ('{', None) => depth += 1,
+            ('}', None) if depth == 0 => return Some(offset),
+            ('}', None) => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// A piece of a text containing `#{}` interpolation.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Segment {
+    /// Text written as it is.
+    Text(String),
+    /// The code of a `#{}`.
+    Code(Code),
+}
+
+/// Splits `text` into the text written as it is and the code of every
+/// `#{code}`. A `#{` that is never closed or whose code cannot be parsed is
+/// an error, returned along with the offset of the `#{` in `text`.
+///
+/// An odd number of backslashes before `#{` escapes it, so `\#{a}` is
+/// written as `#{a}`. Every pair of backslashes is written as one, so
+/// `\\#{a}` is a backslash followed by the value of `a`.
+pub(crate) fn segments(text: &str) -> Result<Vec<Segment>, (usize, String)> {
+    let mut segments = vec![];
+    let mut pending = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("#{") {
+        let before = &rest[..start];
+        let backslashes = before.len() - before.trim_end_matches('\\').len();
+        pending.push_str(&before[..start - backslashes]);
+        pending.push_str(&"\\".repeat(backslashes / 2));
+        if backslashes % 2 == 1 {
+            pending.push_str("#{");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let offset = text.len() - rest.len() + start;
+        let code = &rest[start + 2..];
+        let end = match interpolation_end(code) {
+            Some(end) => end,
+            None => return Err((offset, "unterminated interpolation".to_string())),
+        };
+        match Code::parse(&code[..end]) {
+            Ok(code) => {
+                if !pending.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut pending)));
+                }
+                segments.push(Segment::Code(code));
+            }
+            Err(reason) => {
+                let reason = format!("{} in '#{{{}}}'", reason, &code[..end]);
+                return Err((offset, reason));
+            }
+        }
+        rest = &code[end + 1..];
+    }
+    pending.push_str(rest);
+    if !pending.is_empty() {
+        segments.push(Segment::Text(pending));
+    }
+    Ok(segments)
+}
+
+/// Writes `segments` with every piece of code replaced by its value,
+/// escaped when `escape` is set.
+pub(crate) fn write_segments(
+    segments: &[Segment],
+    scope: &Scope,
+    escape: bool,
+    out: &mut dyn Write,
+) -> fmt::Result {
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => out.write_str(text)?,
+            Segment::Code(code) => {
+                let value = code.evaluate(scope);
+                match escape {
+                    true => write_escaped(out, &to_text(&value))?,
+                    false => out.write_str(&to_text(&value))?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::context::Context;
+    use serde_json::json;
+
+    fn context() -> Context {
+        let mut context = Context::new();
+        context.insert("name", "world");
+        context.insert("user", json!({"name": "Jon", "tags": ["a", "b"]}));
+        context
+    }
+
+    fn eval(code: &str) -> Value {
+        parse(code)
+            .unwrap()
+            .evaluate(&Scope::new(&context()))
+            .into_owned()
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(json!("a'b"), eval("'a\\'b'"));
+        assert_eq!(json!("a\nb"), eval("\"a\\nb\""));
+        assert_eq!(json!(-12), eval("-12"));
+        assert_eq!(json!(1.5), eval("1.5"));
+        assert_eq!(json!("sym"), eval(":sym"));
+        assert_eq!(json!(null), eval("nil"));
+    }
+
+    #[test]
+    fn lookups() {
+        assert_eq!(json!("world"), eval("name"));
+        assert_eq!(json!("world"), eval("@name"));
+        assert_eq!(json!("Jon"), eval("user.name"));
+        assert_eq!(json!("Jon"), eval("user[:name]"));
+        assert_eq!(json!("b"), eval("user.tags[-1]"));
+        assert_eq!(json!(2), eval("user.tags.size"));
+        assert_eq!(json!("JON"), eval("user.name.upcase"));
+        assert_eq!(json!(null), eval("missing.name"));
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(
+            json!({"a": 1, "b": "world", "c": {"d": "Jon"}}),
+            eval("{ a: 1, :b => name, 'c' => { \"d\": user.name }, }")
+        );
+        assert_eq!(json!({}), eval("{}"));
+        assert!(parse("{ a 1 }").is_err());
+        assert_eq!(
+            json!(["a", null, ["Jon"]]),
+            eval("['a', nil, [user.name],]")
+        );
+        assert_eq!(json!([]), eval("[]"));
+        assert!(parse("[1 2]").is_err());
+    }
+
+    #[test]
+    fn render_calls() {
+        let render = |code: &str| parse_render(code);
+        assert!(is_call("render 'a'", "render") && is_call("render('a')", "render"));
+        assert!(!is_call("rendered", "render") && is_call("render", "render"));
+        assert_eq!(Ok(("a/b".to_string(), None)), render("render 'a/b'"));
+        assert_eq!(
+            Ok((
+                "a".to_string(),
+                Some("{ title: name, :n => 1 }".to_string())
+            )),
+            render("render(\"a\", title: name, :n => 1)")
+        );
+        assert_eq!(
+            Ok(("a".to_string(), Some("user".to_string()))),
+            render("render 'a', user")
+        );
+        assert_eq!(
+            Ok(("a".to_string(), Some("{ b: 1 }".to_string()))),
+            render("render partial: 'a', locals: { b: 1 }")
+        );
+        assert!(render("render name").is_err());
+        assert!(render("render 'a', b, c").is_err());
+        assert!(render("render locals: {}").is_err());
+        assert!(render("render").is_err());
+
+        assert_eq!(Ok(None), parse_name_argument(""));
+        assert_eq!(Ok(Some("side".to_string())), parse_name_argument(" :side"));
+        assert_eq!(
+            Ok(Some("side".to_string())),
+            parse_name_argument("('side')")
+        );
+        assert!(parse_name_argument(" side").is_err());
+        assert!(parse_name_argument(" :a, :b").is_err());
+    }
+
+    #[test]
+    fn attribute_hashes() {
+        let keys = |code: &str| {
+            parse_attribute_hash(code).map(|entries| {
+                entries
+                    .into_iter()
+                    .map(|(key, code, _)| (key, code.to_string()))
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            Ok(vec![
+                ("a".to_string(), "'x, y'".to_string()),
+                ("data-id".to_string(), "1".to_string()),
+                ("c".to_string(), "user.name".to_string()),
+                ("aria-label".to_string(), "nil".to_string()),
+            ]),
+            keys("{ a: 'x, y', 'data-id' => 1, :c => user.name, :\"aria-label\" => nil, }")
+        );
+        assert_eq!(Ok(vec![]), keys("{}"));
+        assert!(keys("{ a => 1 }").is_err());
+        assert!(keys("{ a: 1 } b").is_err());
+        assert!(keys("{ a: 1").is_err());
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(json!(true), eval("user.name == 'Jon' && !missing"));
+        assert_eq!(
+            json!(true),
+            eval("not user.tags.empty? and user.tags.size >= 2")
+        );
+        assert_eq!(
+            json!(false),
+            eval("user.tags.size < 2.5 && user.tags.size != 2")
+        );
+        assert_eq!(json!("world"), eval("missing || name"));
+        assert_eq!(json!(null), eval("missing && name"));
+        assert_eq!(json!(true), eval("(name or missing) == 'world'"));
+        assert_eq!(json!(false), eval("name == 1"));
+        assert!(truthy(&eval("0")));
+        assert!(truthy(&eval("''")));
+        assert!(!truthy(&eval("nil")));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse("a +").is_err());
+        assert!(parse("'open").is_err());
+        assert!(parse("user.").is_err());
+    }
+
+    #[test]
+    fn non_ascii() {
+        assert_eq!(json!("&é"), eval("\"&é\""));
+        assert_eq!(json!(null), eval("naïve"));
+        assert!(parse("a🎉").is_err());
+        assert!(parse("a 🎉").is_err());
+        assert!(parse("not🎉").is_err());
+        assert!(parse("a or é").is_ok());
+    }
+
+    #[test]
+    fn interpolation() {
+        let interpolate = |text: &str, context: &Context, escape: bool| {
+            let mut out = String::new();
+            let text = Interpolated::new(text).unwrap();
+            text.write(&Scope::new(context), escape, &mut out).unwrap();
+            out
+        };
+        assert_eq!(
+            "Hi world, Jon",
+            interpolate("Hi #{name}, #{user['name']}", &context(), false)
+        );
+        assert_eq!(
+            "#{name} \\world \\#{name}",
+            interpolate("\\#{name} \\\\#{name} \\\\\\#{name}", &context(), false)
+        );
+
+        let mut context = context();
+        context.insert("html", "<b>");
+        assert_eq!(
+            "<i>&lt;b&gt;</i>",
+            interpolate("<i>#{html}</i>", &context, true)
+        );
+
+        assert_eq!(json!("Hi world #{name}"), eval("\"Hi #{name} \\#{name}\""));
+        assert_eq!(json!("#{name}"), eval("'#{name}'"));
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(json!("\\world"), eval("\"\\\\#{name}\""));
+        assert_eq!(json!("a\\#b"), eval("\"a\\\\#b\""));
+        assert_eq!(json!("a\\#b"), eval("\"a\\\\\\#b\""));
+        assert_eq!(json!("a#b"), eval("\"a\\#b\""));
+        assert_eq!(json!("#{name}"), eval("\"\\#{name}\""));
+        assert_eq!(json!("a\tb\"c"), eval("\"a\\tb\\\"c\""));
+        assert_eq!(json!("c:\\dir"), eval("'c:\\\\dir'"));
+        assert_eq!(json!("a\\nb"), eval("'a\\nb'"));
+        assert_eq!(json!("x}y"), eval("\"#{'x}'}y\""));
+        assert_eq!(json!("Jon!"), eval("\"#{user[\"name\"]}!\""));
+        assert!(parse("\"#{name\"").is_err());
+        assert!(parse("\"a\\\"").is_err());
+    }
+
+    #[test]
+    fn invalid_interpolation() {
+        let error = |text| segments(text).err();
+        assert_eq!(
+            Some((7, "unterminated interpolation".to_string())),
+            error("a #{b} #{c")
+        );
+        assert_eq!(
+            Some((3, "unexpected 'b' in '#{a b}'".to_string())),
+            error("é #{a b}")
+        );
+        assert_eq!(None, error("\\#{a}"));
+        assert!(parse("\"#{a +}\"").is_err());
+    }
+}
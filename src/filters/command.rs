@@ -0,0 +1,214 @@
+use std::io::{Read, Write};
+use std::process::{self, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use wait_timeout::ChildExt;
+
+use crate::filters::{Filter, FilterContext};
+
+/// A filter that pipes its body to a local command and writes what the
+/// command prints, such as `sassc` for `:scss`:
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use haml::filters::Command;
+/// use haml::HamlOptions;
+///
+/// let scss = Command::new("scss", "sassc")
+///     .args(&["--stdin", "--style", "compressed"])
+///     .timeout(Duration::from_secs(2));
+/// let options = HamlOptions::new().filter(scss);
+/// ```
+///
+/// The command fails the filter when it can't be started, exits with an
+/// error or runs longer than its timeout, in which case it is killed. The
+/// timeout also fails it when processes it starts in the background keep
+/// its output open, though those are left running. It is run when a
+/// template with a static body is compiled and on every render otherwise,
+/// and never when [`HamlOptions::allow_commands`] is off.
+///
+/// [`HamlOptions::allow_commands`]: crate::HamlOptions::allow_commands
+#[derive(Clone, Debug)]
+pub struct Command {
+    name: String,
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl Command {
+    /// A filter called `name` running `program`, which is looked up on the
+    /// `PATH` when it isn't a path. The timeout defaults to ten seconds.
+    pub fn new(name: &str, program: &str) -> Command {
+        Command {
+            name: name.to_string(),
+            program: program.to_string(),
+            args: vec![],
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> Command {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub fn args(mut self, args: &[&str]) -> Command {
+        self.args.extend(args.iter().map(|arg| arg.to_string()));
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Command {
+        self.timeout = timeout;
+        self
+    }
+
+    fn run(&self, body: &str) -> Result<String, String> {
+        let deadline = Instant::now() + self.timeout;
+        let mut child = process::Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("could not run {}: {}", self.program, err))?;
+
+        // the pipes are fed and drained on their own threads so that a
+        // command writing a lot before reading everything can't block
+        let mut stdin = child.stdin.take();
+        let input = body.to_string();
+        thread::spawn(move || {
+            if let Some(stdin) = stdin.as_mut() {
+                // a command that exits early closes its end of the pipe,
+                // which is not an error here
+                let _ = stdin.write_all(input.as_bytes());
+            }
+        });
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+
+        let status = match child.wait_timeout(self.timeout) {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(self.timed_out());
+            }
+            Err(err) => return Err(format!("could not wait for {}: {}", self.program, err)),
+        };
+        // processes started by the command may keep its pipes open after it
+        // exits, so reading them is bounded by the timeout as well
+        let stdout = self.output(&stdout, deadline)?;
+        if !status.success() {
+            let stderr = self.output(&stderr, deadline)?;
+            return match stderr.trim() {
+                "" => Err(format!("{} {}", self.program, status)),
+                stderr => Err(format!("{} {}: {}", self.program, status, stderr)),
+            };
+        }
+        Ok(stdout.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// What a pipe drained by `drain` held, once it is closed, or an error
+    /// if it is still open at `deadline`.
+    fn output(&self, pipe: &Receiver<String>, deadline: Instant) -> Result<String, String> {
+        match pipe.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(output) => Ok(output),
+            Err(RecvTimeoutError::Timeout) => Err(self.timed_out()),
+            Err(RecvTimeoutError::Disconnected) => Ok(String::new()),
+        }
+    }
+
+    fn timed_out(&self) -> String {
+        format!("{} timed out after {:?}", self.program, self.timeout)
+    }
+}
+
+/// Reads everything from a pipe of a command on another thread, sending it
+/// once the pipe is closed.
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        let _ = sender.send(String::from_utf8_lossy(&output).into_owned());
+    });
+    receiver
+}
+
+impl Filter for Command {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn render(&self, body: &str, context: &FilterContext) -> Result<String, String> {
+        if !context.options().allow_commands {
+            return Err(format!("{} is not allowed to run", self.program));
+        }
+        self.run(body)
+    }
+
+    fn runs_command(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::HamlOptions;
+
+    fn render(command: &Command, body: &str) -> Result<String, String> {
+        command.render(body, &FilterContext::new(&HamlOptions::new()))
+    }
+
+    #[test]
+    fn output() {
+        let upcase = Command::new("upcase", "tr").args(&["a-z", "A-Z"]);
+        assert_eq!(
+            Ok("HELLO\nWORLD".to_string()),
+            render(&upcase, "hello\nworld\n")
+        );
+        let large = "x".repeat(1 << 20);
+        let cat = Command::new("cat", "cat");
+        assert_eq!(Ok(large.clone()), render(&cat, &large));
+    }
+
+    #[test]
+    fn errors() {
+        let fail = Command::new("fail", "sh").args(&["-c", "echo broken >&2; exit 3"]);
+        assert_eq!(
+            Err("sh exit status: 3: broken".to_string()),
+            render(&fail, "")
+        );
+        let missing = Command::new("missing", "haml-no-such-command");
+        assert!(render(&missing, "")
+            .unwrap_err()
+            .starts_with("could not run"));
+        let slow = Command::new("slow", "sleep")
+            .arg("5")
+            .timeout(Duration::from_millis(100));
+        assert_eq!(
+            Err("sleep timed out after 100ms".to_string()),
+            render(&slow, "")
+        );
+        let background = Command::new("background", "sh")
+            .args(&["-c", "sleep 5 & echo hi"])
+            .timeout(Duration::from_millis(200));
+        let start = Instant::now();
+        assert_eq!(
+            Err("sh timed out after 200ms".to_string()),
+            render(&background, "")
+        );
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let options = HamlOptions::new().allow_commands(false);
+        let cat = Command::new("cat", "cat");
+        let context = FilterContext::new(&options);
+        assert!(cat.render("a", &context).is_err());
+    }
+}
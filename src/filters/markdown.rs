@@ -0,0 +1,772 @@
+//! The `:markdown` filter, converting the common parts of Markdown without
+//! any dependency: ATX and setext headings, paragraphs with hard line
+//! breaks, emphasis, code spans, fenced and indented code blocks, block
+//! quotes, nested lists, rules, links, images and autolinks. Inline HTML is
+//! kept as it is and every other `&`, `<`, `>` and `"` is escaped.
+
+use std::fmt::Write;
+
+use crate::filters::{Filter, FilterContext};
+
+/// `:markdown` converts its body from Markdown. Elements without content
+/// such as `<br>` and `<hr>` are closed the way the format closes them.
+pub(crate) struct Markdown;
+
+impl Filter for Markdown {
+    fn name(&self) -> &str {
+        "markdown"
+    }
+
+    fn render(&self, body: &str, context: &FilterContext) -> Result<String, String> {
+        Ok(to_html(body, context.formatter.self_closing_end()))
+    }
+}
+
+/// Converts Markdown to HTML, ending tags that have no closing tag with
+/// `void_end`, e.g. `>` or ` />`.
+pub(crate) fn to_html(markdown: &str, void_end: &str) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+    blocks(&lines, void_end).join("\n")
+}
+
+/// The HTML of every block in `lines`.
+fn blocks(lines: &[&str], void_end: &str) -> Vec<String> {
+    let mut html = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = unindent(line);
+        let indent = indentation(line);
+        if trimmed.is_empty() {
+            i += 1;
+        } else if indent >= 4 {
+            i = indented_code(lines, i, &mut html);
+        } else if let Some(fence) = fence(trimmed) {
+            i = fenced_code(lines, i, fence, &mut html);
+        } else if let Some((level, text)) = heading(trimmed) {
+            html.push(format!(
+                "<h{}>{}</h{}>",
+                level,
+                inline(text, void_end),
+                level
+            ));
+            i += 1;
+        } else if is_rule(trimmed) {
+            html.push(format!("<hr{}", void_end));
+            i += 1;
+        } else if trimmed.starts_with('>') {
+            let mut quoted = vec![];
+            while let Some(rest) = lines.get(i).and_then(|l| unindent(l).strip_prefix('>')) {
+                quoted.push(rest.strip_prefix(' ').unwrap_or(rest));
+                i += 1;
+            }
+            let inner = blocks(&quoted, void_end).join("\n");
+            html.push(format!("<blockquote>\n{}\n</blockquote>", inner));
+        } else if list_marker(trimmed).is_some() {
+            i = list(lines, i, void_end, &mut html);
+        } else {
+            i = paragraph(lines, i, void_end, &mut html);
+        }
+    }
+    html
+}
+
+/// A line without its indentation. Only spaces and tabs indent, as other
+/// whitespace may take more than one byte.
+fn unindent(line: &str) -> &str {
+    line.trim_start_matches([' ', '\t'])
+}
+
+/// The number of spaces and tabs indenting a line.
+fn indentation(line: &str) -> usize {
+    line.len() - unindent(line).len()
+}
+
+/// Whether a line starts a block other than a paragraph, which ends the
+/// paragraph before it.
+fn starts_block(trimmed: &str) -> bool {
+    fence(trimmed).is_some()
+        || heading(trimmed).is_some()
+        || is_rule(trimmed)
+        || trimmed.starts_with('>')
+        || list_marker(trimmed).is_some()
+}
+
+fn paragraph(lines: &[&str], start: usize, void_end: &str, html: &mut Vec<String>) -> usize {
+    let mut text = vec![lines[start].trim_start()];
+    let mut i = start + 1;
+    while let Some(line) = lines.get(i) {
+        let trimmed = line.trim();
+        // a line of = or - under a paragraph turns it into a heading
+        let underline = trimmed.chars().next().filter(|c| *c == '=' || *c == '-');
+        if let Some(c) = underline.filter(|c| trimmed.chars().all(|t| t == *c)) {
+            let level = if c == '=' { 1 } else { 2 };
+            let content = inline(text.join("\n").trim(), void_end);
+            html.push(format!("<h{}>{}</h{}>", level, content, level));
+            return i + 1;
+        }
+        if trimmed.is_empty() || starts_block(unindent(line)) {
+            break;
+        }
+        text.push(unindent(line));
+        i += 1;
+    }
+    let content = inline(text.join("\n").trim_end(), void_end);
+    html.push(format!("<p>{}</p>", content));
+    i
+}
+
+fn indented_code(lines: &[&str], start: usize, html: &mut Vec<String>) -> usize {
+    let mut code = vec![];
+    let mut i = start;
+    while let Some(line) = lines.get(i) {
+        let indent = indentation(line);
+        if !line.trim().is_empty() && indent < 4 {
+            break;
+        }
+        code.push(line.get(4..).unwrap_or(""));
+        i += 1;
+    }
+    while code.last().is_some_and(|line| line.trim().is_empty()) {
+        code.pop();
+    }
+    html.push(format!(
+        "<pre><code>{}\n</code></pre>",
+        escape(&code.join("\n"))
+    ));
+    i
+}
+
+/// The fence opening a code block: its character and length.
+fn fence(trimmed: &str) -> Option<(char, usize)> {
+    let c = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = trimmed.chars().take_while(|t| *t == c).count();
+    match length >= 3 && !(c == '`' && trimmed[length..].contains('`')) {
+        true => Some((c, length)),
+        false => None,
+    }
+}
+
+fn fenced_code(
+    lines: &[&str],
+    start: usize,
+    fence: (char, usize),
+    html: &mut Vec<String>,
+) -> usize {
+    let (c, length) = fence;
+    let opening = lines[start];
+    let indent = indentation(opening);
+    let language = unindent(opening)[length..].split_whitespace().next();
+    let mut code = vec![];
+    let mut i = start + 1;
+    while let Some(line) = lines.get(i) {
+        i += 1;
+        let trimmed = line.trim();
+        if trimmed.len() >= length && trimmed.chars().all(|t| t == c) {
+            break;
+        }
+        // the content loses as much indentation as the fence has
+        let spaces = indentation(line);
+        code.push(&line[spaces.min(indent)..]);
+    }
+    let class = match language {
+        Some(language) => format!(" class=\"language-{}\"", escape(language)),
+        None => String::new(),
+    };
+    let mut content = escape(&code.join("\n"));
+    if !code.is_empty() {
+        content.push('\n');
+    }
+    html.push(format!("<pre><code{}>{}</code></pre>", class, content));
+    i
+}
+
+/// The level and text of an ATX heading such as `## Title ##`.
+fn heading(trimmed: &str) -> Option<(usize, &str)> {
+    let level = trimmed.chars().take_while(|c| *c == '#').count();
+    let rest = &trimmed[level..];
+    if level == 0 || level > 6 || !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+    let text = rest.trim();
+    // a closing sequence of # is dropped when a space comes before it
+    let without_closing = text.trim_end_matches('#');
+    let text = match without_closing.is_empty() || without_closing.ends_with(' ') {
+        true => without_closing.trim_end(),
+        false => text,
+    };
+    Some((level, text))
+}
+
+/// Whether a line is a rule: three or more `-`, `*` or `_`, possibly
+/// separated by spaces.
+fn is_rule(trimmed: &str) -> bool {
+    let mut chars = trimmed.chars().filter(|c| !c.is_whitespace());
+    match chars.next() {
+        Some(c) if c == '-' || c == '*' || c == '_' => {
+            let rest = chars.collect::<Vec<char>>();
+            rest.len() >= 2 && rest.iter().all(|t| *t == c)
+        }
+        _ => false,
+    }
+}
+
+/// The list marker starting a line: whether the list is ordered, the
+/// number of an ordered item and the width of the marker including the
+/// space after it.
+fn list_marker(trimmed: &str) -> Option<(bool, usize, usize)> {
+    let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+    let (ordered, marker) = match digits {
+        0 if trimmed.starts_with(['-', '*', '+']) => (false, 1),
+        1..=9 if trimmed[digits..].starts_with(['.', ')']) => (true, digits + 1),
+        _ => return None,
+    };
+    let rest = &trimmed[marker..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    let number = trimmed[..digits].parse().unwrap_or(1);
+    let spaces = indentation(rest);
+    Some((ordered, number, marker + spaces.clamp(1, 4)))
+}
+
+fn list(lines: &[&str], start: usize, void_end: &str, html: &mut Vec<String>) -> usize {
+    let first = lines[start];
+    let base = indentation(first);
+    let (ordered, number, _) = list_marker(unindent(first)).unwrap_or((false, 1, 2));
+    let mut items: Vec<Vec<String>> = vec![];
+    let mut loose = false;
+    let mut i = start;
+    while let Some(line) = lines.get(i) {
+        let trimmed = unindent(line);
+        let indent = indentation(line);
+        let width = match list_marker(trimmed) {
+            Some((o, _, width)) if o == ordered && indent <= base + 1 && !is_rule(trimmed) => width,
+            _ => break,
+        };
+        let content = indent + width;
+        let mut item = vec![trimmed.get(width..).unwrap_or("").to_string()];
+        i += 1;
+        while let Some(line) = lines.get(i) {
+            let trimmed = unindent(line);
+            let indent = indentation(line);
+            if trimmed.is_empty() {
+                let next = lines[i..].iter().position(|l| !l.trim().is_empty());
+                let next = match next.map(|n| lines[i + n]) {
+                    Some(next) => next,
+                    None => break,
+                };
+                let next_indent = indentation(next);
+                if next_indent >= content {
+                    // a blank line between the blocks of an item
+                    loose = true;
+                    item.push(String::new());
+                    i += 1;
+                    continue;
+                }
+                let same_list = list_marker(unindent(next)).is_some_and(|(o, _, _)| o == ordered);
+                if next_indent <= base + 1 && same_list {
+                    // a blank line between items
+                    loose = true;
+                }
+                break;
+            } else if indent >= content {
+                item.push(line[content..].to_string());
+            } else if !starts_block(trimmed) && !item.last().is_some_and(String::is_empty) {
+                // a lazy continuation of the item's paragraph
+                item.push(trimmed.to_string());
+            } else {
+                break;
+            }
+            i += 1;
+        }
+        items.push(item);
+        if lines.get(i).is_some_and(|l| l.trim().is_empty()) {
+            // skip the blank lines between items
+            while lines.get(i).is_some_and(|l| l.trim().is_empty()) {
+                i += 1;
+            }
+        }
+    }
+
+    let tag = if ordered { "ol" } else { "ul" };
+    let mut list = format!("<{}", tag);
+    if ordered && number != 1 {
+        let _ = write!(list, " start=\"{}\"", number);
+    }
+    list.push_str(">\n");
+    for item in items {
+        let lines: Vec<&str> = item.iter().map(String::as_str).collect();
+        let mut content = blocks(&lines, void_end);
+        // the paragraphs of a tight list are not wrapped in <p>, and only
+        // the other blocks go on lines of their own
+        let mut bare = vec![false; content.len()];
+        if !loose {
+            for (block, bare) in content.iter_mut().zip(bare.iter_mut()) {
+                if let Some(text) = block
+                    .strip_prefix("<p>")
+                    .and_then(|b| b.strip_suffix("</p>"))
+                {
+                    *block = text.to_string();
+                    *bare = true;
+                }
+            }
+        }
+        list.push_str("<li>");
+        if !bare.first().unwrap_or(&true) {
+            list.push('\n');
+        }
+        list.push_str(&content.join("\n"));
+        if !bare.last().unwrap_or(&true) {
+            list.push('\n');
+        }
+        list.push_str("</li>\n");
+    }
+    let _ = write!(list, "</{}>", tag);
+    html.push(list);
+    i
+}
+
+/// The HTML of the text of a paragraph, heading or list item.
+fn inline(text: &str, void_end: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut html = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(char::is_ascii_punctuation) => {
+                html.push_str(&escape(&chars[i + 1].to_string()));
+                i += 2;
+            }
+            '\\' if chars.get(i + 1) == Some(&'\n') => {
+                let _ = writeln!(html, "<br{}", void_end);
+                i += 2;
+            }
+            '`' => i = code_span(&chars, i, &mut html),
+            '!' if chars.get(i + 1) == Some(&'[') => match link(&chars, i + 1) {
+                Some(link) => {
+                    let _ = write!(
+                        html,
+                        "<img src=\"{}\" alt=\"{}\"{}{}",
+                        escape(&link.url),
+                        escape(&plain_text(&link.text)),
+                        title(&link.title),
+                        void_end
+                    );
+                    i = link.end;
+                }
+                None => {
+                    html.push('!');
+                    i += 1;
+                }
+            },
+            '[' => match link(&chars, i) {
+                Some(link) => {
+                    let _ = write!(
+                        html,
+                        "<a href=\"{}\"{}>{}</a>",
+                        escape(&link.url),
+                        title(&link.title),
+                        inline(&link.text, void_end)
+                    );
+                    i = link.end;
+                }
+                None => {
+                    html.push('[');
+                    i += 1;
+                }
+            },
+            '<' => i = angle_bracket(&chars, i, &mut html),
+            '*' | '_' => i = emphasis(&chars, i, void_end, &mut html),
+            '\n' => {
+                // two spaces at the end of a line make a hard break
+                let hard = html.ends_with("  ");
+                html.truncate(html.trim_end_matches(' ').len());
+                match hard {
+                    true => {
+                        let _ = writeln!(html, "<br{}", void_end);
+                    }
+                    false => html.push('\n'),
+                }
+                i += 1;
+            }
+            '&' => {
+                let rest: String = chars[i..].iter().take(12).collect();
+                match entity(&rest) {
+                    Some(length) => html.push_str(&rest[..length]),
+                    None => html.push_str("&amp;"),
+                }
+                i += entity(&rest).unwrap_or(1);
+            }
+            _ => {
+                html.push_str(&escape(&c.to_string()));
+                i += 1;
+            }
+        }
+    }
+    html
+}
+
+/// Writes the code span starting with the backticks at `start`, or the
+/// backticks themselves when they are never closed.
+fn code_span(chars: &[char], start: usize, html: &mut String) -> usize {
+    let ticks = chars[start..].iter().take_while(|c| **c == '`').count();
+    let mut j = start + ticks;
+    while j < chars.len() {
+        let run = chars[j..].iter().take_while(|c| **c == '`').count();
+        if run == ticks {
+            let code: String = chars[start + ticks..j]
+                .iter()
+                .map(|c| if *c == '\n' { ' ' } else { *c })
+                .collect();
+            let code = match code.starts_with(' ') && code.ends_with(' ') && code.trim() != "" {
+                true => &code[1..code.len() - 1],
+                false => &code[..],
+            };
+            let _ = write!(html, "<code>{}</code>", escape(code));
+            return j + run;
+        }
+        j += run.max(1);
+    }
+    html.push_str(&"`".repeat(ticks));
+    start + ticks
+}
+
+/// A link or an image, `[text](url "title")`.
+struct Link {
+    text: String,
+    url: String,
+    title: Option<String>,
+    // the index just after the closing parenthesis
+    end: usize,
+}
+
+fn link(chars: &[char], start: usize) -> Option<Link> {
+    let close = matching(chars, start, '[', ']')?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = matching(chars, close + 1, '(', ')')?;
+    let text: String = chars[start + 1..close].iter().collect();
+    let destination: String = chars[close + 2..end].iter().collect();
+    let destination = destination.trim();
+    let (url, title) = match destination.find(char::is_whitespace) {
+        Some(space) => {
+            let title = destination[space..].trim();
+            let quoted = title.len() >= 2
+                && (title.starts_with('"') && title.ends_with('"')
+                    || title.starts_with('\'') && title.ends_with('\''));
+            if !quoted {
+                return None;
+            }
+            let title = title[1..title.len() - 1].to_string();
+            (&destination[..space], Some(title))
+        }
+        None => (destination, None),
+    };
+    let url = url
+        .strip_prefix('<')
+        .and_then(|u| u.strip_suffix('>'))
+        .unwrap_or(url);
+    Some(Link {
+        text,
+        url: url.to_string(),
+        title,
+        end: end + 1,
+    })
+}
+
+fn title(title: &Option<String>) -> String {
+    match title {
+        Some(title) => format!(" title=\"{}\"", escape(title)),
+        None => String::new(),
+    }
+}
+
+/// The index of the bracket closing the one at `start`, skipping nested
+/// brackets and escaped characters.
+fn matching(chars: &[char], start: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut j = start;
+    while j < chars.len() {
+        match chars[j] {
+            '\\' => j += 1,
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(j);
+                }
+            }
+            _ => (),
+        }
+        j += 1;
+    }
+    None
+}
+
+/// The text of the alt attribute of an image, without markup.
+fn plain_text(text: &str) -> String {
+    text.chars()
+        .filter(|c| !matches!(c, '*' | '_' | '`' | '[' | ']'))
+        .collect()
+}
+
+/// Writes an autolink, inline HTML or an escaped `<`.
+fn angle_bracket(chars: &[char], start: usize, html: &mut String) -> usize {
+    let end = chars[start + 1..]
+        .iter()
+        .position(|c| *c == '>' || *c == '<' || *c == '\n')
+        .map(|n| start + 1 + n)
+        .filter(|end| chars[*end] == '>');
+    let end = match end {
+        Some(end) => end,
+        None => {
+            html.push_str("&lt;");
+            return start + 1;
+        }
+    };
+    let inside: String = chars[start + 1..end].iter().collect();
+    let scheme = inside.split(':').next().unwrap_or("");
+    let is_url = inside.contains(':')
+        && !scheme.is_empty()
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c))
+        && !inside.contains(char::is_whitespace);
+    let is_email = !inside.contains(char::is_whitespace)
+        && inside.split('@').count() == 2
+        && !inside.starts_with('@')
+        && inside.contains('.');
+    let tag = inside.strip_prefix('/').unwrap_or(&inside);
+    let is_tag = tag.starts_with(|c: char| c.is_ascii_alphabetic()) || inside.starts_with("!--");
+    if is_url {
+        let _ = write!(
+            html,
+            "<a href=\"{}\">{}</a>",
+            escape(&inside),
+            escape(&inside)
+        );
+    } else if is_email {
+        let _ = write!(
+            html,
+            "<a href=\"mailto:{}\">{}</a>",
+            escape(&inside),
+            escape(&inside)
+        );
+    } else if is_tag {
+        let _ = write!(html, "<{}>", inside);
+    } else {
+        html.push_str("&lt;");
+        return start + 1;
+    }
+    end + 1
+}
+
+/// Writes the emphasis opened by the `*` or `_` at `start`, or the
+/// delimiters themselves when they open nothing.
+fn emphasis(chars: &[char], start: usize, void_end: &str, html: &mut String) -> usize {
+    let c = chars[start];
+    let run = chars[start..].iter().take_while(|t| **t == c).count();
+    let before = start.checked_sub(1).map(|b| chars[b]);
+    let after = chars.get(start + run);
+    // `_` inside a word, as in snake_case, is not emphasis
+    let opens = after.is_some_and(|a| !a.is_whitespace())
+        && !(c == '_' && before.is_some_and(char::is_alphanumeric));
+    if opens {
+        for (width, tag) in [(2, "strong"), (1, "em")] {
+            if run < width {
+                continue;
+            }
+            let from = start + width;
+            if let Some(close) = closing(chars, from, c, width) {
+                let inner: String = chars[from..close].iter().collect();
+                let _ = write!(html, "<{}>{}</{}>", tag, inline(&inner, void_end), tag);
+                return close + width;
+            }
+        }
+    }
+    html.push_str(&c.to_string().repeat(run));
+    start + run
+}
+
+/// The index of the delimiters of `width` closing emphasis that starts at
+/// `from`, skipping code spans and runs of other widths.
+fn closing(chars: &[char], from: usize, c: char, width: usize) -> Option<usize> {
+    let mut j = from;
+    while j < chars.len() {
+        match chars[j] {
+            '\\' => j += 2,
+            '`' => {
+                let ticks = chars[j..].iter().take_while(|t| **t == '`').count();
+                let rest = &chars[j + ticks..];
+                let close = (0..rest.len()).find(|k| {
+                    rest[*k..].iter().take_while(|t| **t == '`').count() == ticks
+                        && (*k == 0 || rest[k - 1] != '`')
+                });
+                j += ticks + close.map(|k| k + ticks).unwrap_or(0);
+            }
+            t if t == c => {
+                let run = chars[j..].iter().take_while(|t| **t == c).count();
+                let after = chars.get(j + run);
+                let closes = j > from
+                    && !chars[j - 1].is_whitespace()
+                    && !(c == '_' && after.is_some_and(|a| a.is_alphanumeric()));
+                if closes && (run == width || run == 3) {
+                    return Some(j + run - width);
+                }
+                j += run;
+            }
+            _ => j += 1,
+        }
+    }
+    None
+}
+
+/// The length of the character reference at the start of `text`, such as
+/// `&amp;` or `&#39;`.
+fn entity(text: &str) -> Option<usize> {
+    let end = text.find(';')?;
+    let name = &text[1..end];
+    let valid = match name.strip_prefix('#') {
+        Some(code) => match code.strip_prefix(['x', 'X']) {
+            Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => !code.is_empty() && code.chars().all(|c| c.is_ascii_digit()),
+        },
+        None => !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric()),
+    };
+    match valid {
+        true => Some(end + 1),
+        false => None,
+    }
+}
+
+/// Escapes the characters that are special in HTML text and attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn html(markdown: &str) -> String {
+        to_html(markdown, ">")
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            "<h1>Title</h1>\n<h3>Sub</h3>\n<p>One\ntwo</p>\n<p>Three</p>",
+            html("# Title\n### Sub ###\nOne\ntwo\n\nThree")
+        );
+        assert_eq!("<h1>A</h1>\n<h2>B</h2>", html("A\n===\nB\n---"));
+        assert_eq!("<p>a</p>\n<hr>\n<p>b</p>", html("a\n\n* * *\n\nb"));
+        assert_eq!("<hr />", to_html("---", " />"));
+        assert_eq!("<p>#hashtag</p>", html("#hashtag"));
+        assert_eq!(
+            "<blockquote>\n<p>quoted\ntext</p>\n</blockquote>",
+            html("> quoted\n> text")
+        );
+    }
+
+    #[test]
+    fn code() {
+        assert_eq!(
+            "<pre><code class=\"language-rust\">fn a() {\n  &lt;b&gt;\n}\n</code></pre>",
+            html("```rust\nfn a() {\n  <b>\n}\n```")
+        );
+        assert_eq!(
+            "<p>Code:</p>\n<pre><code>a &amp;&amp; b\n\nc\n</code></pre>",
+            html("Code:\n\n    a && b\n\n    c\n\n")
+        );
+        assert_eq!(
+            "<p><code>a*b*</code> and <code>x ` y</code></p>",
+            html("`a*b*` and `` x ` y ``")
+        );
+        assert_eq!("<p>`open</p>", html("`open"));
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            "<ul>\n<li>one</li>\n<li>two\ncontinued</li>\n</ul>",
+            html("- one\n- two\ncontinued")
+        );
+        assert_eq!(
+            "<ol start=\"3\">\n<li>three</li>\n<li>four</li>\n</ol>",
+            html("3. three\n4. four")
+        );
+        assert_eq!(
+            "<ul>\n<li>\n<p>one</p>\n</li>\n<li>\n<p>two</p>\n</li>\n</ul>",
+            html("* one\n\n* two")
+        );
+        assert_eq!(
+            "<ul>\n<li>a\n<ul>\n<li>b</li>\n</ul>\n</li>\n<li>c</li>\n</ul>",
+            html("- a\n  - b\n- c")
+        );
+        assert_eq!(
+            "<ul>\n<li>a</li>\n</ul>\n<ol>\n<li>b</li>\n</ol>",
+            html("- a\n\n1. b")
+        );
+    }
+
+    #[test]
+    fn inline() {
+        assert_eq!(
+            "<p><em>a</em> <strong>b</strong> <strong><em>c</em></strong> <em>d <strong>e</strong></em></p>",
+            html("*a* __b__ ***c*** _d **e**_")
+        );
+        assert_eq!(
+            "<p>snake_case_name and 2 * 3 * 4</p>",
+            html("snake_case_name and 2 * 3 * 4")
+        );
+        assert_eq!("<p>*not emphasis*</p>", html("\\*not emphasis\\*"));
+        assert_eq!(
+            "<p><a href=\"http://a.com/?a=1&amp;b=2\" title=\"A\">the <em>site</em></a></p>",
+            html("[the *site*](http://a.com/?a=1&b=2 \"A\")")
+        );
+        assert_eq!(
+            "<p><img src=\"a.png\" alt=\"An image\" /></p>",
+            to_html("![An *image*](a.png)", " />")
+        );
+        assert_eq!(
+            "<p><a href=\"https://a.com\">https://a.com</a> <a href=\"mailto:me@a.com\">me@a.com</a></p>",
+            html("<https://a.com> <me@a.com>")
+        );
+        assert_eq!("<p>[not a link] (here)</p>", html("[not a link] (here)"));
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            "<p>1 &lt; 2 &amp;&amp; &quot;x&quot; &copy; <b>bold</b></p>",
+            html("1 < 2 && \"x\" &copy; <b>bold</b>")
+        );
+        assert_eq!("<p>line<br>\nbreak</p>", html("line  \nbreak"));
+        assert_eq!("<p>line<br />\nbreak</p>", to_html("line\\\nbreak", " />"));
+    }
+
+    #[test]
+    fn non_ascii_whitespace() {
+        assert_eq!(
+            "<ul>\n<li>a\n\u{3000}x</li>\n</ul>",
+            html("- a\n \u{3000}x")
+        );
+        assert_eq!(
+            "<pre><code>\u{3000}x\n</code></pre>",
+            html(" ```\n\u{3000}x\n ```")
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! Filters render the body of a `:name` line, such as `:plain` or
+//! `:javascript`. The filters a template can use are registered on its
+//! [`HamlOptions`], which come with the standard Haml filters, and any type
+//! implementing [`Filter`] can be added:
+//!
+//! ```
+//! use haml::filters::{Filter, FilterContext};
+//! use haml::HamlOptions;
+//!
+//! struct Shout;
+//!
+//! impl Filter for Shout {
+//!     fn name(&self) -> &str {
+//!         "shout"
+//!     }
+//!
+//!     fn render(&self, body: &str, _context: &FilterContext) -> Result<String, String> {
+//!         Ok(format!("<p>{}!</p>", body.to_uppercase()))
+//!     }
+//! }
+//!
+//! let options = HamlOptions::new().filter(Shout);
+//! let html = haml::to_html_with_options(":shout\n  hello", &options).unwrap();
+//! assert_eq!("<p>HELLO!</p>", html);
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::{Error, ErrorKind};
+use crate::formatter::{escape_html, get_formatter, HtmlFormatter, SCRIPT, STYLE};
+use crate::options::HamlOptions;
+use crate::span::Span;
+use crate::Format;
+
+mod command;
+mod markdown;
+
+pub use command::Command;
+use markdown::Markdown;
+
+/// What a filter knows about the template it is rendered in.
+pub struct FilterContext<'a> {
+    options: &'a HamlOptions,
+    formatter: Box<dyn HtmlFormatter>,
+}
+
+impl<'a> FilterContext<'a> {
+    pub fn new(options: &'a HamlOptions) -> FilterContext<'a> {
+        FilterContext {
+            options,
+            formatter: get_formatter(options),
+        }
+    }
+
+    /// The options the template is rendered with.
+    pub fn options(&self) -> &'a HamlOptions {
+        self.options
+    }
+
+    /// The format the template is rendered in.
+    pub fn format(&self) -> Format {
+        self.options.effective_format()
+    }
+}
+
+/// Renders the body of a `:name` line to HTML.
+pub trait Filter: Send + Sync {
+    /// The name used after the colon, e.g. `plain` for `:plain`.
+    fn name(&self) -> &str;
+
+    /// The HTML of a filter whose body is `body`. The body has its common
+    /// indentation removed and its `#{}` interpolation done.
+    ///
+    /// An error stops a template whose filter body has no interpolation
+    /// from compiling. Other bodies are only rendered along with the
+    /// template, where an error fails [`Template::try_render`] and writes
+    /// nothing with [`Template::render`].
+    ///
+    /// [`Template::try_render`]: crate::Template::try_render
+    /// [`Template::render`]: crate::Template::render
+    fn render(&self, body: &str, context: &FilterContext) -> std::result::Result<String, String>;
+
+    /// Whether the filter runs a command, which templates can only use when
+    /// [`HamlOptions::allow_commands`] is set.
+    fn runs_command(&self) -> bool {
+        false
+    }
+}
+
+/// The filters available to templates, by name.
+#[derive(Clone)]
+pub struct Filters {
+    filters: BTreeMap<String, Arc<dyn Filter>>,
+    // the names of the standard filters that have not been replaced
+    standard: BTreeSet<String>,
+}
+
+impl Filters {
+    /// No filters at all.
+    pub fn empty() -> Filters {
+        Filters {
+            filters: BTreeMap::new(),
+            standard: BTreeSet::new(),
+        }
+    }
+
+    /// The standard Haml filters: `plain`, `escaped`, `preserve`, `css`,
+    /// `javascript` and `markdown`.
+    pub fn standard() -> Filters {
+        let mut filters = Filters::empty();
+        filters.register(Plain);
+        filters.register(Escaped);
+        filters.register(Preserve);
+        filters.register(Css);
+        filters.register(Javascript);
+        filters.register(Markdown);
+        filters.standard = filters.filters.keys().cloned().collect();
+        filters
+    }
+
+    /// Adds a filter, replacing any filter with the same name.
+    pub fn register<F: Filter + 'static>(&mut self, filter: F) {
+        self.standard.remove(filter.name());
+        self.filters
+            .insert(filter.name().to_string(), Arc::new(filter));
+    }
+
+    /// Removes the filter called `name`, so templates using it no longer
+    /// compile.
+    pub fn remove(&mut self, name: &str) {
+        self.standard.remove(name);
+        self.filters.remove(name);
+    }
+
+    /// Whether the filter called `name` is one of the standard filters,
+    /// which compiled templates can also run.
+    pub(crate) fn is_standard(&self, name: &str) -> bool {
+        self.standard.contains(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Filter> {
+        self.filters.get(name).map(|filter| &**filter)
+    }
+
+    /// The names of the filters, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.filters.keys().map(String::as_str)
+    }
+}
+
+impl Default for Filters {
+    fn default() -> Filters {
+        Filters::standard()
+    }
+}
+
+impl fmt::Debug for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+impl PartialEq for Filters {
+    fn eq(&self, other: &Filters) -> bool {
+        self.filters.len() == other.filters.len()
+            && self
+                .filters
+                .iter()
+                .zip(other.filters.iter())
+                .all(|((a, f), (b, g))| a == b && Arc::ptr_eq(f, g))
+    }
+}
+
+/// Renders a filter of a template compiled with `options`, or returns why
+/// it failed.
+pub(crate) fn render(
+    name: &str,
+    body: &str,
+    options: &HamlOptions,
+) -> std::result::Result<String, String> {
+    match options.filters.get(name) {
+        Some(filter) => filter.render(body, &FilterContext::new(options)),
+        None => Err(format!("Filter \"{}\" is not defined", name)),
+    }
+}
+
+/// The error of the filter `name`, whose line starts at `span`, failing to
+/// render for `reason`.
+pub(crate) fn failed(name: &str, span: Span, reason: String) -> Error {
+    // the template itself is no longer at hand, so its line is rebuilt
+    let snippet = format!("{}:{}", " ".repeat(span.column.saturating_sub(1)), name);
+    Error::new(ErrorKind::FilterFailed(reason), span, &snippet)
+}
+
+/// `:plain` writes its body as it is.
+struct Plain;
+
+impl Filter for Plain {
+    fn name(&self) -> &str {
+        "plain"
+    }
+
+    fn render(&self, body: &str, _context: &FilterContext) -> std::result::Result<String, String> {
+        Ok(body.to_string())
+    }
+}
+
+/// `:escaped` writes its body with HTML escaped.
+struct Escaped;
+
+impl Filter for Escaped {
+    fn name(&self) -> &str {
+        "escaped"
+    }
+
+    fn render(&self, body: &str, _context: &FilterContext) -> std::result::Result<String, String> {
+        Ok(escape_html(body))
+    }
+}
+
+/// `:preserve` writes its body with the newline ending each line encoded, so
+/// that it is not indented.
+struct Preserve;
+
+impl Filter for Preserve {
+    fn name(&self) -> &str {
+        "preserve"
+    }
+
+    fn render(&self, body: &str, _context: &FilterContext) -> std::result::Result<String, String> {
+        Ok(format!("{}\n", body).replace('\n', "&#x000A;"))
+    }
+}
+
+/// `:css` wraps its body in a `style` tag, as the format requires.
+struct Css;
+
+impl Filter for Css {
+    fn name(&self) -> &str {
+        "css"
+    }
+
+    fn render(&self, body: &str, context: &FilterContext) -> std::result::Result<String, String> {
+        Ok(context.formatter.embedded_to_html(&STYLE, body))
+    }
+}
+
+/// `:javascript` wraps its body in a `script` tag, as the format requires.
+struct Javascript;
+
+impl Filter for Javascript {
+    fn name(&self) -> &str {
+        "javascript"
+    }
+
+    fn render(&self, body: &str, context: &FilterContext) -> std::result::Result<String, String> {
+        Ok(context.formatter.embedded_to_html(&SCRIPT, body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter(name: &str, body: &str) -> String {
+        render(name, body, &HamlOptions::new()).unwrap()
+    }
+
+    #[test]
+    fn standard() {
+        assert_eq!("a\n  b", filter("plain", "a\n  b"));
+        assert_eq!("&lt;&amp;&quot;&gt;", filter("escaped", "<&\">"));
+        assert_eq!("a&#x000A;b&#x000A;", filter("preserve", "a\nb"));
+        assert_eq!("<style>\n  a\n\n  b\n</style>", filter("css", "a\n\nb"));
+        assert_eq!("<script>\n  a();\n</script>", filter("javascript", "a();"));
+    }
+
+    #[test]
+    fn embedded() {
+        let body = "a {\n  b: c;\n\n}\n\n";
+        let render = |format| {
+            let options = HamlOptions::new().format(format).attr_wrapper('"');
+            (
+                super::render("css", body, &options).unwrap(),
+                super::render("javascript", "a();", &options).unwrap(),
+            )
+        };
+        assert_eq!(
+            (
+                "<style>\n  a {\n    b: c;\n\n  }\n</style>".to_string(),
+                "<script>\n  a();\n</script>".to_string()
+            ),
+            render(Format::Html5())
+        );
+        assert_eq!(
+            (
+                "<style type=\"text/css\">\n  a {\n    b: c;\n\n  }\n</style>".to_string(),
+                "<script type=\"text/javascript\">\n  a();\n</script>".to_string()
+            ),
+            render(Format::Html4())
+        );
+        let cdata = (
+            "<style type=\"text/css\">\n  /*<![CDATA[*/\n    a {\n      b: c;\n\n    }\n  /*]]>*/\n</style>"
+                .to_string(),
+            "<script type=\"text/javascript\">\n  //<![CDATA[\n    a();\n  //]]>\n</script>".to_string(),
+        );
+        assert_eq!(cdata, render(Format::XHtml()));
+        assert_eq!(cdata, render(Format::Xml()));
+    }
+
+    #[test]
+    fn registry() {
+        let mut filters = Filters::standard();
+        assert_eq!(
+            vec![
+                "css",
+                "escaped",
+                "javascript",
+                "markdown",
+                "plain",
+                "preserve"
+            ],
+            filters.names().collect::<Vec<_>>()
+        );
+        assert_eq!(filters, filters.clone());
+        filters.remove("css");
+        assert!(filters.get("css").is_none());
+        assert_ne!(Filters::standard(), filters);
+        assert!(render("css", "a", &HamlOptions::new().filters(filters)).is_err());
+    }
+
+    #[test]
+    fn replaced() {
+        let mut filters = Filters::standard();
+        assert!(filters.is_standard("plain"));
+        filters.register(Command::new("plain", "cat"));
+        filters.remove("css");
+        assert!(filters.is_standard("escaped"));
+        assert!(!filters.is_standard("plain"));
+        assert!(!filters.is_standard("css"));
+        assert!(!Filters::empty().is_standard("plain"));
+    }
+}
@@ -1,7 +1,20 @@
-use std::fmt::Debug;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Write};
+use std::io;
+use std::sync::Arc;
 
-use crate::arena::Arena;
+use crate::arena::{Arena, ArenaItem};
+use crate::context::{Context, Scope};
+use crate::error::Result;
+use crate::expr;
+use crate::filters;
+use crate::options::{HamlOptions, Newline};
+use crate::parser::element::{AttributeValue, Element};
+use crate::parser::{Code, Each, Filter, Haml, Interpolated, Partial, Script};
+use crate::template::Template;
 use crate::Format;
+use serde_json::Value;
 
 pub mod html4_formatter;
 pub mod html5_formatter;
@@ -13,15 +26,804 @@ use html5_formatter::Html5Formatter;
 use xhtml_formatter::XHtmlFormatter;
 use xml_formatter::XmlFormatter;
 
-pub trait HtmlFormatter: Debug {
-    fn generate(&self, arena: &Arena) -> String;
+pub(crate) fn self_closing_tags(options: &HamlOptions) -> HashMap<String, bool> {
+    options
+        .autoclose
+        .iter()
+        .map(|tag| (tag.to_string(), true))
+        .collect()
 }
 
-pub fn get_formatter(format: &Format) -> Box<dyn HtmlFormatter> {
-    match format {
-        Format::Html4() => Box::new(Html4Formatter::new()),
-        Format::Html5() => Box::new(Html5Formatter::new()),
-        Format::XHtml() => Box::new(XHtmlFormatter::new()),
-        Format::Xml() => Box::new(XmlFormatter::new()),
+/// The element a `:css` or `:javascript` filter wraps its body in.
+pub(crate) struct Embedded {
+    pub tag: &'static str,
+    pub mime_type: &'static str,
+    /// The comments opening and closing a CDATA section in the language of
+    /// the body.
+    pub cdata: (&'static str, &'static str),
+}
+
+pub(crate) const STYLE: Embedded = Embedded {
+    tag: "style",
+    mime_type: "text/css",
+    cdata: ("/*<![CDATA[*/", "/*]]>*/"),
+};
+
+pub(crate) const SCRIPT: Embedded = Embedded {
+    tag: "script",
+    mime_type: "text/javascript",
+    cdata: ("//<![CDATA[", "//]]>"),
+};
+
+/// Wraps `body` in an embedded element, with a `type` attribute quoted by
+/// `wrapper` when one is given and a CDATA section when `cdata` is set.
+/// The body is indented one level further than the tags, and one more
+/// inside a CDATA section. Blank lines stay empty and trailing whitespace
+/// is removed.
+pub(crate) fn embed(embedded: &Embedded, wrapper: Option<char>, cdata: bool, body: &str) -> String {
+    let mut html = format!("<{}", embedded.tag);
+    if let Some(w) = wrapper {
+        let _ = write!(html, " type={}{}{}", w, embedded.mime_type, w);
+    }
+    html.push_str(">\n");
+    let indent = match cdata {
+        true => "    ",
+        false => "  ",
+    };
+    if cdata {
+        let _ = writeln!(html, "  {}", embedded.cdata.0);
+    }
+    for (index, line) in body.trim_end().lines().enumerate() {
+        if index > 0 {
+            html.push('\n');
+        }
+        if !line.trim().is_empty() {
+            html.push_str(indent);
+            html.push_str(line);
+        }
+    }
+    html.push('\n');
+    if cdata {
+        let _ = writeln!(html, "  {}", embedded.cdata.1);
+    }
+    let _ = write!(html, "</{}>", embedded.tag);
+    html
+}
+
+pub(crate) fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    // writing to a String never fails
+    let _ = write_escaped(&mut escaped, value);
+    escaped
+}
+
+/// Writes `value` with the characters that are special in HTML escaped.
+pub(crate) fn write_escaped(out: &mut dyn Write, value: &str) -> fmt::Result {
+    let mut rest = value;
+    while let Some(i) = rest.find(['&', '<', '>', '\'', '"']) {
+        out.write_str(&rest[..i])?;
+        out.write_str(match &rest[i..=i] {
+            "&" => "&amp;",
+            "<" => "&lt;",
+            ">" => "&gt;",
+            "'" => "&#39;",
+            _ => "&quot;",
+        })?;
+        rest = &rest[i + 1..];
+    }
+    out.write_str(rest)
+}
+
+/// Writes an attribute value wrapped in `wrapper`, escaping it when `escape`
+/// is set and otherwise only escaping the wrapper.
+pub(crate) fn write_attribute_value(
+    out: &mut dyn Write,
+    value: &str,
+    wrapper: char,
+    escape: bool,
+) -> fmt::Result {
+    out.write_char(wrapper)?;
+    match escape {
+        true => write_escaped(out, value)?,
+        false => {
+            out.write_str(&value.replace(wrapper, if wrapper == '"' { "&quot;" } else { "&#39;" }))?
+        }
+    }
+    out.write_char(wrapper)
+}
+
+/// How an attribute is written once its value is known.
+#[derive(Debug, PartialEq)]
+pub(crate) enum AttributeForm {
+    /// Left out, for `nil`, `false` or an array without any value.
+    Omitted(),
+    /// A boolean attribute, for `true`.
+    Boolean(),
+    /// An attribute with a value.
+    Text(String),
+}
+
+impl AttributeForm {
+    pub(crate) fn of(key: &str, value: &Value) -> AttributeForm {
+        match value {
+            Value::Null | Value::Bool(false) => AttributeForm::Omitted(),
+            Value::Bool(true) => AttributeForm::Boolean(),
+            Value::Array(_) => {
+                let mut words = vec![];
+                flatten(value, &mut words);
+                let text = match key {
+                    // each class is written once, in the order given
+                    "class" => {
+                        let mut classes: Vec<&str> = vec![];
+                        for class in words.iter().flat_map(|word| word.split_whitespace()) {
+                            if !classes.contains(&class) {
+                                classes.push(class);
+                            }
+                        }
+                        classes.join(" ")
+                    }
+                    // `#a{id: 'b'}` is `id='a_b'`
+                    "id" => words.join("_"),
+                    _ => words.join(" "),
+                };
+                match text.is_empty() {
+                    true => AttributeForm::Omitted(),
+                    false => AttributeForm::Text(text),
+                }
+            }
+            value => AttributeForm::Text(expr::to_text(value).into_owned()),
+        }
+    }
+}
+
+/// The text of every item of a possibly nested array, leaving out `nil`,
+/// `false` and empty strings.
+fn flatten(value: &Value, words: &mut Vec<String>) {
+    match value {
+        Value::Array(items) => items.iter().for_each(|item| flatten(item, words)),
+        Value::Null | Value::Bool(false) => (),
+        value => {
+            let text = expr::to_text(value);
+            if !text.is_empty() {
+                words.push(text.into_owned());
+            }
+        }
+    }
+}
+
+/// The value of an attribute with the variables of `scope`. An attribute
+/// given several values, such as a class from both the shorthand and a
+/// hash, has them merged.
+pub(crate) fn attribute_value(key: &str, values: &[AttributeValue], scope: &Scope) -> Value {
+    match values {
+        [value] => value.evaluate(scope),
+        values => merge_values(
+            key,
+            values.iter().map(|value| value.evaluate(scope)).collect(),
+        ),
+    }
+}
+
+/// Merges the values of an attribute given by more than one of the
+/// shorthand and the attribute lists. The classes of all of them are
+/// written once each in sorted order, while anything else, such as the ids
+/// later joined with `_`, stays an array.
+pub fn merge_values(key: &str, values: Vec<Value>) -> Value {
+    let value = Value::Array(values);
+    if key != "class" {
+        return value;
+    }
+    let mut words = vec![];
+    flatten(&value, &mut words);
+    let mut classes: Vec<&str> = words
+        .iter()
+        .flat_map(|word| word.split_whitespace())
+        .collect();
+    classes.sort_unstable();
+    classes.dedup();
+    match classes.is_empty() {
+        true => Value::Null,
+        false => Value::String(classes.join(" ")),
+    }
+}
+
+/// Translates the `\n` written by the formatter into another line ending as
+/// the output is written.
+pub struct NewlineWriter<'a> {
+    out: &'a mut dyn Write,
+    newline: &'a str,
+}
+
+impl<'a> NewlineWriter<'a> {
+    pub fn new(out: &'a mut dyn Write, newline: &'a str) -> NewlineWriter<'a> {
+        NewlineWriter { out, newline }
+    }
+}
+
+impl Write for NewlineWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.out.write_str(first)?;
+        }
+        for line in lines {
+            self.out.write_str(self.newline)?;
+            self.out.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Adapts an [`io::Write`] so the formatter can write into it, keeping the
+/// io error that [`fmt::Error`] has no room for.
+struct IoWriter<'a, W: io::Write> {
+    out: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> Write for IoWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+/// Renders into an [`io::Write`] through `render`, returning the io error
+/// that stopped it if there was one.
+pub(crate) fn write_io<W: io::Write>(
+    out: &mut W,
+    render: impl FnOnce(&mut dyn Write) -> fmt::Result,
+) -> io::Result<()> {
+    let mut writer = IoWriter { out, error: None };
+    match render(&mut writer) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(writer
+            .error
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, "formatter error"))),
+    }
+}
+
+/// Whether an item asked for the whitespace around it to be removed.
+pub(crate) fn is_tight(item: &ArenaItem) -> bool {
+    match &item.value {
+        Haml::Element(el) => el.whitespace_removal_outside,
+        _ => false,
+    }
+}
+
+/// The block variables of every iteration of a loop over `collection`.
+/// Anything but an array or a hash has no items.
+pub fn loop_locals<S: AsRef<str>>(
+    collection: Value,
+    variables: &[S],
+    with_index: bool,
+) -> Vec<Vec<(String, Value)>> {
+    let items: Vec<Vec<Value>> = match collection {
+        Value::Array(items) => items.into_iter().map(|item| vec![item]).collect(),
+        Value::Object(map) => map
+            .into_iter()
+            .map(|(key, value)| vec![Value::String(key), value])
+            .collect(),
+        _ => vec![],
+    };
+    let mut iterations = vec![];
+    for (index, mut values) in items.into_iter().enumerate() {
+        let mut names = variables.iter().map(AsRef::as_ref);
+        let mut locals = vec![];
+        if with_index {
+            if let Some(name) = names.next_back() {
+                locals.push((name.to_string(), Value::from(index)));
+            }
+        }
+        // a single variable takes a hash entry as a [key, value] array,
+        // several variables take the elements of an array item
+        match (values.len(), names.len()) {
+            (2, 1) => values = vec![Value::Array(values)],
+            (1, n) if n > 1 && values[0].is_array() => {
+                if let Some(Value::Array(elements)) = values.pop() {
+                    values = elements;
+                }
+            }
+            _ => (),
+        }
+        let mut values = values.into_iter();
+        for name in names {
+            locals.push((name.to_string(), values.next().unwrap_or(Value::Null)));
+        }
+        iterations.push(locals);
+    }
+    iterations
+}
+
+/// Keeps track of the items written at one level of the document, which
+/// are joined by newlines unless one of them removes the whitespace around
+/// it.
+pub struct Siblings {
+    // whether a newline goes before the first item, as after an opening tag
+    leading_newline: bool,
+    // whether the last item written removed the whitespace around it, or
+    // None when nothing has been written yet
+    previous_tight: Option<bool>,
+}
+
+impl Siblings {
+    pub fn new(leading_newline: bool) -> Siblings {
+        Siblings {
+            leading_newline,
+            previous_tight: None,
+        }
+    }
+
+    /// Writes whatever separates the next item from the previous one.
+    pub fn separate(&mut self, tight: bool, out: &mut dyn Write) -> fmt::Result {
+        let newline = match self.previous_tight {
+            None => self.leading_newline,
+            Some(previous) => !previous,
+        };
+        self.previous_tight = Some(tight);
+        match newline && !tight {
+            true => out.write_char('\n'),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether a newline goes after the last item, as before a closing tag.
+    pub fn trailing_newline(&self) -> bool {
+        self.previous_tight == Some(false)
+    }
+}
+
+/// Renders an arena by writing into a [`fmt::Write`]. Every method streams
+/// its output, so no string holding the whole document is ever built.
+pub trait HtmlFormatter: Debug + Send + Sync {
+    fn options(&self) -> &HamlOptions;
+
+    /// The doctype or XML prolog for a `!!!` line. An empty string means
+    /// that the declaration is silent in this format.
+    fn prolog_to_html(&self, value: &Option<String>) -> String;
+
+    fn is_self_closing(&self, name: &str) -> bool;
+
+    /// The end of a tag that has no closing tag, e.g. `>` or ` />`.
+    fn self_closing_end(&self) -> &str;
+
+    fn boolean_attribute_to_html(&self, key: &str, out: &mut dyn Write) -> fmt::Result;
+
+    /// The HTML of a `:css` or `:javascript` filter, whose body is wrapped
+    /// in the `embedded` element.
+    fn embedded_to_html(&self, embedded: &Embedded, body: &str) -> String;
+
+    /// The HTML of the item at `index` when it was written ahead of time
+    /// because it is the same whatever the context.
+    fn static_html(&self, _index: usize) -> Option<&str> {
+        None
+    }
+
+    /// The compiled partial called `name`. A [`crate::Document`] has none,
+    /// so its `= render` lines write nothing.
+    fn partial(&self, _name: &str) -> Option<&Template> {
+        None
+    }
+
+    fn generate(&self, arena: &Arena, context: &Context) -> String {
+        let mut html = String::new();
+        // writing to a String never fails
+        let _ = self.render(arena, context, &mut html);
+        html
+    }
+
+    /// Writes the whole document into `out` using the configured newline.
+    fn render(&self, arena: &Arena, context: &Context, out: &mut dyn Write) -> fmt::Result {
+        self.render_scope(arena, &Scope::new(context), out)
+    }
+
+    /// Writes the whole document with the variables of `scope`.
+    fn render_scope(&self, arena: &Arena, scope: &Scope, out: &mut dyn Write) -> fmt::Result {
+        let mut siblings = Siblings::new(false);
+        match self.options().newline {
+            Newline::Lf() => self.children_to_html(arena.root(), arena, scope, &mut siblings, out),
+            newline => {
+                let mut out = NewlineWriter {
+                    out,
+                    newline: newline.as_str(),
+                };
+                self.children_to_html(arena.root(), arena, scope, &mut siblings, &mut out)
+            }
+        }
+    }
+
+    /// Whether an item writes anything at all. Silent items take no part in
+    /// joining their siblings with newlines.
+    fn renders(&self, item: &ArenaItem) -> bool {
+        match &item.value {
+            Haml::Doctype(value) => !self.prolog_to_html(value).is_empty(),
+            Haml::SilentComment(_) | Haml::Root() => false,
+            _ => true,
+        }
+    }
+
+    fn item_to_html(
+        &self,
+        index: usize,
+        item: &ArenaItem,
+        arena: &Arena,
+        scope: &Scope,
+        siblings: &mut Siblings,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        if !self.renders(item) {
+            return Ok(());
+        }
+        siblings.separate(is_tight(item), out)?;
+        if let Some(html) = self.static_html(index) {
+            return out.write_str(html);
+        }
+        match &item.value {
+            Haml::Element(el) => self.element_to_html(el, item, arena, scope, out),
+            Haml::Text(text) => self.text_to_html(text, scope, out),
+            Haml::Comment(line) => self.comment_to_html(line, item, arena, scope, out),
+            Haml::ConditionalComment(_, value) => {
+                self.conditional_comment_to_html(value, item, arena, scope, out)
+            }
+            Haml::Doctype(value) => out.write_str(&self.prolog_to_html(value)),
+            Haml::Filter(filter) => self.filter_to_html(filter, item, scope, out),
+            Haml::Script(script) => self.script_to_html(script, scope, out),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the children of `item`. The children of the branches of an
+    /// `if` that are taken are written as if they were children of `item`.
+    fn children_to_html(
+        &self,
+        item: &ArenaItem,
+        arena: &Arena,
+        scope: &Scope,
+        siblings: &mut Siblings,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        // whether a branch of the current if, elsif and else chain was taken
+        let mut taken = false;
+        for index in &item.children {
+            let child = arena.item(*index);
+            let render = match &child.value {
+                Haml::If(code) => {
+                    taken = self.condition(code, scope);
+                    taken
+                }
+                Haml::Unless(code) => {
+                    taken = !self.condition(code, scope);
+                    taken
+                }
+                Haml::ElsIf(code) if !taken => {
+                    taken = self.condition(code, scope);
+                    taken
+                }
+                Haml::ElsIf(_) => false,
+                Haml::Else() => !taken,
+                Haml::Each(each) => {
+                    self.each_to_html(each, child, arena, scope, siblings, out)?;
+                    continue;
+                }
+                Haml::Partial(partial) => {
+                    self.partial_to_html(partial, scope, siblings, out)?;
+                    continue;
+                }
+                Haml::Yield(name) => {
+                    if let Some(page) = scope.page() {
+                        let context = scope.context();
+                        page.content_to_html(name.as_deref(), &Scope::new(context), siblings, out)?;
+                    }
+                    continue;
+                }
+                Haml::ContentFor(_) => continue,
+                _ => {
+                    self.item_to_html(*index, child, arena, scope, siblings, out)?;
+                    continue;
+                }
+            };
+            if render {
+                self.children_to_html(child, arena, scope, siblings, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the children of a loop once per item, with the block variables
+    /// set to the item.
+    fn each_to_html(
+        &self,
+        each: &Each,
+        item: &ArenaItem,
+        arena: &Arena,
+        scope: &Scope,
+        siblings: &mut Siblings,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        let collection = each.code.evaluate(scope).into_owned();
+        for locals in loop_locals(collection, &each.variables, each.with_index) {
+            let scope = scope.child(locals);
+            self.children_to_html(item, arena, &scope, siblings, out)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a partial with the context of `scope` and its own locals. The
+    /// block variables around the `= render` line are not visible to it.
+    fn partial_to_html(
+        &self,
+        partial: &Partial,
+        scope: &Scope,
+        siblings: &mut Siblings,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        let template = match self.partial(&partial.name) {
+            Some(template) => template,
+            None => return Ok(()),
+        };
+        let locals = match partial.locals.as_ref().map(|code| code.evaluate(scope)) {
+            Some(locals) => match locals.into_owned() {
+                Value::Object(locals) => locals.into_iter().collect(),
+                _ => vec![],
+            },
+            None => vec![],
+        };
+        let result = template.content_to_html(None, &scope.partial(locals), siblings, out);
+        if result.is_err() {
+            scope.failed_in(&partial.name);
+        }
+        result
+    }
+
+    /// Whether the code of an `if` or `unless` is truthy.
+    fn condition(&self, code: &Code, scope: &Scope) -> bool {
+        expr::truthy(&code.evaluate(scope))
+    }
+
+    /// Writes text with its `#{}` interpolation done.
+    fn text_to_html(&self, text: &Interpolated, scope: &Scope, out: &mut dyn Write) -> fmt::Result {
+        text.write(scope, self.options().escape_html, out)
+    }
+
+    /// Writes what a filter renders from its body with the `#{}`
+    /// interpolation done. A filter that fails writes nothing.
+    fn filter_to_html(
+        &self,
+        filter: &Filter,
+        item: &ArenaItem,
+        scope: &Scope,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        let options = self.options();
+        let mut body = String::new();
+        filter.body.write(scope, options.escape_html, &mut body)?;
+        match filters::render(&filter.name, &body, options) {
+            Ok(html) => out.write_str(&html),
+            Err(reason) => scope.fail(filters::failed(&filter.name, item.span, reason)),
+        }
+    }
+
+    fn attributes_to_html(&self, el: &Element, scope: &Scope, out: &mut dyn Write) -> fmt::Result {
+        for key in el.attributes() {
+            if let Some(values) = el.attributes.get(key) {
+                self.attribute_to_html(key, &attribute_value(key, values, scope), out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes an attribute whose value is known. `nil` and `false` leave it
+    /// out and `true` makes it a boolean attribute.
+    fn attribute_to_html(&self, key: &str, value: &Value, out: &mut dyn Write) -> fmt::Result {
+        match AttributeForm::of(key, value) {
+            AttributeForm::Omitted() => Ok(()),
+            AttributeForm::Boolean() => self.boolean_attribute_to_html(key, out),
+            AttributeForm::Text(text) => {
+                write!(out, " {}=", key)?;
+                self.attribute_value(&text, out)
+            }
+        }
+    }
+
+    /// Escapes an attribute value and wraps it in the configured quotes.
+    fn attribute_value(&self, value: &str, out: &mut dyn Write) -> fmt::Result {
+        let options = self.options();
+        write_attribute_value(out, value, options.attr_wrapper, options.escape_attrs)
+    }
+
+    fn element_to_html(
+        &self,
+        el: &Element,
+        item: &ArenaItem,
+        arena: &Arena,
+        scope: &Scope,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        let name = el.name().unwrap_or_default();
+        write!(out, "<{}", name)?;
+        self.attributes_to_html(el, scope, out)?;
+        if (el.self_close || self.is_self_closing(&name))
+            && el.inline_text.is_none()
+            && el.inline_script.is_none()
+            && item.children.is_empty()
+        {
+            return out.write_str(self.self_closing_end());
+        }
+        out.write_char('>')?;
+        if let Some(text) = &el.inline_text {
+            self.text_to_html(text, scope, out)?;
+        } else if let Some(script) = &el.inline_script {
+            self.script_to_html(script, scope, out)?;
+        } else {
+            let preserve = el.whitespace_removal_inside || el.preserve;
+            let mut children = Siblings::new(!preserve);
+            self.children_to_html(item, arena, scope, &mut children, out)?;
+            if !preserve && children.trailing_newline() {
+                out.write_char('\n')?;
+            }
+        }
+        write!(out, "</{}>", name)
+    }
+
+    fn script_to_html(&self, script: &Script, scope: &Scope, out: &mut dyn Write) -> fmt::Result {
+        let value = script.code.evaluate(scope);
+        let value = expr::to_text(&value);
+        match script.escape.unwrap_or(self.options().escape_html) {
+            true => write_escaped(out, &value),
+            false => out.write_str(&value),
+        }
+    }
+
+    fn comment_to_html(
+        &self,
+        line: &str,
+        item: &ArenaItem,
+        arena: &Arena,
+        scope: &Scope,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        if item.children.is_empty() {
+            write!(out, "<!-- {} -->", line)
+        } else {
+            out.write_str("<!--\n")?;
+            self.children_to_html(item, arena, scope, &mut Siblings::new(false), out)?;
+            out.write_str("\n-->")
+        }
+    }
+
+    fn conditional_comment_to_html(
+        &self,
+        value: &str,
+        item: &ArenaItem,
+        arena: &Arena,
+        scope: &Scope,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        writeln!(out, "<!--[{}]>", value)?;
+        self.children_to_html(item, arena, scope, &mut Siblings::new(false), out)?;
+        out.write_str("\n<![endif]-->")
+    }
+}
+
+/// Whether an item renders the same whatever the context, given that its
+/// children do.
+fn is_static(item: &ArenaItem) -> bool {
+    match &item.value {
+        Haml::Element(el) => {
+            el.inline_script.is_none()
+                && el.inline_text.as_ref().map_or(true, |text| text.is_static())
+                && el
+                    .attributes
+                    .values()
+                    .flatten()
+                    .all(AttributeValue::is_static)
+        }
+        Haml::Text(text) => text.is_static(),
+        Haml::Filter(filter) => filter.body.is_static(),
+        Haml::Comment(_)
+        | Haml::ConditionalComment(..)
+        | Haml::Doctype(_)
+        | Haml::SilentComment(_) => true,
+        _ => false,
+    }
+}
+
+/// A formatter along with the HTML of every part of a document that is the
+/// same whatever the context, written once up front so that rendering only
+/// has to evaluate the code.
+#[derive(Debug)]
+pub(crate) struct Precomputed {
+    formatter: Box<dyn HtmlFormatter>,
+    // the HTML of the outermost static items, by index in the arena
+    statics: Vec<Option<String>>,
+    partials: HashMap<String, Arc<Template>>,
+}
+
+impl Precomputed {
+    /// Writes the static parts of the document, failing when one of their
+    /// filters does.
+    pub fn new(
+        formatter: Box<dyn HtmlFormatter>,
+        arena: &Arena,
+        partials: HashMap<String, Arc<Template>>,
+    ) -> Result<Precomputed> {
+        // children always come after their parent in the arena
+        let mut fixed = vec![true; arena.len()];
+        for index in (1..arena.len()).rev() {
+            let item = arena.item(index);
+            fixed[index] = is_static(item) && item.children.iter().all(|child| fixed[*child]);
+        }
+        let context = Context::new();
+        let failure = RefCell::new(None);
+        let scope = Scope::new(&context).failing_into(&failure);
+        let mut statics = vec![None; arena.len()];
+        for (index, html) in statics.iter_mut().enumerate() {
+            let item = arena.item(index);
+            if index == 0 || !fixed[index] || (item.parent != 0 && fixed[item.parent]) {
+                continue;
+            }
+            let mut static_html = String::new();
+            let mut siblings = Siblings::new(false);
+            // writing to a String never fails, so an error is a failing
+            // filter
+            let written =
+                formatter.item_to_html(index, item, arena, &scope, &mut siblings, &mut static_html);
+            if written.is_err() {
+                if let Some(error) = failure.take() {
+                    return Err(error);
+                }
+            }
+            *html = Some(static_html);
+        }
+        Ok(Precomputed {
+            formatter,
+            statics,
+            partials,
+        })
+    }
+}
+
+impl HtmlFormatter for Precomputed {
+    fn options(&self) -> &HamlOptions {
+        self.formatter.options()
+    }
+
+    fn prolog_to_html(&self, value: &Option<String>) -> String {
+        self.formatter.prolog_to_html(value)
+    }
+
+    fn is_self_closing(&self, name: &str) -> bool {
+        self.formatter.is_self_closing(name)
+    }
+
+    fn self_closing_end(&self) -> &str {
+        self.formatter.self_closing_end()
+    }
+
+    fn boolean_attribute_to_html(&self, key: &str, out: &mut dyn Write) -> fmt::Result {
+        self.formatter.boolean_attribute_to_html(key, out)
+    }
+
+    fn embedded_to_html(&self, embedded: &Embedded, body: &str) -> String {
+        self.formatter.embedded_to_html(embedded, body)
+    }
+
+    fn static_html(&self, index: usize) -> Option<&str> {
+        self.statics.get(index).and_then(|html| html.as_deref())
+    }
+
+    fn partial(&self, name: &str) -> Option<&Template> {
+        self.partials.get(name).map(Arc::as_ref)
+    }
+}
+
+pub fn get_formatter(options: &HamlOptions) -> Box<dyn HtmlFormatter> {
+    match options.effective_format() {
+        Format::Html4() => Box::new(Html4Formatter::new(options)),
+        Format::Html5() => Box::new(Html5Formatter::new(options)),
+        Format::XHtml() => Box::new(XHtmlFormatter::new(options)),
+        Format::Xml() => Box::new(XmlFormatter::new(options)),
     }
 }
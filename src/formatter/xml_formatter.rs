@@ -1,18 +1,47 @@
-use crate::arena::{Arena, ArenaItem};
-use crate::formatter::HtmlFormatter;
-use crate::parser::Haml;
+use crate::formatter::xhtml_formatter::xhtml_prolog;
+use crate::formatter::{embed, self_closing_tags, Embedded, HtmlFormatter};
+use crate::options::HamlOptions;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
 
 #[derive(Debug)]
-pub struct XmlFormatter;
+pub struct XmlFormatter {
+    options: HamlOptions,
+    self_closing_tags: HashMap<String, bool>,
+}
 
 impl XmlFormatter {
-    pub fn new() -> XmlFormatter {
-        XmlFormatter {}
+    pub fn new(options: &HamlOptions) -> XmlFormatter {
+        XmlFormatter {
+            options: options.clone(),
+            self_closing_tags: self_closing_tags(options),
+        }
     }
 }
 
 impl HtmlFormatter for XmlFormatter {
-    fn generate(&self, arena: &Arena) -> String {
-        String::new()
+    fn options(&self) -> &HamlOptions {
+        &self.options
+    }
+
+    fn prolog_to_html(&self, value: &Option<String>) -> String {
+        xhtml_prolog(value, self.options.attr_wrapper)
+    }
+
+    fn is_self_closing(&self, name: &str) -> bool {
+        self.self_closing_tags.contains_key(name)
+    }
+
+    fn self_closing_end(&self) -> &str {
+        " />"
+    }
+
+    fn boolean_attribute_to_html(&self, key: &str, out: &mut dyn Write) -> fmt::Result {
+        write!(out, " {}=", key)?;
+        self.attribute_value(key, out)
+    }
+
+    fn embedded_to_html(&self, embedded: &Embedded, body: &str) -> String {
+        embed(embedded, Some(self.options.attr_wrapper), true, body)
     }
 }
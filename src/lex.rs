@@ -1,149 +1,62 @@
+use crate::span::Span;
 use crate::Token;
 
-pub(crate) fn lex(haml: &str) -> Vec<Token> {
+pub(crate) fn lex(haml: &str) -> Vec<(Token, Span)> {
     let mut tokens = vec![];
     let mut buffer = String::new();
-    for ch in haml.chars() {
-        match ch {
-            ' ' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::Whitespace());
-            },
-            '(' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::OpenParen());
-            },
-            ')' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::CloseParen());
-            },
-            '{' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::OpenBrace());
-            },
-            '}' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::CloseBrace());
-            },
-            '%' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::PercentageSign());
-            },
-            '.' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::Period());
-            },
-            '=' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::Equal());
-            },
-            '\"' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::SingleQuote());
-            },
-            '\'' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::DoubleQuote());
-            },
-            '\\' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::BackSlash());
-            },
-            '/' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::ForwardSlash());
-            },
-            '#' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::Hashtag());
-            },
-            '<' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::LessThan());
-            },
-            '>' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::GreaterThan());
-            },
-            '!' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::Exclamation());
-            },
-            '&' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::Ampersand());
-            },
-            '~' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::Tilde());
-            },
-            '\n' => {
-                if !buffer.is_empty() {
-                    tokens.push(Token::Text(buffer.to_string()));
-                    buffer.clear();
-                }
-                tokens.push(Token::Newline());
-            },
-            c => buffer.push(c),
+    // span of the text currently in the buffer
+    let mut buffer_span = Span::default();
+    let mut line = 1;
+    let mut column = 1;
+    for (offset, ch) in haml.char_indices() {
+        let span = Span::new(offset, offset + ch.len_utf8(), line, column);
+        if ch == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
         }
-
+        let token = match ch {
+            ' ' => Token::Whitespace(),
+            '\t' => Token::Tab(),
+            '(' => Token::OpenParen(),
+            ')' => Token::CloseParen(),
+            '{' => Token::OpenBrace(),
+            '}' => Token::CloseBrace(),
+            '%' => Token::PercentageSign(),
+            '.' => Token::Period(),
+            '=' => Token::Equal(),
+            '\'' => Token::SingleQuote(),
+            '\"' => Token::DoubleQuote(),
+            '\\' => Token::BackSlash(),
+            '/' => Token::ForwardSlash(),
+            '#' => Token::Hashtag(),
+            '<' => Token::LessThan(),
+            '>' => Token::GreaterThan(),
+            '!' => Token::Exclamation(),
+            '&' => Token::Ampersand(),
+            '~' => Token::Tilde(),
+            '-' => Token::Minus(),
+            '\n' => Token::Newline(),
+            // carriage returns from Windows line endings carry no meaning
+            '\r' => continue,
+            c => {
+                if buffer.is_empty() {
+                    buffer_span = span;
+                }
+                buffer.push(c);
+                buffer_span.end = span.end;
+                continue;
+            }
+        };
+        if !buffer.is_empty() {
+            tokens.push((Token::Text(buffer.to_string()), buffer_span));
+            buffer.clear();
+        }
+        tokens.push((token, span));
     }
     if !buffer.is_empty() {
-        tokens.push(Token::Text(buffer.to_string()));
+        tokens.push((Token::Text(buffer.to_string()), buffer_span));
     }
     tokens
 }
@@ -156,9 +69,43 @@ mod test {
     fn t() {
         let haml = "%test";
         let tokens = lex(haml);
-        let mut it = tokens.iter();
+        let mut it = tokens.iter().map(|(token, _)| token);
         assert_eq!(Some(&Token::PercentageSign()), it.next());
         assert_eq!(Some(&Token::Text("test".to_string())), it.next());
         assert_eq!(None, it.next());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn quotes() {
+        let tokens = lex("'a\"\r\n");
+        let mut it = tokens.iter().map(|(token, _)| token);
+        assert_eq!(Some(&Token::SingleQuote()), it.next());
+        assert_eq!(Some(&Token::Text("a".to_string())), it.next());
+        assert_eq!(Some(&Token::DoubleQuote()), it.next());
+        assert_eq!(Some(&Token::Newline()), it.next());
+        assert_eq!(None, it.next());
+    }
+
+    #[test]
+    fn minus() {
+        let tokens = lex("- a-b");
+        let mut it = tokens.iter().map(|(token, _)| token);
+        assert_eq!(Some(&Token::Minus()), it.next());
+        assert_eq!(Some(&Token::Whitespace()), it.next());
+        assert_eq!(Some(&Token::Text("a".to_string())), it.next());
+        assert_eq!(Some(&Token::Minus()), it.next());
+        assert_eq!(Some(&Token::Text("b".to_string())), it.next());
+        assert_eq!(None, it.next());
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = lex("%p\n  héllo wörld");
+        let spans: Vec<Span> = tokens.iter().map(|(_, span)| *span).collect();
+        assert_eq!(Span::new(0, 1, 1, 1), spans[0]);
+        assert_eq!(Span::new(1, 2, 1, 2), spans[1]);
+        assert_eq!(Span::new(2, 3, 1, 3), spans[2]);
+        assert_eq!(Span::new(5, 11, 2, 3), spans[5]);
+        assert_eq!(Span::new(12, 18, 2, 9), spans[7]);
+    }
+}
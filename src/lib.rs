@@ -1,16 +1,42 @@
 #![allow(dead_code)]
-// mod arena;
-// mod formatter;
-// mod parser;
-// mod regex;
-// use formatter::HtmlFormatter;
-// use parser::Parser;
+mod arena;
+pub mod build;
+#[doc(hidden)]
+pub mod codegen;
+mod context;
+mod error;
+mod expr;
+pub mod filters;
+mod formatter;
 mod lex;
+mod loader;
+mod options;
 mod parse;
+mod parser;
+mod registry;
+#[doc(hidden)]
+pub mod runtime;
+mod span;
+mod template;
 
-use std::collections::{BTreeSet, HashMap};
-
-#[derive(Debug)]
+use std::fmt;
+use std::io;
+
+use serde::Serialize;
+
+pub use arena::{Arena, ArenaItem};
+pub use context::Context;
+pub use error::{Error, ErrorKind, Result};
+pub use loader::Loader;
+pub use options::{HamlOptions, Newline};
+pub use parser::element::{AttributeValue, Element, ElementType};
+pub use parser::{Code, Each, Filter, Haml, Interpolated, Partial, Script};
+pub use registry::TemplateRegistry;
+pub use runtime::HamlTemplate;
+pub use span::Span;
+pub use template::Template;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Format {
     Html4(),
     Html5(),
@@ -18,33 +44,150 @@ pub enum Format {
     XHtml(),
 }
 
+/// Renders a Haml template to HTML.
+///
+/// # Panics
+///
+/// Panics if the template is invalid. Use [`try_to_html`] when the template
+/// comes from an untrusted source.
 pub fn to_html(haml: &str, format: &Format) -> String {
-    // let mut parser = Parser::new(format);
-    // let ast = parser.parse(haml);
-    // let generator = formatter::get_formatter(format);
-    // generator.generate(&ast)
-    String::new()
+    match try_to_html(haml, format) {
+        Ok(html) => html,
+        Err(err) => panic!("{}", err),
+    }
 }
 
-use std::fmt;
+/// Renders a Haml template to HTML, returning an [`Error`] describing the
+/// first problem found in an invalid template.
+pub fn try_to_html(haml: &str, format: &Format) -> Result<String> {
+    to_html_with_options(haml, &HamlOptions::new().format(*format))
+}
+
+/// Renders a Haml template to HTML with every option of [`HamlOptions`].
+pub fn to_html_with_options(haml: &str, options: &HamlOptions) -> Result<String> {
+    Template::compile(haml, options)?.try_render(&Context::new())
+}
+
+/// Renders a Haml template with the variables of `context`, which may be
+/// anything that serializes to a map. `= name`, `%p= user.name` and
+/// `#{name}` are replaced by the values they refer to.
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// let mut context = HashMap::new();
+/// context.insert("name", "world");
+/// let html = haml::render("%p= name", &context).unwrap();
+/// assert_eq!("<p>world</p>", html);
+/// ```
+pub fn render<T: Serialize + ?Sized>(haml: &str, context: &T) -> Result<String> {
+    render_with_options(haml, context, &HamlOptions::new())
+}
+
+/// Renders a Haml template with the variables of `context` and every option
+/// of [`HamlOptions`].
+pub fn render_with_options<T: Serialize + ?Sized>(
+    haml: &str,
+    context: &T,
+    options: &HamlOptions,
+) -> Result<String> {
+    let context = Context::from_serialize(context)?;
+    Template::compile(haml, options)?.try_render(&context)
+}
+
+/// Parses a Haml template into a [`Document`] without rendering it.
+pub fn parse(haml: &str) -> Result<Document> {
+    parse_with_options(haml, &HamlOptions::new())
+}
+
+/// Parses a Haml template into a [`Document`]. The `preserve` and
+/// `remove_whitespace` options are applied while parsing, every other option
+/// when the document is rendered.
+pub fn parse_with_options(haml: &str, options: &HamlOptions) -> Result<Document> {
+    let tokens = lex::lex(haml);
+    let arena = parse::parse(&tokens, options)?;
+    Ok(Document {
+        arena,
+        options: options.clone(),
+    })
+}
+
+/// A parsed Haml template. Its `= render` lines write nothing, as the
+/// partials they refer to are only looked up when a [`Template`] is
+/// compiled.
+#[derive(Debug)]
+pub struct Document {
+    arena: Arena,
+    options: HamlOptions,
+}
+
+impl Document {
+    /// The arena holding every node of the document.
+    pub fn arena(&self) -> &Arena {
+        &self.arena
+    }
+
+    /// The [`Haml::Root`] item whose children are the top level lines.
+    pub fn root(&self) -> &ArenaItem {
+        self.arena.root()
+    }
+
+    /// The options the document was parsed with.
+    pub fn options(&self) -> &HamlOptions {
+        &self.options
+    }
+
+    /// Renders the document with the options it was parsed with and no
+    /// variables.
+    pub fn html(&self) -> String {
+        self.render(&Context::new())
+    }
+
+    /// Renders the document with the variables of `context`.
+    pub fn render(&self, context: &Context) -> String {
+        let generator = formatter::get_formatter(&self.options);
+        generator.generate(&self.arena, context)
+    }
+
+    /// Renders the document with the options it was parsed with, except for
+    /// the format.
+    pub fn to_html(&self, format: &Format) -> String {
+        let generator = formatter::get_formatter(&self.options.clone().format(*format));
+        generator.generate(&self.arena, &Context::new())
+    }
+
+    /// Renders the document with the variables of `context` into `out` as
+    /// it goes, without building the whole document as a string first.
+    pub fn render_to<W: fmt::Write>(&self, context: &Context, out: &mut W) -> fmt::Result {
+        let generator = formatter::get_formatter(&self.options);
+        generator.render(&self.arena, context, out)
+    }
+
+    /// Renders the document into a byte sink such as a file or a response
+    /// body. Wrap unbuffered sinks in a [`io::BufWriter`], as the output is
+    /// written in many small pieces.
+    pub fn write_to<W: io::Write>(&self, context: &Context, out: &mut W) -> io::Result<()> {
+        let generator = formatter::get_formatter(&self.options);
+        formatter::write_io(out, |out| generator.render(&self.arena, context, out))
+    }
+}
 
 impl fmt::Display for Format {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut format = "html5";
-        match self {
-            Format::XHtml() => format = "xhtml",
-            Format::Html4() => format = "html4",
-            Format::Html5() => format = "html5",
-            Format::Xml() => format = "xml",
-        }
+        let format = match self {
+            Format::XHtml() => "xhtml",
+            Format::Html4() => "html4",
+            Format::Html5() => "html5",
+            Format::Xml() => "xml",
+        };
         write!(f, "{}", format)
     }
 }
 
-
 #[derive(Debug, PartialEq)]
 pub(crate) enum Token {
     Whitespace(),
+    Tab(),
     Text(String),
     OpenParen(),
     CloseParen(),
@@ -63,67 +206,36 @@ pub(crate) enum Token {
     Exclamation(),
     Ampersand(),
     Tilde(),
+    Minus(),
     Newline(),
 }
 
-pub trait Haml {}
-
-pub(crate) struct Declaration {
-    value: String,
-}
-
-impl Declaration {
-    pub fn new(value: &str) -> Declaration {
-        Declaration {
-            value: value.to_string(),
-        }
-    }
-}
-
-impl Haml for Declaration {}
-
-pub(crate) struct Element {
-    name: String,
-    attributes: HashMap<String, Vec<String>>,
-    attribute_order: BTreeSet<String>,
-}
-
-impl Element {
-    pub fn new(name: &str) -> Element {
-        Element {
-            name: name.to_string(),
-            attributes: HashMap::new(),
-            attribute_order: BTreeSet::new(),
-        }
-    }
-}
-impl Haml for Element {}
-
-pub(crate) struct Class {
-    pub name: String,
-}
-
-impl Class {
-    pub fn new(name: &str) -> Class {
-        Class {
-            name: name.to_string(),
-        }
-    }
-}
-
-impl Haml for Class {}
-
-
-pub(crate) struct Id {
-    pub name: String,
-}
-
-impl Id {
-    pub fn new(name: &str) -> Id {
-        Id {
-            name: name.to_string(),
-        }
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = match self {
+            Token::Whitespace() => " ",
+            Token::Tab() => "\t",
+            Token::Text(text) => text,
+            Token::OpenParen() => "(",
+            Token::CloseParen() => ")",
+            Token::OpenBrace() => "{",
+            Token::CloseBrace() => "}",
+            Token::PercentageSign() => "%",
+            Token::Period() => ".",
+            Token::Equal() => "=",
+            Token::SingleQuote() => "'",
+            Token::DoubleQuote() => "\"",
+            Token::ForwardSlash() => "/",
+            Token::BackSlash() => "\\",
+            Token::Hashtag() => "#",
+            Token::LessThan() => "<",
+            Token::GreaterThan() => ">",
+            Token::Exclamation() => "!",
+            Token::Ampersand() => "&",
+            Token::Tilde() => "~",
+            Token::Minus() => "-",
+            Token::Newline() => "\n",
+        };
+        write!(f, "{}", value)
     }
 }
-
-impl Haml for Id {}
\ No newline at end of file
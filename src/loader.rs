@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use crate::error::{Error, ErrorKind, Result};
+use crate::span::Span;
+
+/// Where the templates rendered with `= render "name"` come from.
+///
+/// A [`TemplateRegistry`](crate::TemplateRegistry) loads partials from its
+/// directory, and a `HashMap` of names to sources holds them in memory.
+pub trait Loader {
+    /// The Haml source of the partial called `name`.
+    fn load(&self, name: &str) -> Result<String>;
+}
+
+impl<S: BuildHasher> Loader for HashMap<String, String, S> {
+    fn load(&self, name: &str) -> Result<String> {
+        self.get(name).cloned().ok_or_else(|| not_found(name))
+    }
+}
+
+impl<S: BuildHasher> Loader for HashMap<&str, &str, S> {
+    fn load(&self, name: &str) -> Result<String> {
+        self.get(name)
+            .map(|haml| haml.to_string())
+            .ok_or_else(|| not_found(name))
+    }
+}
+
+pub(crate) fn not_found(name: &str) -> Error {
+    Error::new(
+        ErrorKind::TemplateNotFound(name.to_string()),
+        Span::default(),
+        "",
+    )
+}
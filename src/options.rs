@@ -0,0 +1,172 @@
+use crate::filters::{Filter, Filters};
+use crate::Format;
+
+/// The line ending written between lines of output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Newline {
+    Lf(),
+    CrLf(),
+}
+
+impl Newline {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Newline::Lf() => "\n",
+            Newline::CrLf() => "\r\n",
+        }
+    }
+}
+
+/// Options controlling how a template is parsed and rendered. These mirror
+/// the options hash of the Ruby implementation and default to its values.
+///
+/// ```
+/// use haml::{Format, HamlOptions};
+///
+/// let options = HamlOptions::new()
+///     .format(Format::XHtml())
+///     .escape_html(true)
+///     .attr_wrapper('"');
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct HamlOptions {
+    /// The output format. Defaults to HTML5.
+    pub format: Format,
+    /// Whether the result of `=` is escaped. `&=` and `!=` always escape or
+    /// don't regardless. Defaults to false.
+    pub escape_html: bool,
+    /// Whether attribute values are escaped. Defaults to true.
+    pub escape_attrs: bool,
+    /// The quote placed around attribute values. Defaults to `'`.
+    pub attr_wrapper: char,
+    /// Tags that are closed automatically when they have no content.
+    pub autoclose: Vec<String>,
+    /// Tags whose content keeps its whitespace and is never indented.
+    /// Defaults to `textarea`, `pre` and `code`.
+    pub preserve: Vec<String>,
+    /// Whether underscores in the keys of a `data` or `aria` hash become
+    /// hyphens. Defaults to true.
+    pub hyphenate_data_attrs: bool,
+    /// Whether whitespace around and inside every tag is removed, as if each
+    /// had `<>` appended. Defaults to false.
+    pub remove_whitespace: bool,
+    /// The line ending of the output. Defaults to `\n`.
+    pub newline: Newline,
+    /// The mime type of the output. `text/xml` forces the XHTML format when
+    /// an HTML format is selected. Defaults to `text/html`.
+    pub mime_type: String,
+    /// The filters available to `:name` lines. Defaults to
+    /// [`Filters::standard`].
+    pub filters: Filters,
+    /// Whether filters that run a command, such as a
+    /// [`Command`](crate::filters::Command), may be used. Turn this off for
+    /// templates that aren't trusted. Defaults to true.
+    pub allow_commands: bool,
+}
+
+const AUTOCLOSE: &[&str] = &[
+    "area", "base", "basefont", "br", "col", "command", "embed", "frame", "hr", "img", "input",
+    "isindex", "keygen", "link", "menuitem", "meta", "param", "source", "track", "wbr",
+];
+
+const PRESERVE: &[&str] = &["textarea", "pre", "code"];
+
+impl HamlOptions {
+    pub fn new() -> HamlOptions {
+        HamlOptions {
+            format: Format::Html5(),
+            escape_html: false,
+            escape_attrs: true,
+            attr_wrapper: '\'',
+            autoclose: AUTOCLOSE.iter().map(|tag| tag.to_string()).collect(),
+            preserve: PRESERVE.iter().map(|tag| tag.to_string()).collect(),
+            hyphenate_data_attrs: true,
+            remove_whitespace: false,
+            newline: Newline::Lf(),
+            mime_type: "text/html".to_string(),
+            filters: Filters::standard(),
+            allow_commands: true,
+        }
+    }
+
+    pub fn format(mut self, format: Format) -> HamlOptions {
+        self.format = format;
+        self
+    }
+
+    pub fn escape_html(mut self, escape_html: bool) -> HamlOptions {
+        self.escape_html = escape_html;
+        self
+    }
+
+    pub fn escape_attrs(mut self, escape_attrs: bool) -> HamlOptions {
+        self.escape_attrs = escape_attrs;
+        self
+    }
+
+    pub fn attr_wrapper(mut self, attr_wrapper: char) -> HamlOptions {
+        self.attr_wrapper = attr_wrapper;
+        self
+    }
+
+    pub fn autoclose(mut self, autoclose: &[&str]) -> HamlOptions {
+        self.autoclose = autoclose.iter().map(|tag| tag.to_string()).collect();
+        self
+    }
+
+    pub fn preserve(mut self, preserve: &[&str]) -> HamlOptions {
+        self.preserve = preserve.iter().map(|tag| tag.to_string()).collect();
+        self
+    }
+
+    pub fn hyphenate_data_attrs(mut self, hyphenate_data_attrs: bool) -> HamlOptions {
+        self.hyphenate_data_attrs = hyphenate_data_attrs;
+        self
+    }
+
+    pub fn remove_whitespace(mut self, remove_whitespace: bool) -> HamlOptions {
+        self.remove_whitespace = remove_whitespace;
+        self
+    }
+
+    pub fn newline(mut self, newline: Newline) -> HamlOptions {
+        self.newline = newline;
+        self
+    }
+
+    pub fn mime_type(mut self, mime_type: &str) -> HamlOptions {
+        self.mime_type = mime_type.to_string();
+        self
+    }
+
+    /// Adds a filter to the standard ones, or replaces the standard filter
+    /// with the same name.
+    pub fn filter<F: Filter + 'static>(mut self, filter: F) -> HamlOptions {
+        self.filters.register(filter);
+        self
+    }
+
+    pub fn filters(mut self, filters: Filters) -> HamlOptions {
+        self.filters = filters;
+        self
+    }
+
+    pub fn allow_commands(mut self, allow_commands: bool) -> HamlOptions {
+        self.allow_commands = allow_commands;
+        self
+    }
+
+    /// The format actually rendered, taking `mime_type` into account.
+    pub fn effective_format(&self) -> Format {
+        match self.format {
+            Format::Html4() | Format::Html5() if self.mime_type == "text/xml" => Format::XHtml(),
+            format => format,
+        }
+    }
+}
+
+impl Default for HamlOptions {
+    fn default() -> HamlOptions {
+        HamlOptions::new()
+    }
+}
@@ -1,152 +1,1217 @@
-use crate::{Class, Declaration, Element, Haml, Id, Token};
+use crate::arena::Arena;
+use crate::error::{Error, ErrorKind, Result};
+use crate::expr;
+use crate::options::HamlOptions;
+use crate::parser::element::Element;
+use crate::parser::{Code, Each, Filter, Haml, Interpolated, Partial, Script};
+use crate::span::Span;
+use crate::Token;
 
 struct State<'a> {
-    tokens: &'a Vec<Token>,
-    length: usize,
+    tokens: &'a [(Token, Span)],
+    options: &'a HamlOptions,
     index: usize,
+    arena: Arena,
+    // indentation and arena index of every item that may still receive
+    // children, innermost last
+    parents: Vec<(usize, usize)>,
+    // the number of spaces or tabs making up one level of indentation, set
+    // by the first indented line
+    indentation: Option<usize>,
+    // whether the template is indented with tabs rather than spaces, set by
+    // the first indented line
+    tabs: Option<bool>,
+    // index of the first token after the indentation of the current line
+    line_start: usize,
+    // a `= render` or `= yield` following a tag, placed under the tag once
+    // the tag is in the arena
+    inline_child: Option<(Haml, Span)>,
 }
 
 impl<'a> State<'a> {
-    pub fn new(tokens: &'a Vec<Token>) -> State {
-        let length = tokens.len();
+    pub fn new(tokens: &'a [(Token, Span)], options: &'a HamlOptions) -> State<'a> {
         State {
             tokens,
-            length,
+            options,
             index: 0,
+            arena: Arena::new(),
+            parents: vec![],
+            indentation: None,
+            tabs: None,
+            line_start: 0,
+            inline_child: None,
         }
     }
 
-    fn get_next(&mut self) -> Option<&Token> {
-        if self.index + 1 < self.length {
-            self.tokens.get(self.index + 1)
-        } else {
-            None
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.index).map(|(token, _)| token)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&'a Token> {
+        self.tokens.get(self.index + offset).map(|(token, _)| token)
+    }
+
+    fn get_next(&mut self) -> Option<&'a Token> {
+        let token = self.peek();
+        if token.is_some() {
+            self.index += 1;
         }
+        token
     }
 
-    fn declaration(&mut self) -> Option<Box<dyn Haml>> {
-        if self.index + 1 < self.length {
-            let temp = self.index;
-            match self.get_next() {
-                Some(Token::Exclamation()) => {
-                    self.index += 1;
-                    match self.get_next() {
-                        Some(Token::Exclamation()) => {
-                            self.index += 1;
-                            match self.get_next() {
-                                Some(Token::Text(txt)) => Some(Box::new(Declaration::new(txt))),
-                                None => Some(Box::new(Declaration::new(""))),
-                                _ => None,
-                            }
-                        },
-                        _ => {
-                            self.index = temp;
-                            None
-                        }
-                    }
-                },
+    fn whitespace(&mut self) -> usize {
+        let mut count = 0;
+        while let Some(Token::Whitespace()) | Some(Token::Tab()) = self.peek() {
+            self.index += 1;
+            count += 1;
+        }
+        count
+    }
+
+    /// Consumes everything up to, but not including, the next newline.
+    fn rest_of_line(&mut self) -> String {
+        let mut line = String::new();
+        while let Some(token) = self.peek() {
+            if let Token::Newline() = token {
+                break;
+            }
+            line.push_str(&token.to_string());
+            self.index += 1;
+        }
+        line
+    }
+
+    /// Consumes every following line that is blank or indented deeper than
+    /// `whitespace`, returning the indentation and content of each. The
+    /// current line must already be consumed.
+    fn nested_lines(&mut self, whitespace: usize) -> Vec<(usize, String)> {
+        let mut lines = vec![];
+        loop {
+            let start = self.index;
+            if let Some(Token::Newline()) = self.peek() {
+                self.index += 1;
+            } else {
+                break;
+            }
+            let ws = self.whitespace();
+            match self.peek() {
+                None | Some(Token::Newline()) => lines.push((0, String::new())),
+                _ if ws > whitespace => lines.push((ws, self.rest_of_line())),
                 _ => {
-                    self.index = temp;
-                    None
+                    self.index = start;
+                    break;
                 }
             }
+        }
+        lines
+    }
+
+    /// The body of a filter: its nested lines with the common indentation
+    /// and any trailing blank lines removed.
+    fn filter_body(&mut self, whitespace: usize) -> String {
+        let mut lines = self.nested_lines(whitespace);
+        while let Some((_, line)) = lines.last() {
+            if line.is_empty() {
+                lines.pop();
+            } else {
+                break;
+            }
+        }
+        let base = lines
+            .iter()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(ws, _)| *ws)
+            .min()
+            .unwrap_or(0);
+        lines
+            .iter()
+            .map(|(ws, line)| match line.is_empty() {
+                true => String::new(),
+                false => format!("{}{}", " ".repeat(ws - base), line),
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// The body of a filter, as with `filter_body`, with its interpolation
+    /// parsed. An error points at the `#{` at fault in the nested lines.
+    fn interpolated_body(&mut self, whitespace: usize) -> Result<Interpolated> {
+        let start = self.index;
+        let body = self.filter_body(whitespace);
+        Interpolated::new(&body).map_err(|(offset, reason)| {
+            let kind = ErrorKind::InvalidExpression(reason);
+            let line_begin = body[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+            let line = &body[line_begin..];
+            // the indentation left in the body, which the content follows
+            let indentation = line.len() - line.trim_start_matches(' ').len();
+            let newline = self.tokens[start..]
+                .iter()
+                .enumerate()
+                .filter(|(_, (token, _))| *token == Token::Newline())
+                .nth(body[..offset].matches('\n').count());
+            match newline {
+                Some((newline, _)) => {
+                    let content = start + newline + 1;
+                    let content = content + self.whitespace_at(content);
+                    let index = self.token_at(content, offset - line_begin - indentation);
+                    self.error(kind, index)
+                }
+                None => self.error(kind, start),
+            }
+        })
+    }
+
+    /// Parses the interpolation of `text`, which is written from the token
+    /// at `start` on. An error points at the `#{` at fault.
+    fn interpolated(&self, text: &str, start: usize) -> Result<Interpolated> {
+        Interpolated::new(text).map_err(|(offset, reason)| {
+            let index = self.token_at(start, offset);
+            self.error(ErrorKind::InvalidExpression(reason), index)
+        })
+    }
+
+    /// The index of the token holding the byte at `offset` of the text
+    /// written from the token at `start` on.
+    fn token_at(&self, start: usize, offset: usize) -> usize {
+        let mut length = 0;
+        for (index, (token, _)) in self.tokens.iter().enumerate().skip(start) {
+            length += token.to_string().len();
+            if length > offset {
+                return index;
+            }
+        }
+        self.tokens.len()
+    }
+
+    /// The span of the token at `index`, or an empty span just past the end
+    /// of the template when `index` is out of bounds.
+    fn span_at(&self, index: usize) -> Span {
+        if let Some((_, span)) = self.tokens.get(index) {
+            return *span;
+        }
+        match self.tokens.last() {
+            Some((Token::Newline(), span)) => Span::new(span.end, span.end, span.line + 1, 1),
+            Some((token, span)) => Span::new(
+                span.end,
+                span.end,
+                span.line,
+                span.column + token.to_string().chars().count(),
+            ),
+            None => Span::new(0, 0, 1, 1),
+        }
+    }
+
+    /// The span from the token at `start` up to the last token before `end`
+    /// that is not whitespace.
+    fn span_between(&self, start: usize, end: usize) -> Span {
+        let last = self.tokens[start..end]
+            .iter()
+            .rposition(|(token, _)| {
+                !matches!(token, Token::Newline() | Token::Whitespace() | Token::Tab())
+            })
+            .map_or(start, |offset| start + offset);
+        self.span_at(start).to(self.span_at(last))
+    }
+
+    /// Builds an error pointing at the token at `index`.
+    fn error(&self, kind: ErrorKind, index: usize) -> Error {
+        let span = self.span_at(index);
+        let line_begin = self.tokens[..index.min(self.tokens.len())]
+            .iter()
+            .rposition(|(token, _)| *token == Token::Newline())
+            .map_or(0, |newline| newline + 1);
+        let snippet: String = self.tokens[line_begin..]
+            .iter()
+            .map(|(token, _)| token)
+            .take_while(|token| **token != Token::Newline())
+            .map(|token| token.to_string())
+            .collect();
+        Error::new(kind, span, &snippet)
+    }
+
+    fn unexpected(&self, index: usize) -> Error {
+        let token = match self.tokens.get(index).map(|(token, _)| token) {
+            Some(Token::Newline()) | None => "end of line".to_string(),
+            Some(token) => token.to_string(),
+        };
+        self.error(ErrorKind::UnexpectedToken(token), index)
+    }
+
+    /// Finds the parent of a line indented by `whitespace`, checking that the
+    /// indentation is consistent and that the parent may have children.
+    /// Lines are indented with either spaces or tabs, but a template can't
+    /// mix both.
+    fn parent(&mut self, whitespace: usize) -> Result<usize> {
+        if whitespace > 0 {
+            let tabs = self.tokens[self.line_start - whitespace..self.line_start]
+                .iter()
+                .filter(|(token, _)| *token == Token::Tab())
+                .count();
+            let uses_tabs = tabs == whitespace;
+            if (tabs > 0 && !uses_tabs) || self.tabs.is_some_and(|t| t != uses_tabs) {
+                return Err(self.error(ErrorKind::BadIndentation, self.line_start));
+            }
+            self.tabs = Some(uses_tabs);
+        }
+        let previous = self.parents.last().map(|(ws, _)| *ws);
+        let consistent = match (previous, self.indentation) {
+            (None, _) => whitespace == 0,
+            (Some(previous), None) => {
+                if whitespace > previous {
+                    self.indentation = Some(whitespace - previous);
+                }
+                true
+            }
+            (Some(previous), Some(unit)) => {
+                whitespace % unit == 0 && whitespace <= previous + unit
+            }
+        };
+        if !consistent {
+            return Err(self.error(ErrorKind::BadIndentation, self.line_start));
+        }
+
+        while let Some((ws, _)) = self.parents.last() {
+            if *ws >= whitespace {
+                self.parents.pop();
+            } else {
+                break;
+            }
+        }
+        let parent = match self.parents.last() {
+            Some((_, idx)) => *idx,
+            None => return Ok(0),
+        };
+        let reason = match &self.arena.item(parent).value {
+            Haml::Text(_) => Some("nesting within plain text is illegal.".to_string()),
+            Haml::Doctype(_) => Some("nesting within a header command is illegal.".to_string()),
+            Haml::Script(_) | Haml::Partial(_) | Haml::Yield(_) => {
+                Some("nesting within a script line is illegal.".to_string())
+            }
+            Haml::Comment(line) if !line.is_empty() => {
+                Some("nesting within a tag that already has content is illegal.".to_string())
+            }
+            Haml::Element(el) if el.self_close => {
+                Some("nesting within a self-closing tag is illegal.".to_string())
+            }
+            Haml::Element(el)
+                if el.inline_text.is_some()
+                    || el.inline_script.is_some()
+                    || self.has_inline_child(parent) =>
+            {
+                Some(format!(
+                    "content can't be both given on the same line as %{} and nested within it.",
+                    el.name().unwrap_or_default()
+                ))
+            }
+            _ => None,
+        };
+        match reason {
+            Some(reason) => Err(self.error(ErrorKind::IllegalNesting(reason), self.line_start)),
+            None => Ok(parent),
+        }
+    }
+
+    /// Whether the item at `index` has a child given on its own line, as
+    /// with `%title= yield :title`.
+    fn has_inline_child(&self, index: usize) -> bool {
+        let item = self.arena.item(index);
+        item.children
+            .first()
+            .is_some_and(|child| self.arena.item(*child).span.start < item.span.end)
+    }
+
+    fn whitespace_at(&self, index: usize) -> usize {
+        self.tokens[index..]
+            .iter()
+            .take_while(|(token, _)| matches!(token, Token::Whitespace() | Token::Tab()))
+            .count()
+    }
+
+    fn is_declaration(&self) -> bool {
+        (0..3).all(|offset| self.peek_at(offset) == Some(&Token::Exclamation()))
+    }
+
+    fn declaration(&mut self) -> Haml {
+        self.index += 3;
+        let value = self.rest_of_line().trim().to_string();
+        let value = match value.is_empty() {
+            true => None,
+            false => Some(value),
+        };
+        Haml::Doctype(value)
+    }
+
+    fn comment(&mut self, whitespace: usize) -> Haml {
+        self.index += 1;
+        let line = self.rest_of_line().trim().to_string();
+        if let Some(condition) = line.strip_prefix('[') {
+            let value = match condition.find(']') {
+                Some(end) => condition[..end].to_string(),
+                None => condition.to_string(),
+            };
+            Haml::ConditionalComment(whitespace, value)
         } else {
-            None
+            Haml::Comment(line)
         }
     }
 
-    fn classes_and_ids(&mut self, items: &mut Vec<Box<dyn Haml>>) {
-        match self.get_next() {
-            Some(Token::Period()) => {
-                match self.get_next() {
-                    Some(Token::Text(txt)) => {
-                        items.push(Box::new(Class::new(txt)));
-                        self.classes_and_ids(items);
-                    },
-                    _ => (),
+    /// Parses the code following a `=`, `&=`, `!=` or `~` that is `length`
+    /// tokens long, checking that it is an expression that can be evaluated.
+    fn script(&mut self, length: usize, escape: Option<bool>, preserve: bool) -> Result<Script> {
+        self.index += length;
+        let start = self.index + self.whitespace_at(self.index);
+        let code = self.rest_of_line().trim().to_string();
+        for method in &["render", "yield"] {
+            if expr::is_call(&code, method) {
+                let reason = format!("'{}' can only be used on a '=' line of its own", method);
+                return Err(self.error(ErrorKind::InvalidExpression(reason), start));
+            }
+        }
+        match Code::parse(&code) {
+            Ok(code) => Ok(Script {
+                code,
+                escape,
+                preserve,
+            }),
+            Err(reason) => Err(self.error(ErrorKind::InvalidExpression(reason), start)),
+        }
+    }
+
+    /// Parses a `-` line of control flow. `parent` is checked for the branch
+    /// an `elsif` or `else` belongs to.
+    fn silent_script(&mut self, parent: usize) -> Result<Haml> {
+        self.index += 1;
+        let start = self.index + self.whitespace_at(self.index);
+        let line = self.rest_of_line();
+        let line = line.trim();
+        let (keyword, condition) = match line.find(char::is_whitespace) {
+            Some(end) => (&line[..end], line[end..].trim()),
+            None => (line, ""),
+        };
+        let previous = self
+            .arena
+            .children_of(parent)
+            .last()
+            .map(|idx| &self.arena.item(*idx).value);
+        let follows_if = matches!(previous, Some(Haml::If(_)) | Some(Haml::ElsIf(_)));
+        if let Some((code, variables, with_index)) = each(line) {
+            return match variables.iter().all(|v| is_identifier(v)) {
+                true => match Code::parse(code) {
+                    Ok(code) => Ok(Haml::Each(Each {
+                        code,
+                        variables,
+                        with_index,
+                    })),
+                    Err(reason) => Err(self.error(ErrorKind::InvalidExpression(reason), start)),
+                },
+                false => {
+                    let reason = format!("invalid block variables in '{}'", line);
+                    Err(self.error(ErrorKind::InvalidExpression(reason), start))
                 }
+            };
+        }
+        if expr::is_call(line, "content_for") {
+            return self.content_for(line, parent, start);
+        }
+        let branch: fn(Code) -> Haml = match keyword {
+            "if" => Haml::If,
+            "unless" => Haml::Unless,
+            "elsif" if follows_if => Haml::ElsIf,
+            "else" if follows_if || matches!(previous, Some(Haml::Unless(_))) => {
+                return match condition.is_empty() {
+                    true => Ok(Haml::Else()),
+                    false => Err(self.unexpected(start + 1 + self.whitespace_at(start + 1))),
+                };
+            }
+            "elsif" | "else" => {
+                return Err(self.error(ErrorKind::NoPrecedingIf(keyword.to_string()), start))
+            }
+            _ => {
+                let reason = format!("unsupported code '{}'", line);
+                return Err(self.error(ErrorKind::InvalidExpression(reason), start));
+            }
+        };
+        let condition_start = start + 1 + self.whitespace_at(start + 1);
+        match Code::parse(condition) {
+            Ok(code) => Ok(branch(code)),
+            Err(reason) => Err(self.error(ErrorKind::InvalidExpression(reason), condition_start)),
+        }
+    }
+
+    /// Parses a `= render "name", locals` line.
+    fn partial(&mut self) -> Result<Haml> {
+        self.index += 1;
+        let start = self.index + self.whitespace_at(self.index);
+        let code = self.rest_of_line();
+        match expr::parse_render(code.trim()) {
+            Ok((name, locals)) => match locals.as_deref().map(Code::parse).transpose() {
+                Ok(locals) => Ok(Haml::Partial(Partial { name, locals })),
+                Err(reason) => Err(self.error(ErrorKind::InvalidExpression(reason), start)),
             },
-            Some(Token::Hashtag()) => {
-                match self.get_next() {
-                    Some(Token::Text(txt)) => {
-                        items.push(Box::new(Id::new(txt)));
-                        self.classes_and_ids(items);
-                    },
+            Err(reason) => Err(self.error(ErrorKind::InvalidExpression(reason), start)),
+        }
+    }
+
+    /// Parses a `= yield` or `= yield :name` line.
+    fn yield_line(&mut self) -> Result<Haml> {
+        self.index += 1;
+        let start = self.index + self.whitespace_at(self.index);
+        let code = self.rest_of_line();
+        match expr::parse_name_argument(&code.trim()["yield".len()..]) {
+            Ok(name) => Ok(Haml::Yield(name)),
+            Err(reason) => Err(self.error(ErrorKind::InvalidExpression(reason), start)),
+        }
+    }
+
+    /// Whether the code of a `=` line calls `method`, as in `= render ...`.
+    fn calls(&self, method: &str) -> bool {
+        let code: String = self.tokens[self.index + 1..]
+            .iter()
+            .take_while(|(token, _)| *token != Token::Newline())
+            .map(|(token, _)| token.to_string())
+            .collect();
+        expr::is_call(code.trim_start(), method)
+    }
+
+    /// Parses `- content_for :name do`, which may only be used at the top
+    /// level of a page.
+    fn content_for(&self, line: &str, parent: usize, start: usize) -> Result<Haml> {
+        if parent != 0 {
+            let reason = "content_for can only be used at the top level of a page.".to_string();
+            return Err(self.error(ErrorKind::IllegalNesting(reason), self.line_start));
+        }
+        let arguments = line["content_for".len()..].trim_end();
+        let name = match arguments.strip_suffix("do") {
+            Some(arguments) if arguments.ends_with(|c: char| c.is_whitespace() || c == ')') => {
+                expr::parse_name_argument(arguments)
+            }
+            _ => Err("expected 'do' after content_for".to_string()),
+        };
+        match name {
+            Ok(Some(name)) => Ok(Haml::ContentFor(name)),
+            Ok(None) => {
+                let reason = "expected the name of the content".to_string();
+                Err(self.error(ErrorKind::InvalidExpression(reason), start))
+            }
+            Err(reason) => Err(self.error(ErrorKind::InvalidExpression(reason), start)),
+        }
+    }
+
+    /// The script following a tag, e.g. `%p= name`, if there is one.
+    fn inline_script(&mut self) -> Result<Option<Script>> {
+        let script = match (self.peek(), self.peek_at(1)) {
+            (Some(Token::Equal()), _) => self.script(1, None, false)?,
+            (Some(Token::Ampersand()), Some(Token::Equal())) => {
+                self.script(2, Some(true), false)?
+            }
+            (Some(Token::Exclamation()), Some(Token::Equal())) => {
+                self.script(2, Some(false), false)?
+            }
+            (Some(Token::Tilde()), _) => self.script(1, None, true)?,
+            _ => return Ok(None),
+        };
+        Ok(Some(script))
+    }
+
+    /// Parses the `:name` line of a filter. The body is filled in once the
+    /// line has been placed in the arena.
+    fn filter(&mut self, name: &str) -> Result<Haml> {
+        self.index += 1;
+        let rest_start = self.index;
+        if !self.rest_of_line().trim().is_empty() {
+            return Err(self.unexpected(rest_start + self.whitespace_at(rest_start)));
+        }
+        let kind = match self.options.filters.get(name) {
+            None => Some(ErrorKind::UnknownFilter(name.to_string())),
+            Some(filter) if filter.runs_command() && !self.options.allow_commands => {
+                Some(ErrorKind::FilterDisabled(name.to_string()))
+            }
+            Some(_) => None,
+        };
+        if let Some(kind) = kind {
+            return Err(self.error(kind, rest_start - 1));
+        }
+        Ok(Haml::Filter(Filter {
+            name: name.to_string(),
+            body: Interpolated::default(),
+        }))
+    }
+
+    fn text(&mut self) -> Result<Haml> {
+        let start = self.index + self.whitespace_at(self.index);
+        let text = self.rest_of_line();
+        Ok(Haml::Text(self.interpolated(text.trim(), start)?))
+    }
+
+    /// Collects an attribute list from its opening token up to the matching
+    /// closing token, which may be several lines further down. Whitespace
+    /// outside of quoted values is collapsed to a single space, and a
+    /// backslash in a quoted value keeps the token after it from closing
+    /// the value.
+    fn attributes(&mut self, open: &Token, close: &Token) -> Result<String> {
+        let start = self.index;
+        let mut attributes = String::new();
+        let mut depth = 0;
+        let mut quote: Option<&Token> = None;
+        while let Some(token) = self.get_next() {
+            match token {
+                Token::BackSlash() if quote.is_some() => {
+                    attributes.push('\\');
+                    if let Some(escaped) = self.get_next() {
+                        attributes.push_str(&escaped.to_string());
+                    }
+                    continue;
+                }
+                Token::SingleQuote() | Token::DoubleQuote() => match quote {
+                    Some(q) if q == token => quote = None,
+                    None => quote = Some(token),
                     _ => (),
+                },
+                Token::Whitespace() | Token::Tab() | Token::Newline() if quote.is_none() => {
+                    if !attributes.ends_with(' ') {
+                        attributes.push(' ');
+                    }
+                    continue;
                 }
-            },
-            Some(Token::OpenParen()) => (),
-            Some(Token::OpenBrace()) => (),
-            _ => panic!("Invalid token"),
-        }
-    }
-
-    fn element(&mut self) -> Option<Box<dyn Haml>> {
-        let mut temp = self.index;
-        if self.index + 1 < self.length {
-            match self.get_next() {
-                Some(Token::Text(txt)) => {
-                    self.index = self.index + 1;
-                    let inner_temp = self.index;
-                    match self.get_next() {
-                        Some(Token::Period()) => {
-                            let mut items = vec![];
-                            self.classes_and_ids(&mut items);
-                        }
+                t if quote.is_none() && t == open => depth += 1,
+                t if quote.is_none() && t == close => depth -= 1,
+                _ => (),
+            }
+            attributes.push_str(&token.to_string());
+            if depth == 0 {
+                return Ok(attributes);
+            }
+        }
+        Err(self.error(ErrorKind::UnterminatedAttributes, start))
+    }
+
+    /// Consumes a tag, class or id name, which may contain hyphens.
+    fn name(&mut self) -> Option<String> {
+        let mut name = String::new();
+        while let Some(token @ Token::Text(_)) | Some(token @ Token::Minus()) = self.peek() {
+            name.push_str(&token.to_string());
+            self.index += 1;
+        }
+        match name.is_empty() {
+            true => None,
+            false => Some(name),
+        }
+    }
+
+    fn classes_and_ids(&mut self, element: &mut Element) -> Result<()> {
+        loop {
+            let class = match self.peek() {
+                Some(Token::Period()) => true,
+                Some(Token::Hashtag()) => false,
+                _ => return Ok(()),
+            };
+            self.index += 1;
+            match (self.name(), class) {
+                (Some(name), true) => element.add_class(&name),
+                (Some(name), false) => element.add_id(&name),
+                (None, _) => return Err(self.unexpected(self.index)),
+            }
+        }
+    }
+
+    fn element_body(&mut self, mut element: Element) -> Result<Haml> {
+        self.classes_and_ids(&mut element)?;
+        loop {
+            match self.peek() {
+                Some(Token::OpenParen()) => {
+                    let start = self.index;
+                    let attributes = self.attributes(&Token::OpenParen(), &Token::CloseParen())?;
+                    if let Err(reason) = element.parse_html_attributes(&attributes) {
+                        return Err(self.error(ErrorKind::InvalidExpression(reason), start));
                     }
-                    let element = Element::new(&txt.to_string());
-                    let mut items = vec![];
-                    self.classes_and_ids(&mut items);
-                    element
-                                },
-                _ => {
-                    self.index = temp;
-                    None
                 }
+                Some(Token::OpenBrace()) => {
+                    let start = self.index;
+                    let attributes = self.attributes(&Token::OpenBrace(), &Token::CloseBrace())?;
+                    let hyphenate = self.options.hyphenate_data_attrs;
+                    if let Err(reason) = element.parse_ruby_attributes(&attributes, hyphenate) {
+                        return Err(self.error(ErrorKind::InvalidExpression(reason), start));
+                    }
+                }
+                Some(Token::GreaterThan()) => {
+                    element.whitespace_removal_outside = true;
+                    self.index += 1;
+                }
+                Some(Token::LessThan()) => {
+                    element.whitespace_removal_inside = true;
+                    self.index += 1;
+                }
+                Some(Token::ForwardSlash()) => {
+                    element.self_close = true;
+                    self.index += 1;
+                }
+                _ => break,
             }
-        } else {
-            None
         }
+        if let Some(Token::Equal()) = self.peek() {
+            let start = self.index;
+            let child = match () {
+                _ if self.calls("render") => Some(self.partial()?),
+                _ if self.calls("yield") => Some(self.yield_line()?),
+                _ => None,
+            };
+            if let Some(child) = child {
+                // the content is written inside the tag as with `<`
+                element.whitespace_removal_inside = true;
+                self.inline_child = Some((child, self.span_between(start, self.index)));
+            }
+        }
+        element.inline_script = self.inline_script()?;
+        let start = self.index + self.whitespace_at(self.index);
+        let text = self.rest_of_line();
+        let text = text.trim();
+        if !text.is_empty() {
+            element.inline_text = Some(self.interpolated(text, start)?);
+        }
+        let name = element.name().unwrap_or_default();
+        element.preserve = self.options.preserve.contains(&name);
+        if self.options.remove_whitespace {
+            element.whitespace_removal_inside = true;
+            element.whitespace_removal_outside = true;
+        }
+        Ok(Haml::Element(element))
     }
-    pub fn next(&mut self) -> Option<Box<dyn Haml>> {
-        match self.tokens.get(self.index) {
-            Some(Token::Exclamation()) => self.declaration(),
-            Some(Token::PercentageSign()) => self.element(),
-            _ => None,
+
+    fn element(&mut self, whitespace: usize) -> Result<Haml> {
+        self.index += 1;
+        match self.name() {
+            Some(name) => self.element_body(Element::new(&name, whitespace)),
+            None => Err(self.unexpected(self.index)),
         }
     }
+
+    fn div(&mut self, whitespace: usize) -> Result<Haml> {
+        self.element_body(Element::div(whitespace))
+    }
+
+    fn line(&mut self) -> Result<()> {
+        let whitespace = self.whitespace();
+        self.line_start = self.index;
+        if let None | Some(Token::Newline()) = self.peek() {
+            self.index += 1;
+            return Ok(());
+        }
+        let parent = self.parent(whitespace)?;
+        let haml = match (self.peek(), self.peek_at(1)) {
+            (Some(Token::Exclamation()), _) if self.is_declaration() => self.declaration(),
+            (Some(Token::PercentageSign()), _) => self.element(whitespace)?,
+            (Some(Token::Period()), Some(Token::Text(_)))
+            | (Some(Token::Period()), Some(Token::Minus()))
+            | (Some(Token::Hashtag()), Some(Token::Text(_)))
+            | (Some(Token::Hashtag()), Some(Token::Minus())) => self.div(whitespace)?,
+            (Some(Token::ForwardSlash()), _) => self.comment(whitespace),
+            (Some(Token::Minus()), Some(Token::Hashtag())) => {
+                self.rest_of_line();
+                Haml::SilentComment(whitespace)
+            }
+            (Some(Token::Minus()), _) => self.silent_script(parent)?,
+            (Some(Token::Equal()), _) if self.calls("render") => self.partial()?,
+            (Some(Token::Equal()), _) if self.calls("yield") => self.yield_line()?,
+            (Some(Token::Equal()), _)
+            | (Some(Token::Ampersand()), Some(Token::Equal()))
+            | (Some(Token::Exclamation()), Some(Token::Equal()))
+            | (Some(Token::Tilde()), _) => match self.inline_script()? {
+                Some(script) => Haml::Script(script),
+                None => self.text()?,
+            },
+            (Some(Token::Text(text)), _) if text.len() > 1 && text.starts_with(':') => {
+                self.filter(&text[1..])?
+            }
+            (Some(Token::BackSlash()), _) => {
+                self.index += 1;
+                self.text()?
+            }
+            _ => self.text()?,
+        };
+        let haml = match haml {
+            Haml::SilentComment(_) => {
+                self.nested_lines(whitespace);
+                haml
+            }
+            Haml::Filter(filter) => Haml::Filter(Filter {
+                body: self.interpolated_body(whitespace)?,
+                ..filter
+            }),
+            _ => haml,
+        };
+        let span = self.span_between(self.line_start, self.index);
+        let idx = self.arena.insert(haml, parent, span);
+        if let Some((child, span)) = self.inline_child.take() {
+            self.arena.insert(child, idx, span);
+        }
+        self.parents.push((whitespace, idx));
+        if let Some(Token::Newline()) = self.peek() {
+            self.index += 1;
+        }
+        Ok(())
+    }
+
+    pub fn parse(mut self) -> Result<Arena> {
+        while self.peek().is_some() {
+            self.line()?;
+        }
+        if !self.tokens.is_empty() {
+            let span = self.span_at(0).to(self.span_at(self.tokens.len()));
+            self.arena.set_span(0, span);
+        }
+        Ok(self.arena)
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Reads the loop of a `-` line, either `code.each do |a, b|`,
+/// `code.each_with_index do |a, i|` or `for a, b in code`, returning the
+/// code of the collection, the block variables and whether the last one is
+/// the index.
+fn each(line: &str) -> Option<(&str, Vec<String>, bool)> {
+    let variables = |names: &str| names.split(',').map(|v| v.trim().to_string()).collect();
+    if let Some(rest) = line.strip_prefix("for ") {
+        let (names, code) = rest.split_once(" in ")?;
+        return Some((code.trim(), variables(names), false));
+    }
+    let (head, names) = line.strip_suffix('|')?.rsplit_once('|')?;
+    let head = head.trim_end().strip_suffix("do")?;
+    let (code, method) = head.trim_end().rsplit_once('.')?;
+    let with_index = match method {
+        "each" => false,
+        "each_with_index" => true,
+        _ => return None,
+    };
+    Some((code.trim(), variables(names), with_index))
 }
 
-pub(crate) fn parse(tokens: &Vec<Token>) -> String {
-    // let mut arena = Arena::new();
-    let mut index = 0;
-    let len = tokens.len();
-    loop {
-        
-    }
-    for tok in tokens {
-        match tok {
-            Token::Text(txt) => (),
-            Token::Whitespace() => (),
-            Token::OpenParen() => (),
-            Token::CloseParen() => (),
-            Token::OpenBrace() => (),
-            Token::CloseBrace() => (),
-            Token::PercentageSign() => (),
-            Token::Period() => (),
-            Token::Equal() => (),            
-            Token::SingleQuote() => (),
-            Token::DoubleQuote() => (),
-            Token::ForwardSlash() => (),
-            Token::BackSlash() => (),
-            Token::Hashtag() => (),
-            Token::LessThan() => (),            
-            Token::GreaterThan() => (),      
-            Token::Exclamation() => (),      
-            Token::Ampersand() => (),      
-            Token::Tilde() => (),      
-            Token::Newline() => (),      
-        }
-    }
-    String::new()
-}
\ No newline at end of file
+pub(crate) fn parse(tokens: &[(Token, Span)], options: &HamlOptions) -> Result<Arena> {
+    State::new(tokens, options).parse()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lex::lex;
+    use crate::parser::element::AttributeValue;
+
+    fn parse_haml(haml: &str) -> Result<Arena> {
+        parse(&lex(haml), &HamlOptions::new())
+    }
+
+    #[test]
+    fn parse_element() {
+        let tokens = lex("%hi\n  .box\n    #b\n  %span");
+        let arena = parse(&tokens, &HamlOptions::new()).unwrap();
+        let hi = arena.item(arena.root().children[0]);
+        match &hi.value {
+            Haml::Element(el) => assert_eq!(Some("hi".to_string()), el.name),
+            _ => panic!("expected an element"),
+        }
+        assert_eq!(2, hi.children.len());
+        let bx = arena.item(hi.children[0]);
+        match &bx.value {
+            Haml::Element(el) => assert_eq!(Some("box".to_string()), el.get_attribute("class")),
+            _ => panic!("expected an element"),
+        }
+        assert_eq!(1, bx.children.len());
+    }
+
+    #[test]
+    fn parse_text() {
+        let tokens = lex(r"\= test");
+        let arena = parse(&tokens, &HamlOptions::new()).unwrap();
+        let item = arena.item(arena.root().children[0]);
+        assert_eq!(Haml::Text(Interpolated::new("= test").unwrap()), item.value);
+    }
+
+    #[test]
+    fn parse_multiline_attributes() {
+        let tokens = lex("%p(a='b'\n  c='d')\n%br");
+        let arena = parse(&tokens, &HamlOptions::new()).unwrap();
+        assert_eq!(2, arena.root().children.len());
+        match &arena.item(arena.root().children[0]).value {
+            Haml::Element(el) => assert_eq!(Some("d".to_string()), el.get_attribute("c")),
+            _ => panic!("expected an element"),
+        }
+    }
+
+    #[test]
+    fn parse_html_attributes() {
+        let arena = parse_haml(
+            "%a(href=\"/?a=1&b=2\" title='say \\'hi\\''\n  alt='#{name}' n=count on=true\n  download)",
+        )
+        .unwrap();
+        match &arena.item(arena.root().children[0]).value {
+            Haml::Element(el) => {
+                let attribute = |key: &str| el.get_attribute(key).unwrap_or_default();
+                assert_eq!("/?a=1&b=2", attribute("href"));
+                assert_eq!("say 'hi'", attribute("title"));
+                assert_eq!("#{name}", attribute("alt"));
+                assert_eq!("#{count}", attribute("n"));
+                assert_eq!("true", attribute("on"));
+                assert_eq!(
+                    Some(&vec![AttributeValue::Bool(true)]),
+                    el.attributes.get("download")
+                );
+            }
+            _ => panic!("expected an element"),
+        }
+
+        let err = parse_error("%p\n  %a(href= title='b')");
+        assert!(matches!(err.kind(), ErrorKind::InvalidExpression(_)));
+        assert_eq!((2, 5), (err.line(), err.column()));
+    }
+
+    #[test]
+    fn parse_ruby_attributes() {
+        let arena = parse_haml(
+            "%a{href: 'a?b=1, c', \"title\" => \"say \\\"hi\\\"\",\n  :'data-x' => 2, n: name, on: true}",
+        )
+        .unwrap();
+        match &arena.item(arena.root().children[0]).value {
+            Haml::Element(el) => {
+                let attribute = |key: &str| el.get_attribute(key).unwrap_or_default();
+                assert_eq!("a?b=1, c", attribute("href"));
+                assert_eq!("say \"hi\"", attribute("title"));
+                assert_eq!("2", attribute("data-x"));
+                assert_eq!("#{name}", attribute("n"));
+                assert_eq!("true", attribute("on"));
+            }
+            _ => panic!("expected an element"),
+        }
+
+        let err = parse_error("%p\n  %a{href 'b'}");
+        assert!(matches!(err.kind(), ErrorKind::InvalidExpression(_)));
+        assert_eq!((2, 5), (err.line(), err.column()));
+    }
+
+    #[test]
+    fn parse_data_attributes() {
+        let haml = "%div{data: {user_id: 5, role: 'admin', x: {y_z: id}}, aria: {hidden: true}}";
+        let attributes = |options: &HamlOptions| {
+            let arena = parse(&lex(haml), options).unwrap();
+            match &arena.item(arena.root().children[0]).value {
+                Haml::Element(el) => el
+                    .attributes()
+                    .iter()
+                    .map(|key| (key.clone(), el.get_attribute(key).unwrap_or_default()))
+                    .collect::<Vec<_>>(),
+                _ => panic!("expected an element"),
+            }
+        };
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert_eq!(
+            vec![
+                pair("aria-hidden", "true"),
+                pair("data-role", "admin"),
+                pair("data-user-id", "5"),
+                pair("data-x-y-z", "#{id}"),
+            ],
+            attributes(&HamlOptions::new())
+        );
+        assert_eq!(
+            vec![
+                pair("aria-hidden", "true"),
+                pair("data-role", "admin"),
+                pair("data-user_id", "5"),
+                pair("data-x-y_z", "#{id}"),
+            ],
+            attributes(&HamlOptions::new().hyphenate_data_attrs(false))
+        );
+    }
+
+    fn parse_error(haml: &str) -> Error {
+        parse_haml(haml).unwrap_err()
+    }
+
+    #[test]
+    fn missing_tag_name() {
+        let err = parse_error(
+            "%p
+  % hi",
+        );
+        assert_eq!(&ErrorKind::UnexpectedToken(" ".to_string()), err.kind());
+        assert_eq!(2, err.line());
+        assert_eq!(4, err.column());
+        assert_eq!("  % hi", err.snippet());
+    }
+
+    #[test]
+    fn empty_class() {
+        let err = parse_error("%p.");
+        assert_eq!(
+            &ErrorKind::UnexpectedToken("end of line".to_string()),
+            err.kind()
+        );
+        assert_eq!(4, err.column());
+    }
+
+    #[test]
+    fn bad_indentation() {
+        assert_eq!(&ErrorKind::BadIndentation, parse_error("  %p").kind());
+        assert_eq!(
+            &ErrorKind::BadIndentation,
+            parse_error(
+                "%p
+  %a
+     %b"
+            )
+            .kind()
+        );
+        assert_eq!(
+            &ErrorKind::BadIndentation,
+            parse_error(
+                "%p
+  %a
+      %b"
+            )
+            .kind()
+        );
+        let err = parse_error(
+            "%p
+    %a
+  %b",
+        );
+        assert_eq!(&ErrorKind::BadIndentation, err.kind());
+        assert_eq!(3, err.line());
+        assert_eq!(3, err.column());
+    }
+
+    #[test]
+    fn tab_indentation() {
+        let tokens = lex("%p\n\t%a\n\t\t%b\n%p");
+        let arena = parse(&tokens, &HamlOptions::new()).unwrap();
+        assert_eq!(2, arena.root().children.len());
+        let p = arena.item(arena.root().children[0]);
+        assert_eq!(1, p.children.len());
+        assert_eq!(1, arena.item(p.children[0]).children.len());
+
+        for haml in &["%p\n\t %a", "%p\n\t%a\n%p\n  %b", "%p\n  %a\n\t\t%b"] {
+            assert_eq!(&ErrorKind::BadIndentation, parse_error(haml).kind());
+        }
+    }
+
+    #[test]
+    fn unterminated_attributes() {
+        let err = parse_error(
+            "%div
+  %p(a='b'
+  c='d'",
+        );
+        assert_eq!(&ErrorKind::UnterminatedAttributes, err.kind());
+        assert_eq!(2, err.line());
+        assert_eq!(5, err.column());
+    }
+
+    #[test]
+    fn illegal_nesting() {
+        for haml in &[
+            "%p hello
+  world",
+            "hello
+  world",
+            "%br/
+  a",
+            "!!!
+  a",
+        ] {
+            match parse_error(haml).kind() {
+                ErrorKind::IllegalNesting(_) => (),
+                kind => panic!("unexpected error {:?}", kind),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_filter() {
+        let arena = parse_haml(":plain\n  hello\n\n    world\n\n%p").unwrap();
+        assert_eq!(2, arena.root().children.len());
+        let filter = Filter {
+            name: "plain".to_string(),
+            body: Interpolated::new("hello\n\n  world").unwrap(),
+        };
+        assert_eq!(
+            Haml::Filter(filter),
+            arena.item(arena.root().children[0]).value
+        );
+    }
+
+    #[test]
+    fn parse_script() {
+        let arena = parse_haml("!= 'a'\n~ b").unwrap();
+        let children = &arena.root().children;
+        match &arena.item(children[0]).value {
+            Haml::Script(script) => {
+                assert_eq!(Some("a"), script.literal());
+                assert_eq!(Some(false), script.escape);
+            }
+            _ => panic!("expected a script"),
+        }
+        match &arena.item(children[1]).value {
+            Haml::Script(script) => assert!(script.preserve && script.literal().is_none()),
+            _ => panic!("expected a script"),
+        }
+    }
+
+    #[test]
+    fn parse_inline_script() {
+        let arena = parse_haml("%p&= user.name").unwrap();
+        match &arena.item(1).value {
+            Haml::Element(el) => {
+                let script = el.inline_script.as_ref().unwrap();
+                assert_eq!("user.name", script.code.source());
+                assert_eq!(Some(true), script.escape);
+                assert_eq!(None, el.inline_text);
+            }
+            _ => panic!("expected an element"),
+        }
+        let err = parse_error("%p= a b");
+        assert_eq!(
+            &ErrorKind::InvalidExpression("unexpected 'b'".to_string()),
+            err.kind()
+        );
+        assert_eq!(5, err.column());
+    }
+
+    #[test]
+    fn parse_partial() {
+        let arena = parse_haml("= render 'shared/nav', user: user\n= rendered").unwrap();
+        let partial = Partial {
+            name: "shared/nav".to_string(),
+            locals: Some(Code::parse("{ user: user }").unwrap()),
+        };
+        assert_eq!(Haml::Partial(partial), arena.item(1).value);
+        assert!(matches!(arena.item(2).value, Haml::Script(_)));
+
+        let err = parse_error("= render user");
+        assert_eq!(3, err.column());
+        let err = parse_error("%p&= render 'a'");
+        assert_eq!(6, err.column());
+        let err = parse_error("= render 'a'\n  %p");
+        assert!(matches!(err.kind(), ErrorKind::IllegalNesting(_)));
+    }
+
+    #[test]
+    fn parse_layout() {
+        let arena = parse_haml("= yield\n= yield(:side)\n- content_for :side do\n  %p").unwrap();
+        assert_eq!(Haml::Yield(None), arena.item(1).value);
+        assert_eq!(Haml::Yield(Some("side".to_string())), arena.item(2).value);
+        assert_eq!(Haml::ContentFor("side".to_string()), arena.item(3).value);
+        assert_eq!(1, arena.children_of(3).len());
+
+        let err = parse_error("%div\n  - content_for :side do");
+        assert!(matches!(err.kind(), ErrorKind::IllegalNesting(_)));
+        let err = parse_error("- content_for :side");
+        assert_eq!(
+            &ErrorKind::InvalidExpression("expected 'do' after content_for".to_string()),
+            err.kind()
+        );
+        let err = parse_error("= yield side");
+        assert_eq!(3, err.column());
+
+        let arena = parse_haml("%title= yield :title\n%p{:a => 'b'}= render 'x'").unwrap();
+        assert_eq!(Haml::Yield(Some("title".to_string())), arena.item(2).value);
+        assert!(matches!(arena.item(4).value, Haml::Partial(_)));
+        let err = parse_error("%title= yield :title\n  %p");
+        assert!(matches!(err.kind(), ErrorKind::IllegalNesting(_)));
+    }
+
+    #[test]
+    fn parse_conditionals() {
+        let arena = parse_haml("- if a\n  %p\n- elsif b\n- else\n%a-b.c-d").unwrap();
+        let values: Vec<&Haml> = arena
+            .children_of(0)
+            .iter()
+            .map(|idx| &arena.item(*idx).value)
+            .collect();
+        assert_eq!(&Haml::If(Code::parse("a").unwrap()), values[0]);
+        assert_eq!(&Haml::ElsIf(Code::parse("b").unwrap()), values[1]);
+        assert_eq!(&Haml::Else(), values[2]);
+        match values[3] {
+            Haml::Element(el) => {
+                assert_eq!(Some("a-b".to_string()), el.name());
+                assert_eq!(Some("c-d".to_string()), el.get_attribute("class"));
+            }
+            _ => panic!("expected an element"),
+        }
+
+        let err = parse_error("%p\n- else");
+        assert_eq!(&ErrorKind::NoPrecedingIf("else".to_string()), err.kind());
+        assert_eq!((2, 3), (err.line(), err.column()));
+        let err = parse_error("- unless a\n- elsif b");
+        assert_eq!(&ErrorKind::NoPrecedingIf("elsif".to_string()), err.kind());
+        let err = parse_error("- if a\n  - if b\n- else c");
+        assert_eq!(&ErrorKind::UnexpectedToken("c".to_string()), err.kind());
+        assert_eq!((3, 8), (err.line(), err.column()));
+        let err = parse_error("- if a b");
+        assert_eq!(6, err.column());
+        let err = parse_error("- x = 1");
+        assert_eq!(
+            &ErrorKind::InvalidExpression("unsupported code 'x = 1'".to_string()),
+            err.kind()
+        );
+        let err = parse_error("-");
+        assert_eq!(
+            &ErrorKind::InvalidExpression("unsupported code ''".to_string()),
+            err.kind()
+        );
+    }
+
+    #[test]
+    fn parse_each() {
+        let arena = parse_haml(
+            "- user.posts.each_with_index do |post, i|\n  %p= post\n- for k, v in map\n  %p",
+        )
+        .unwrap();
+        let values: Vec<&Haml> = arena
+            .children_of(0)
+            .iter()
+            .map(|idx| &arena.item(*idx).value)
+            .collect();
+        let each = Each {
+            code: Code::parse("user.posts").unwrap(),
+            variables: vec!["post".to_string(), "i".to_string()],
+            with_index: true,
+        };
+        assert_eq!(&Haml::Each(each), values[0]);
+        let each = Each {
+            code: Code::parse("map").unwrap(),
+            variables: vec!["k".to_string(), "v".to_string()],
+            with_index: false,
+        };
+        assert_eq!(&Haml::Each(each), values[1]);
+        assert_eq!(1, arena.children_of(arena.children_of(0)[0]).len());
+
+        let err = parse_error("- items.each do |a b|");
+        assert_eq!(
+            &ErrorKind::InvalidExpression(
+                "invalid block variables in 'items.each do |a b|'".to_string()
+            ),
+            err.kind()
+        );
+        let err = parse_error("- items.map do |a|");
+        assert_eq!(
+            &ErrorKind::InvalidExpression("unsupported code 'items.map do |a|'".to_string()),
+            err.kind()
+        );
+    }
+
+    #[test]
+    fn item_spans() {
+        let haml = "%p(a='b'\n  c='d')\n  :plain\n    x\n\n%br";
+        let arena = parse_haml(haml).unwrap();
+        let p = arena.item(arena.root().children[0]);
+        assert_eq!(Span::new(0, 17, 1, 1), p.span);
+        let filter = arena.item(p.children[0]);
+        assert_eq!(Span::new(20, 32, 3, 3), filter.span);
+        assert_eq!(":plain\n    x", &haml[filter.span.start..filter.span.end]);
+        let br = arena.item(arena.root().children[1]);
+        assert_eq!(Span::new(34, 37, 6, 1), br.span);
+        assert_eq!(Span::new(0, 37, 1, 1), arena.root().span);
+    }
+}
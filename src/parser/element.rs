@@ -1,7 +1,9 @@
-// use https://regexr.com/ to test regex
-use crate::regex::{div, element, element_class_id, html_attribute, ruby_attribute};
-use regex::{Captures, Regex};
+use crate::context::Scope;
+use crate::expr::{self, Expr};
+use crate::parser::{Code, Interpolated, Script};
+use serde_json::{Number, Value};
 use std::collections::{BTreeSet, HashMap};
+use std::fmt;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ElementType {
@@ -9,352 +11,326 @@ pub enum ElementType {
     Other(),
 }
 
+/// The value of an attribute as written in the template.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeValue {
+    /// Text written as it is.
+    String(String),
+    /// Text with `#{}` interpolated when the template is rendered.
+    Interpolated(Interpolated),
+    Number(Number),
+    /// `true` makes a boolean attribute, such as `checked` in HTML or
+    /// `checked='checked'` in XHTML, and `false` leaves the attribute out.
+    Bool(bool),
+    /// `nil`, which leaves the attribute out.
+    Nil(),
+    /// An array, whose items are joined with spaces.
+    List(Vec<AttributeValue>),
+    /// A hash given to an attribute other than `data` and `aria`.
+    Hash(Vec<(String, AttributeValue)>),
+    /// Code evaluated when the template is rendered.
+    Expression(Code),
+}
+
+impl AttributeValue {
+    /// The value of the code `code`, parsed as `value`.
+    fn from_code(code: &str, value: Expr) -> Result<AttributeValue, String> {
+        Ok(match value {
+            Expr::Literal(Value::String(text)) => AttributeValue::String(text),
+            Expr::Literal(Value::Number(number)) => AttributeValue::Number(number),
+            Expr::Literal(Value::Bool(value)) => AttributeValue::Bool(value),
+            Expr::Literal(_) => AttributeValue::Nil(),
+            Expr::Interpolated(text) => AttributeValue::Interpolated(text),
+            Expr::Array(_) => AttributeValue::List(
+                expr::parse_attribute_array(code)?
+                    .into_iter()
+                    .map(|(code, item)| AttributeValue::from_code(code, item))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Hash(_) => AttributeValue::Hash(
+                expr::parse_attribute_hash(code)?
+                    .into_iter()
+                    .map(|(key, code, value)| Ok((key, AttributeValue::from_code(code, value)?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            value => AttributeValue::Expression(Code::new(code, value)),
+        })
+    }
+
+    /// Whether the value is the same whatever the template's variables.
+    pub fn is_static(&self) -> bool {
+        match self {
+            AttributeValue::Interpolated(_) => false,
+            AttributeValue::List(items) => items.iter().all(AttributeValue::is_static),
+            AttributeValue::Hash(entries) => entries.iter().all(|(_, value)| value.is_static()),
+            AttributeValue::Expression(_) => false,
+            _ => true,
+        }
+    }
+
+    /// The value with the variables of `scope`.
+    pub(crate) fn evaluate(&self, scope: &Scope) -> Value {
+        match self {
+            AttributeValue::String(text) => Value::String(text.clone()),
+            AttributeValue::Interpolated(text) => Value::String(text.value(scope)),
+            AttributeValue::Number(number) => Value::Number(number.clone()),
+            AttributeValue::Bool(value) => Value::Bool(*value),
+            AttributeValue::Nil() => Value::Null,
+            AttributeValue::List(items) => {
+                Value::Array(items.iter().map(|item| item.evaluate(scope)).collect())
+            }
+            AttributeValue::Hash(entries) => Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.evaluate(scope)))
+                    .collect(),
+            ),
+            AttributeValue::Expression(code) => code.evaluate(scope).into_owned(),
+        }
+    }
+}
+
+/// The value as written, with code as a `#{}` interpolation.
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttributeValue::String(text) => f.write_str(text),
+            AttributeValue::Interpolated(text) => write!(f, "{}", text),
+            AttributeValue::Number(number) => write!(f, "{}", number),
+            AttributeValue::Bool(value) => write!(f, "{}", value),
+            AttributeValue::Nil() => Ok(()),
+            AttributeValue::List(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                f.write_str(&items.join(" "))
+            }
+            AttributeValue::Hash(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            AttributeValue::Expression(code) => write!(f, "#{{{}}}", code),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Element {
     pub whitespace: usize,
     pub name: Option<String>,
     pub element_type: ElementType,
-    pub inline_text: Option<String>,
-    pub attributes: HashMap<String, Vec<String>>,
+    pub inline_text: Option<Interpolated>,
+    /// Code following the tag, as in `%p= name`.
+    pub inline_script: Option<Script>,
+    pub attributes: HashMap<String, Vec<AttributeValue>>,
     pub attribute_order: BTreeSet<String>,
     pub self_close: bool,
     pub whitespace_removal_inside: bool,
     pub whitespace_removal_outside: bool,
+    /// Whether the tag is in the `preserve` list, keeping the whitespace of
+    /// its content.
+    pub preserve: bool,
 }
 
 impl Element {
-    pub fn name(&self) -> Option<String> {
-        if let Some(name) = &self.name {
-            Some(name.to_owned())
-        } else {
-            None
-        }
-    }
-
-    pub fn attributes(&self) -> &BTreeSet<String> {
-        &self.attribute_order
-    }
-
-    fn div_id_class(caps: &Captures) -> Option<String> {
-        let mut value = String::new();
-        let name = match caps.name("name") {
-            Some(name) => name.as_str(),
-            _ => "",
-        };
-        let other = match caps.name("classid") {
-            Some(classid) => classid.as_str(),
-            _ => "",
-        };
-        let output = format!("{}{}", name.trim(), other.trim());
-        match output.is_empty() {
-            true => None,
-            _ => Some(output),
-        }
-    }
-
-    fn create_div<'a>(caps: &'a Captures) -> Element {
-        let (mut attributes, order) = Element::handle_attributes(caps);
+    pub fn new(name: &str, whitespace: usize) -> Element {
         Element {
-            whitespace: Element::handle_whitespace(caps),
-            name: Some("div".to_string()),
-            element_type: ElementType::Div(),
-            inline_text: Element::handle_inline_text(caps),
-            attributes: attributes,
-            attribute_order: order,
-            self_close: Element::handle_self_close(caps),
+            whitespace,
+            name: Some(name.to_string()),
+            element_type: ElementType::Other(),
+            inline_text: None,
+            inline_script: None,
+            attributes: HashMap::new(),
+            attribute_order: BTreeSet::new(),
+            self_close: false,
             whitespace_removal_inside: false,
             whitespace_removal_outside: false,
+            preserve: false,
         }
     }
 
-    fn handle_self_close(caps: &Captures) -> bool {
-        match caps.name("self_close") {
-            Some(m) => match m.as_str() {
-                "" => false,
-                _ => true,
-            },
-            None => false,
-        }
-    }
-    fn handle_whitespace(caps: &Captures) -> usize {
-        match caps.name("ws") {
-            Some(ws) => ws.as_str().len(),
-            None => 0,
-        }
+    pub fn div(whitespace: usize) -> Element {
+        let mut element = Element::new("div", whitespace);
+        element.element_type = ElementType::Div();
+        element
     }
 
-    fn handle_name(caps: &Captures) -> (Option<String>, bool, bool) {
-        match caps.name("name") {
-            Some(name) => {
-                
-                let mut val = name.as_str().to_owned();
-                let whitespace_removal_inside = val.ends_with("<");
-                let whitespace_removal_outside = val.ends_with(">");
-                let len = val.len();
-                if val.starts_with("%") {
-                    val = val[1..].to_owned();
-                    if whitespace_removal_inside || whitespace_removal_outside {
-                        // len - 1 is the full string, len - 2 takes the last character off
-                        val = val[0..len - 2].to_string();
-                    }
-                }
-                (Some(val.as_str().to_owned()), whitespace_removal_inside, whitespace_removal_outside)
-            }
-            None => (None, false, false)
-        }
+    pub fn name(&self) -> Option<String> {
+        self.name.clone()
     }
 
-    fn format_value(val: &str) -> String {
-        match val.starts_with("\"") {
-            true => val[1..val.len() - 1].to_owned(),
-            false => val.to_owned(),
-        }
+    pub fn attributes(&self) -> &BTreeSet<String> {
+        &self.attribute_order
     }
 
-    fn handle_whitespace_removal(caps: &Captures) -> (bool, bool) {
-        match caps.name("name") {
-            None => (false, false),
-            Some(m) => {
-                let val = m.as_str();
-                let remove_surrounding_tag = val.ends_with(">");
-                let remove_inside_tag = val.ends_with("<");
-                (remove_inside_tag, remove_surrounding_tag)
+    /// Adds a class from the `.class` shorthand. The classes of the
+    /// shorthand make up a single value, keeping the order they are given
+    /// in unless they are merged with classes from an attribute list.
+    pub fn add_class(&mut self, class: &str) {
+        // the shorthand comes before any attribute list
+        match self.attributes.get_mut("class").map(Vec::as_mut_slice) {
+            Some([AttributeValue::String(classes)]) => {
+                classes.push(' ');
+                classes.push_str(class);
             }
+            _ => self.add_value("class", AttributeValue::String(class.to_string())),
         }
     }
 
-    fn add_to_map(map: &mut HashMap<String, Vec<String>>, key: &str, value: &str) {
-        let mut val = value.to_string();
-        if val.starts_with("'") {
-            val = val[1..].to_string();
-        }
-        if val.ends_with("'") {
-            val = val[0..val.len() - 1].to_string();
-        }
-        if let Some(values) = map.get_mut(key) {
-            match key {
-                "id" => {
-                    (*values).clear();
-                    (*values).push(format!("id_{}", val));
-                }
-                _ => (*values).push(val),
-            }
-        } else {
-            map.insert(key.to_owned(), vec![val]);
-        }
+    /// Sets the id from the `#id` shorthand. A later shorthand id replaces
+    /// an earlier one.
+    pub fn add_id(&mut self, id: &str) {
+        let id = AttributeValue::String(id.to_string());
+        self.attributes.insert("id".to_string(), vec![id]);
+        self.attribute_order.insert("id".to_string());
     }
 
-    // parse the attributes by hand so that atomic values are covered
-    fn parse_html_attributes(
-        attributes: &str,
-        map: &mut HashMap<String, Vec<String>>,
-        order: &mut BTreeSet<String>,
-    ) {
-        if !attributes.is_empty() {
-            let mut seen_key = false;
-            let mut step = false;
-            let mut attrs = String::new();
-            if attributes.starts_with("(") {
-                attrs = attributes[1..].to_owned();
-            }
-
-            if attrs.ends_with(")") {
-                attrs = attrs[0..attrs.len() - 1].to_owned();
-            }
-
-            let words: Vec<&str> = attrs.split(" ").collect();
-            let mut words_iter = words.iter();
-            let mut buffer = words_iter.next();
-            let mut temp = "";
-            loop {
-                if let Some(wrd) = buffer {
-                    let split: Vec<&str> = wrd.split("=").collect();
-                    if split.len() > 1 {
-                        let key = split.get(0).unwrap();
-                        let value = split.get(1).unwrap();
-                        Element::add_to_map(map, key, value);
-                        order.insert(key.to_string());
-                        buffer = words_iter.next();
-                    } else {
-                        if let Some(next) = words_iter.next() {
-                            match *next {
-                                "=" => match words_iter.next() {
-                                    Some(w) => {
-                                        Element::add_to_map(map, *wrd, w);
-                                        order.insert((*wrd).to_owned());
-                                        buffer = words_iter.next();
-                                    }
-                                    None => break,
-                                },
-                                e => {
-                                    map.insert((*wrd).to_owned(), vec![]);
-                                    order.insert((*wrd).to_owned());
-                                    temp = e;
-                                    buffer = Some(&temp);
-                                }
-                            }
-                        } else {
-                            map.insert((*wrd).to_owned(), vec![]);
-                            order.insert((*wrd).to_owned());
-                            break;
-                        }
-                    }
-                } else {
-                    break;
-                }
-            }
+    /// Adds the attributes of one attribute list. A key given more than once
+    /// in the same list takes the last value, as in a Ruby hash.
+    fn add_list(&mut self, list: Vec<(String, AttributeValue)>) {
+        let mut last: Vec<(String, AttributeValue)> = vec![];
+        for (key, value) in list {
+            last.retain(|(other, _)| *other != key);
+            last.push((key, value));
         }
-    }
-    fn handle_attrs(
-        attributes: &str,
-        attribute_regex: &str,
-        separator: &str,
-        map: &mut HashMap<String, Vec<String>>,
-        start_index: usize,
-        order: &mut BTreeSet<String>,
-    ) {
-        if !attributes.is_empty() {
-            let r = Regex::new(attribute_regex).unwrap();
-            if r.is_match(attributes) {
-                for attr in r.find_iter(attributes) {
-                    let mut attr_it = attr.as_str().split(separator);
-                    let id = attr_it.next();
-                    let val = attr_it.next();
-                    match (id, val) {
-                        (_, None) => continue,
-                        (None, _) => continue,
-                        (Some(i), Some(v)) => {
-                            if let Some(current_val) =
-                                map.get_mut(&i[start_index..].trim().to_owned())
-                            {
-                                (*current_val).push(Element::format_value(v));
-                            } else {
-                                order.insert(i[start_index..].to_owned());
-                                map.insert(
-                                    i[start_index..].to_owned(),
-                                    vec![Element::format_value(v)],
-                                );
-                            }
-                        }
-                    }
-                }
-            }
+        for (key, value) in last {
+            self.add_value(&key, value);
         }
     }
 
-    fn handle_attributes(caps: &Captures) -> (HashMap<String, Vec<String>>, BTreeSet<String>) {
-        let mut map: HashMap<String, Vec<String>> = HashMap::new();
-        let mut order: BTreeSet<String> = BTreeSet::new();
-        if let Some(c) = caps.name("name") {
-            let name = c.as_str();
-            if name.starts_with(".") {
-                map.insert("class".to_owned(), vec![name[1..].to_owned()]);
-                order.insert("class".to_owned());
-            } else if name.starts_with("#") {
-                map.insert("id".to_owned(), vec![name[1..].to_owned()]);
-                order.insert("id".to_owned());
-            }
+    /// Adds a value to the attribute `key`. Classes and ids given by more
+    /// than one of the shorthand and the attribute lists are merged when
+    /// they are written, keeping every class and joining the ids with `_`,
+    /// while any other attribute takes the last value given.
+    fn add_value(&mut self, key: &str, value: AttributeValue) {
+        let values = self.attributes.entry(key.to_string()).or_default();
+        if key != "class" && key != "id" {
+            values.clear();
         }
+        values.push(value);
+        self.attribute_order.insert(key.to_string());
+    }
 
-        match caps.name("classid") {
-            Some(c) => {
-                let class_id_reg = Regex::new(&element_class_id()).unwrap();
-                let classid_value = c.as_str();
-                for ci in class_id_reg.find_iter(classid_value) {
-                    let value = classid_value[ci.start()..ci.end()].to_string();
-                    match &value[0..1] {
-                        "#" => {
-                            map.insert("id".to_owned(), vec![value[1..].to_owned()]);
-                            order.insert("id".to_string());
-                        }
-                        "." => {
-                            Element::add_to_map(&mut map, "class", &value[1..]);
-                            order.insert("class".to_string());
-                        }
-                        _ => (),
-                    }
-                }
-            }
-            None => (),
-        }
-        match caps.name("ruby_attributes") {
-            Some(attributes) => Element::handle_attrs(
-                attributes.as_str(),
-                &ruby_attribute(),
-                "=>",
-                &mut map,
-                1,
-                &mut order,
-            ),
-            None => (),
-        }
-        match caps.name("html_attributes") {
-            Some(attributes) => {
-                Element::parse_html_attributes(attributes.as_str(), &mut map, &mut order)
+    /// Parses HTML style attributes such as `(a='b' c="d" e=f flag)`.
+    /// `attributes` includes the surrounding parentheses. Values in either
+    /// quotes are interpolated and unescaped like Ruby strings, while an
+    /// unquoted value is code such as `true` or a variable. An attribute
+    /// without a value is atomic.
+    pub fn parse_html_attributes(&mut self, attributes: &str) -> Result<(), String> {
+        let inner = attributes.strip_prefix('(').unwrap_or(attributes);
+        let inner = inner.strip_suffix(')').unwrap_or(inner);
+        let mut rest = inner.trim_start();
+        let mut list = vec![];
+        while !rest.is_empty() {
+            let name_end = rest
+                .find(|c: char| c.is_whitespace() || c == '=')
+                .unwrap_or(rest.len());
+            let name = &rest[..name_end];
+            if name.is_empty() || name.contains(['\'', '"']) {
+                return Err(format!("expected an attribute name at '{}'", rest));
             }
-            None => (),
+            rest = rest[name_end..].trim_start();
+            let value = match rest.strip_prefix('=') {
+                Some(value) => value.trim_start(),
+                None => {
+                    list.push((name.to_string(), AttributeValue::Bool(true)));
+                    continue;
+                }
+            };
+            let end = match value.chars().next() {
+                Some(quote @ '\'') | Some(quote @ '"') => quoted_end(value, quote)
+                    .ok_or_else(|| format!("unterminated value of '{}'", name))?,
+                Some(_) => value.find(char::is_whitespace).unwrap_or(value.len()),
+                None => return Err(format!("expected a value for '{}'", name)),
+            };
+            let code = &value[..end];
+            let parsed = match code.starts_with(['\'', '"']) {
+                true => expr::parse_attribute_value(code)?,
+                false => expr::parse(code)?,
+            };
+            list.push((name.to_string(), AttributeValue::from_code(code, parsed)?));
+            rest = value[end..].trim_start();
         }
-
-        (map, order)
+        self.add_list(list);
+        Ok(())
     }
 
-    fn handle_inline_text(caps: &Captures) -> Option<String> {
-        match caps.name("text") {
-            Some(text) => Some(text.as_str().trim().to_owned()),
-            None => None,
-        }
+    /// Parses a Ruby style attribute hash, with `key: value`, `:key =>
+    /// value` or `"key" => value` entries. `attributes` includes the
+    /// surrounding braces.
+    ///
+    /// A hash given to `data` or `aria` becomes one attribute per entry, so
+    /// `data: {user_id: 5}` is `data-user-id`, with the underscores of its
+    /// keys kept when `hyphenate_data_attrs` is off.
+    pub fn parse_ruby_attributes(
+        &mut self,
+        attributes: &str,
+        hyphenate_data_attrs: bool,
+    ) -> Result<(), String> {
+        let mut list = vec![];
+        prefixed_attributes(None, attributes, hyphenate_data_attrs, &mut list)?;
+        self.add_list(list);
+        Ok(())
     }
 
-    fn create_element<'a>(caps: &'a Captures) -> Element {
-        let (attributes, order) = Element::handle_attributes(caps);
-        let (name, whitespace_removal_inside, whitespace_removal_outside) = Element::handle_name(caps);
-        Element {
-            whitespace: Element::handle_whitespace(caps),
-            name,
-            element_type: ElementType::Other(),
-            inline_text: Element::handle_inline_text(caps),
-            attributes: attributes,
-            attribute_order: order,
-            self_close: Element::handle_self_close(caps),
-            whitespace_removal_inside,
-            whitespace_removal_outside,
+    /// The values of an attribute as written, joined with spaces, or with
+    /// `_` for an id. Classes from more than one source are sorted.
+    pub fn get_attribute(&self, name: &str) -> Option<String> {
+        if let Some(attributes) = self.attributes.get(name) {
+            let values: Vec<String> = attributes.iter().map(|v| v.to_string()).collect();
+            if name == "class" && values.len() > 1 {
+                let mut classes: Vec<&str> =
+                    values.iter().flat_map(|v| v.split_whitespace()).collect();
+                classes.sort_unstable();
+                classes.dedup();
+                return Some(classes.join(" "));
+            }
+            let separator = if name == "id" { "_" } else { " " };
+            Some(values.join(separator).trim().to_owned())
+        } else {
+            None
         }
     }
+}
 
-    pub fn from_string(haml: &str) -> Option<Element> {
-        let element_regex = Regex::new(&element()).unwrap();
-        let div_regex = Regex::new(&div());
-        let element: Option<Element> = match Regex::new(&element()) {
-            Ok(el) => match el.is_match(haml) {
-                true => {
-                    let caps = el.captures(haml).unwrap();
-                    Some(Element::create_element(&caps))
-                }
-                false => None,
-            },
-            _ => None,
+/// Adds the entries of the attribute hash `code` to `list`. With a
+/// `prefix`, entries are named `prefix-key`. A hash given to `data` or
+/// `aria` adds its own entries in turn, with the key as their prefix.
+fn prefixed_attributes(
+    prefix: Option<&str>,
+    code: &str,
+    hyphenate_data_attrs: bool,
+    list: &mut Vec<(String, AttributeValue)>,
+) -> Result<(), String> {
+    for (key, code, value) in expr::parse_attribute_hash(code)? {
+        let key = match (prefix, hyphenate_data_attrs) {
+            (None, _) => key,
+            (Some(prefix), true) => format!("{}-{}", prefix, key.replace('_', "-")),
+            (Some(prefix), false) => format!("{}-{}", prefix, key),
         };
-
-        match element {
-            Some(el) => Some(el),
-            None => match div_regex {
-                Ok(el) => match el.is_match(haml) {
-                    true => {
-                        let caps = el.captures(haml).unwrap();
-                        Some(Element::create_div(&caps))
-                    }
-                    false => None,
-                },
-                _ => None,
-            },
+        match value {
+            Expr::Hash(_) if prefix.is_some() || key == "data" || key == "aria" => {
+                prefixed_attributes(Some(&key), code, hyphenate_data_attrs, list)?
+            }
+            value => list.push((key, AttributeValue::from_code(code, value)?)),
         }
     }
+    Ok(())
+}
 
-    pub fn get_attribute(&self, name: &str) -> Option<String> {
-        if let Some(attributes) = self.attributes.get(name) {
-            Some(attributes.join(" ").trim().to_owned())
-        } else {
-            None
+/// The length of the quoted string at the start of `text` including its
+/// quotes, skipping escaped characters.
+fn quoted_end(text: &str, quote: char) -> Option<usize> {
+    let mut chars = text.char_indices().skip(1);
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            c if c == quote => return Some(offset + 1),
+            _ => (),
         }
     }
+    None
 }
@@ -1,275 +1,230 @@
-mod doctype;
 pub mod element;
 
-use crate::arena::Arena;
-use crate::Format;
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+
+use crate::context::Scope;
+use crate::expr::{self, Expr, Segment};
+use element::Element;
+use serde_json::Value;
+
+/// A single node of a parsed Haml template.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Haml {
+    /// The root of the document. Every top level line is one of its children.
+    Root(),
+    /// A tag such as `%p`, `.class` or `#id`.
+    Element(Element),
+    /// A line of plain text.
+    Text(Interpolated),
+    /// An HTML comment started with `/`.
+    Comment(String),
+    /// A `!!!` line, holding the doctype or prolog that follows it if any.
+    Doctype(Option<String>),
+    /// A `-#` comment along with its indentation. Nested lines are dropped.
+    SilentComment(usize),
+    /// A `/[condition]` comment along with its indentation.
+    ConditionalComment(usize, String),
+    /// A `:name` filter along with its body.
+    Filter(Filter),
+    /// A line of code started with `=`, `&=`, `!=` or `~`.
+    Script(Script),
+    /// `- if condition`. Its nested lines are rendered when the condition is
+    /// truthy, which is anything but `nil` and `false`.
+    If(Code),
+    /// `- elsif condition`, following an `if` or another `elsif` at the same
+    /// indentation.
+    ElsIf(Code),
+    /// `- else`, following an `if`, `elsif` or `unless` at the same
+    /// indentation.
+    Else(),
+    /// `- unless condition`. Its nested lines are rendered when the condition
+    /// is `nil` or `false`.
+    Unless(Code),
+    /// `- items.each do |item|`, `- items.each_with_index do |item, i|` or
+    /// `- for item in items`. Its nested lines are rendered once per item.
+    Each(Each),
+    /// `= render "name", locals`, replaced by the template called `name`.
+    Partial(Partial),
+    /// `= yield` or `= yield :name` in a layout, replaced by the page or by
+    /// its `content_for :name` blocks.
+    Yield(Option<String>),
+    /// `- content_for :name do` in a page. Its nested lines are rendered by
+    /// `= yield :name` in the layout rather than in place.
+    ContentFor(String),
+}
 
+/// A filter such as `:plain` or `:javascript`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub name: String,
+    /// The nested lines of the filter with their common indentation removed.
+    pub body: Interpolated,
+}
 
-pub fn parse(haml: &str, format: Format) -> Arena {
-    let mut arena = Arena::new();
-    for (idx, ch) in haml.char_indices() {
+/// A loop over an array or a hash.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Each {
+    /// The code of the array or hash.
+    pub code: Code,
+    /// The block variables. An array item is spread over them when there
+    /// are several, and a hash entry is given as its key and value.
+    pub variables: Vec<String>,
+    /// Whether the last block variable is the index, as with
+    /// `each_with_index`.
+    pub with_index: bool,
+}
 
-    }
-    arena
+/// Another template rendered in place of a `= render` line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partial {
+    pub name: String,
+    /// The code of the hash of variables set for the partial, if any.
+    pub locals: Option<Code>,
 }
-// mod doctype;
-// pub mod element;
 
-// use crate::arena::Arena;
-// use crate::formatter::html4_formatter::Html4Formatter;
-// use crate::formatter::html5_formatter::Html5Formatter;
-// use crate::formatter::xhtml_formatter::XHtmlFormatter;
-// use crate::formatter::xml_formatter::XmlFormatter;
-// use crate::formatter::HtmlFormatter;
-// use crate::regex::{
-//     conditional_comment, prolog, sanitize, silent_comment, COMMENT_REGEX, TEXT_REGEX,
-// };
-// use crate::Format;
-// use doctype::Doctype;
-// use element::{Element, ElementType};
-// use regex::Regex;
-// use std::collections::HashMap;
+/// Code whose result is inserted into the document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Script {
+    pub code: Code,
+    /// `Some(true)` for `&=`, `Some(false)` for `!=` and `None` when the
+    /// global setting applies.
+    pub escape: Option<bool>,
+    /// Whether the result is whitespace preserved, as with `~`.
+    pub preserve: bool,
+}
 
-// fn sanitize_html(line: &str) -> Option<String> {
-//     let r = Regex::new(&sanitize()).unwrap();
-//     match r.is_match(line) {
-//         true => {
-//             let caps = r.captures(line).unwrap();
-//             match caps.name("text") {
-//                 Some(m) => Some(
-//                     m.as_str()
-//                         .replace("&", "&amp;")
-//                         .replace("<", "&lt;")
-//                         .replace(">", "&gt;")
-//                         .replace("'", "&apos;")
-//                         .replace("\"", "&quot;")
-//                         .to_owned(),
-//                 ),
-//                 None => None,
-//             }
-//         }
-//         false => None,
-//     }
-// }
+impl Script {
+    /// The value of the code if it is a single or double quoted string.
+    pub fn literal(&self) -> Option<&str> {
+        let code = self.code.source();
+        let quoted = code.len() > 1
+            && ((code.starts_with('\'') && code.ends_with('\''))
+                || (code.starts_with('"') && code.ends_with('"')));
+        match quoted {
+            true => Some(&code[1..code.len() - 1]),
+            false => None,
+        }
+    }
+}
 
-// fn text_from_string(line: &str) -> Option<String> {
-//     let r = Regex::new(TEXT_REGEX).unwrap();
-//     match r.captures(line) {
-//         Some(m) => match m.name("text") {
-//             Some(n) => Some(n.as_str().to_owned()),
-//             None => None,
-//         },
-//         None => None,
-//     }
-// }
+/// Code of a template, such as the code of a `=` line or the condition of
+/// an `if`, along with its parsed form so that it is only parsed once.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Code {
+    source: String,
+    expr: Expr,
+}
 
-// fn comment(line: &str) -> Option<String> {
-//     let r = Regex::new(COMMENT_REGEX).unwrap();
-//     match r.is_match(line) {
-//         true => {
-//             let caps = r.captures(line).unwrap();
-//             if let Some(c) = caps.name("comment") {
-//                 Some(c.as_str().to_owned())
-//             } else {
-//                 None
-//             }
-//         }
-//         false => None,
-//     }
-// }
+impl Code {
+    /// Parses `source`, returning why it could not be parsed on failure.
+    pub(crate) fn parse(source: &str) -> Result<Code, String> {
+        Ok(Code::new(source, expr::parse(source)?))
+    }
 
-// fn silent(line: &str) -> Option<Haml> {
-//     let r = Regex::new(&silent_comment()).unwrap();
-//     match r.captures(line) {
-//         Some(m) => match m.name("ws") {
-//             Some(ws) => Some(Haml::SilentComment(ws.as_str().len())),
-//             None => Some(Haml::SilentComment(0)),
-//         },
-//         None => None,
-//     }
-// }
+    /// Code that has already been parsed as `expr`.
+    pub(crate) fn new(source: &str, expr: Expr) -> Code {
+        Code {
+            source: source.to_string(),
+            expr,
+        }
+    }
 
-// fn conditional(line: &str) -> Option<Haml> {
-//     let r = Regex::new(&conditional_comment()).unwrap();
-//     let mut whitespace = 0;
-//     let mut value = String::new();
-//     match r.captures(line) {
-//         Some(m) => {
-//             match m.name("ws") {
-//                 Some(ws) => whitespace = ws.as_str().len(),
-//                 None => whitespace = 0,
-//             }
-//             match m.name("val") {
-//                 Some(val) => value = val.as_str().to_string(),
-//                 None => (),
-//             }
-//             Some(Haml::ConditionalComment(whitespace, value))
-//         }
-//         None => None,
-//     }
-// }
+    /// The code as written in the template.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
 
-// #[derive(Clone, Debug, PartialEq)]
-// pub enum Haml {
-//     Root(),
-//     Element(Element),
-//     Text(String),
-//     InnerText(String),
-//     Comment(String),
-//     Prolog(Option<String>),
-//     SilentComment(usize),
-//     ConditionalComment(usize, String),
-// }
+    pub(crate) fn expr(&self) -> &Expr {
+        &self.expr
+    }
 
-// pub struct Parser<'a> {
-//     arena: Arena,
-//     format: &'a Format,
-// }
+    /// The value of the code with the variables of `scope`.
+    pub(crate) fn evaluate<'a>(&'a self, scope: &'a Scope) -> Cow<'a, Value> {
+        self.expr.evaluate(scope)
+    }
+}
 
-// impl<'a> Parser<'a> {
-//     pub fn new(format: &'a Format) -> Parser {
-//         Parser {
-//             arena: Arena::new(),
-//             format,
-//         }
-//     }
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
 
-//     pub fn parse(&mut self, haml: &str) -> &Arena {
-//         let mut previous_id = 0;
-//         let mut first_line = true;
-//         let prolog_regex = Regex::new(&prolog()).unwrap();
-//         for line in haml.lines() {
-//             println!("Hi: {}", line);
-//                 // matches lines that start with &=
-//             if let Some(sanitized_html) = sanitize_html(line) {
-//                 self.arena.insert(Haml::Text(sanitized_html), previous_id);
-//                 first_line = false;
-//             } else if let Some(sc) = silent(line) {
-//                 previous_id = self.arena.insert(sc, previous_id);
-//                 first_line = false;
-//             } else if let Some(cc) = conditional(line) {
-//                 previous_id = self.arena.insert(cc, previous_id);
-//                 first_line = false;
-//             } else if prolog_regex.is_match(line) {
-//                 first_line = false;
-//                 let caps = prolog_regex.captures(line).unwrap();
-//                 let value = match caps.name("type") {
-//                     Some(m) => match m.as_str() {
-//                         "" => None,
-//                         val => Some(val.to_string()),
-//                     },
-//                     None => None,
-//                 };
-//                 self.arena.insert(Haml::Prolog(value), previous_id);
-//             } else if let Some(el) = Element::from_string(line) {
-//                 let ws = el.whitespace;
-//                 let element = Haml::Element(el);
-                
-//                 if !first_line {
-//                     let p_id = self.arena.from_whitespace(previous_id, ws);
-//                     previous_id = self.arena.insert(element, p_id);
-//                 } else {
-//                     previous_id = self.arena.insert(element, 0);
-//                     first_line = false;
-//                 }
-//             } else if let Some(comment) = comment(line) {
-//                 previous_id = self.arena.insert(Haml::Comment(comment), previous_id);
-//                 first_line = false;
-//             } else if let Some(text_line) = text_from_string(line) {
-//                 first_line = false;
-//                 self.arena.insert(Haml::Text(text_line), previous_id);
-//             }
-//         }
-//         &self.arena
-//     }
-// }
+/// Text in which every `#{code}` is replaced by the value of its code when
+/// the template is rendered, along with the code of each parsed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Interpolated {
+    text: String,
+    segments: Vec<Segment>,
+}
 
-// // #[cfg(test)]
-// // mod test {
-// //     use super::*;
+impl Interpolated {
+    /// Parses the code of every `#{}` of `text`. On failure, returns why
+    /// along with the offset in `text` of the `#{` at fault.
+    pub(crate) fn new(text: &str) -> Result<Interpolated, (usize, String)> {
+        Ok(Interpolated {
+            text: text.to_string(),
+            segments: expr::segments(text)?,
+        })
+    }
 
-// //     #[test]
-// //     fn parse_text() {
-// //         let haml = r"\= test";
-// //         let mut p = Parser::new();
-// //         let e = p.parse(haml);
-// //         let id = e.root().children[0];
-// //         let item = e.item(id);
-// //         match &item.value {
-// //             Haml::Text(ref text) => assert_eq!("= test".to_owned(), *text),
-// //             _ => panic!("failed"),
-// //         }
-// //     }
+    /// Text whose `#{}` have already been parsed into `segments`.
+    pub(crate) fn from_segments(text: &str, segments: Vec<Segment>) -> Interpolated {
+        Interpolated {
+            text: text.to_string(),
+            segments,
+        }
+    }
 
-// //     #[test]
-// //     fn parse_element_text() {
-// //         let haml = "%hi\n\\value";
-// //         let mut p = Parser::new();
-// //         let e = p.parse(haml);
-// //         let id = e.root().children[0];
-// //         let item = e.item(id);
-// //         if let Haml::Element(el) = &item.value {
-// //             let mut it = item.children.iter();
-// //             match it.next() {
-// //                 Some(child_id) => {
-// //                     let child = e.item(*child_id);
-// //                     match &child.value {
-// //                         Haml::Text(ref txt) => assert_eq!("value".to_owned(), *txt),
-// //                         _ => panic!("Failed"),
-// //                     }
-// //                 },
-// //                 None => panic!("Failed"),
-// //             }
-// //         }
-// //     }
+    /// The text as written in the template.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
 
-// //     #[test]
-// //     fn parse_element() {
-// //         let haml = "%hi\n  .box\n    #b\n  %span";
-// //         let mut p = Parser::new();
-// //         let e = p.parse(haml);
-// //         let id = e.item(0).children[0];
-// //         let item = e.item(id);
-// //         let el = match &item.value {
-// //             Haml::Element(el) => el,
-// //             _ => panic!("failed"),
-// //         };
+    /// Whether the text has no interpolation, so that it is the same
+    /// whatever the variables.
+    pub fn is_static(&self) -> bool {
+        self.segments
+            .iter()
+            .all(|segment| matches!(segment, Segment::Text(_)))
+    }
 
-// //         assert_eq!(Some("%hi".to_owned()), el.name);
-// //         assert_eq!(ElementType::Other(), el.element_type);
-// //         assert_eq!(0, el.whitespace);
+    /// The text written as it is when it has no interpolation, with any
+    /// escaped `#{` unescaped.
+    pub(crate) fn static_text(&self) -> Option<String> {
+        let mut text = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(segment) => text.push_str(segment),
+                Segment::Code(_) => return None,
+            }
+        }
+        Some(text)
+    }
 
-// //         let mut it = item.children.iter();
-// //         let b = it.next().unwrap();
-// //         let bel = e.item(*b);
-// //         let el2 = match &bel.value {
-// //             Haml::Element(el) => el,
-// //             _ => panic!("failed")
-// //         };
-// //         assert_eq!(Some(".box".to_owned()), el2.name);
-// //         assert_eq!(ElementType::Div(), el2.element_type);
-// //         assert_eq!(2, el2.whitespace);
+    pub(crate) fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
 
-// //         let mut it2 = bel.children.iter();
-// //         let c = it2.next().unwrap();
-// //         let cel = e.item(*c);
-// //         let el3 = match &cel.value {
-// //             Haml::Element(el) => el,
-// //             _ => panic!("failed")
-// //         };
-// //         assert_eq!(Some("#b".to_owned()), el3.name);
-// //         assert_eq!(ElementType::Div(), el3.element_type);
-// //         assert_eq!(4, el3.whitespace);
+    /// Writes the text with the values of its code, escaped when `escape`
+    /// is set.
+    pub(crate) fn write(&self, scope: &Scope, escape: bool, out: &mut dyn Write) -> fmt::Result {
+        expr::write_segments(&self.segments, scope, escape, out)
+    }
 
-// //         let mut d = it.next().unwrap();
-// //         let del = e.item(*d);
-// //         let el4 = match &del.value {
-// //             Haml::Element(el) => el,
-// //             _ => panic!("failed")
-// //         };
-// //         assert_eq!(Some("%span".to_owned()), el4.name);
-// //         assert_eq!(ElementType::Other(), el4.element_type);
-// //         assert_eq!(2, el4.whitespace);
+    /// The text with the values of its code.
+    pub(crate) fn value(&self, scope: &Scope) -> String {
+        let mut value = String::new();
+        // writing to a String never fails
+        let _ = self.write(scope, false, &mut value);
+        value
+    }
+}
 
-// //     }
-// // }
+impl fmt::Display for Interpolated {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
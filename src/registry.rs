@@ -0,0 +1,220 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::context::Context;
+use crate::error::{Error, ErrorKind, Result};
+use crate::loader::{not_found, Loader};
+use crate::options::HamlOptions;
+use crate::span::Span;
+use crate::template::{Build, Template};
+
+/// A compiled template along with every file it was compiled from, its own
+/// and those of its partials, and their modification times when they were
+/// read.
+#[derive(Debug)]
+struct Cached {
+    template: Arc<Template>,
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+/// Loads the partials of a template from the registry, keeping track of
+/// the files read.
+struct Recorder<'a> {
+    registry: &'a TemplateRegistry,
+    files: RefCell<Vec<(PathBuf, Option<SystemTime>)>>,
+}
+
+impl Recorder<'_> {
+    fn read(&self, name: &str, path: &Path) -> Result<String> {
+        self.files
+            .borrow_mut()
+            .push((path.to_path_buf(), modified(path)));
+        read(name, path)
+    }
+}
+
+impl Loader for Recorder<'_> {
+    fn load(&self, name: &str) -> Result<String> {
+        self.read(name, &self.registry.partial_path(name)?)
+    }
+}
+
+/// The templates of a directory, looked up by name. `users/show` is the
+/// file `users/show.haml` under the root directory.
+///
+/// Templates are compiled the first time they are asked for and kept
+/// afterwards. With [`reload`](TemplateRegistry::reload) set, the
+/// modification time of the file and of the partials it renders is checked
+/// on every lookup and a changed template is compiled again, which is handy
+/// during development.
+///
+/// `= render "users/form"` renders the partial `users/form`, found as
+/// described in [`partial`](TemplateRegistry::partial).
+///
+/// ```no_run
+/// use haml::{Context, HamlOptions, TemplateRegistry};
+///
+/// let registry = TemplateRegistry::new("views", HamlOptions::new()).reload(true);
+/// let html = registry.render("users/show", &Context::new()).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TemplateRegistry {
+    root: PathBuf,
+    options: HamlOptions,
+    extension: String,
+    reload: bool,
+    cache: Mutex<HashMap<PathBuf, Cached>>,
+}
+
+impl TemplateRegistry {
+    pub fn new<P: Into<PathBuf>>(root: P, options: HamlOptions) -> TemplateRegistry {
+        TemplateRegistry {
+            root: root.into(),
+            options,
+            extension: "haml".to_string(),
+            reload: false,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The extension of template files, without the leading dot. Defaults
+    /// to `haml`.
+    pub fn extension(mut self, extension: &str) -> TemplateRegistry {
+        self.extension = extension.to_string();
+        self
+    }
+
+    /// Whether a template whose file changed since it was compiled is
+    /// compiled again. Defaults to false.
+    pub fn reload(mut self, reload: bool) -> TemplateRegistry {
+        self.reload = reload;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The options templates are compiled with.
+    pub fn options(&self) -> &HamlOptions {
+        &self.options
+    }
+
+    /// The compiled template called `name`.
+    pub fn get(&self, name: &str) -> Result<Arc<Template>> {
+        let path = self.path(name, false)?;
+        self.load(name, &path)
+    }
+
+    /// The compiled partial called `name`. As in Rails, the partial
+    /// `users/form` is the file `users/_form.haml`, and the template
+    /// `users/form` is used when there is no such file.
+    pub fn partial(&self, name: &str) -> Result<Arc<Template>> {
+        let path = self.partial_path(name)?;
+        self.load(name, &path)
+    }
+
+    /// Renders the template called `name` with the variables of `context`.
+    pub fn render(&self, name: &str, context: &Context) -> Result<String> {
+        self.get(name)?
+            .try_render(context)
+            .map_err(|err| err.in_template(name))
+    }
+
+    /// Renders the template called `name` inside the layout called `layout`.
+    pub fn render_in_layout(&self, name: &str, layout: &str, context: &Context) -> Result<String> {
+        let page = self.get(name)?;
+        page.try_render_in_layout(&*self.get(layout)?, context)
+    }
+
+    /// Forgets every compiled template, so each is read again the next time
+    /// it is asked for.
+    pub fn clear(&self) {
+        self.cache().clear();
+    }
+
+    /// The file of the template `name`. Names are relative paths without
+    /// their extension and may not leave the root directory.
+    fn path(&self, name: &str, partial: bool) -> Result<PathBuf> {
+        let relative = Path::new(name);
+        let valid = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        let file_name = match relative.file_name() {
+            Some(file_name) if valid => file_name.to_string_lossy(),
+            _ => return Err(not_found(name)),
+        };
+        let file_name = match partial {
+            true => format!("_{}.{}", file_name, self.extension),
+            false => format!("{}.{}", file_name, self.extension),
+        };
+        Ok(self.root.join(relative).with_file_name(file_name))
+    }
+
+    fn partial_path(&self, name: &str) -> Result<PathBuf> {
+        let path = self.path(name, true)?;
+        match path.is_file() {
+            true => Ok(path),
+            false => self.path(name, false),
+        }
+    }
+
+    fn load(&self, name: &str, path: &Path) -> Result<Arc<Template>> {
+        if let Some(cached) = self.cache().get(path) {
+            let unchanged = |(path, time): &(PathBuf, Option<SystemTime>)| modified(path) == *time;
+            if !self.reload || cached.files.iter().all(unchanged) {
+                return Ok(Arc::clone(&cached.template));
+            }
+        }
+        let recorder = Recorder {
+            registry: self,
+            files: RefCell::new(vec![]),
+        };
+        let template = recorder.read(name, path).and_then(|haml| {
+            Template::build(&haml, &self.options, Some(&recorder), &mut Build::default())
+                .map_err(|err| err.in_template(name))
+        });
+        let template = match template {
+            Ok(template) => Arc::new(template),
+            Err(err) => {
+                self.cache().remove(path);
+                return Err(err);
+            }
+        };
+        let cached = Cached {
+            template: Arc::clone(&template),
+            files: recorder.files.into_inner(),
+        };
+        self.cache().insert(path.to_path_buf(), cached);
+        Ok(template)
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, Cached>> {
+        // the cache is only ever left half updated by a panic while
+        // inserting, which leaves it usable
+        self.cache.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Loader for TemplateRegistry {
+    fn load(&self, name: &str) -> Result<String> {
+        read(name, &self.partial_path(name)?)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn read(name: &str, path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => not_found(name),
+        _ => Error::new(ErrorKind::Io(err.to_string()), Span::default(), "").in_template(name),
+    })
+}
@@ -0,0 +1,123 @@
+//! Support for the code generated from templates at compile time by the
+//! `haml_derive` crate and [`crate::build`]. Nothing here is meant to be
+//! used directly.
+
+use std::borrow::Cow;
+use std::fmt::{self, Display, Write};
+use std::sync::OnceLock;
+
+pub use serde_json::Value;
+
+pub use crate::context::Scope;
+pub use crate::expr::truthy;
+use crate::expr::{self, Expr};
+use crate::formatter::AttributeForm;
+pub use crate::formatter::{loop_locals, merge_values, NewlineWriter, Siblings};
+use crate::options::HamlOptions;
+use crate::Format;
+
+/// A template compiled into Rust code, as generated by
+/// `#[derive(HamlTemplate)]` from the `haml_derive` crate.
+pub trait HamlTemplate {
+    /// Renders the template into `out` as it goes.
+    fn render_to(&self, out: &mut dyn Write) -> fmt::Result;
+
+    fn render(&self) -> String {
+        let mut html = String::new();
+        // writing to a String never fails
+        let _ = self.render_to(&mut html);
+        html
+    }
+}
+
+/// Writes the result of a script, escaped when `escape` is set.
+pub fn write_value<T: Display + ?Sized>(
+    out: &mut dyn Write,
+    value: &T,
+    escape: bool,
+) -> fmt::Result {
+    match escape {
+        true => crate::formatter::write_escaped(out, &value.to_string()),
+        false => write!(out, "{}", value),
+    }
+}
+
+/// Writes an attribute whose value depends on the template's variables.
+/// `boolean` is the HTML of the attribute when its value is `true`.
+pub fn write_attribute(
+    out: &mut dyn Write,
+    key: &str,
+    value: &Value,
+    boolean: &str,
+    wrapper: char,
+    escape: bool,
+) -> fmt::Result {
+    match AttributeForm::of(key, value) {
+        AttributeForm::Omitted() => Ok(()),
+        AttributeForm::Boolean() => out.write_str(boolean),
+        AttributeForm::Text(text) => {
+            write!(out, " {}=", key)?;
+            crate::formatter::write_attribute_value(out, &text, wrapper, escape)
+        }
+    }
+}
+
+/// The code of a template, parsed the first time it is evaluated.
+pub struct Code {
+    source: &'static str,
+    parsed: OnceLock<Option<Expr>>,
+}
+
+impl Code {
+    pub const fn new(source: &'static str) -> Code {
+        Code {
+            source,
+            parsed: OnceLock::new(),
+        }
+    }
+
+    /// The value of the code with the variables of `scope`.
+    pub fn evaluate<'a>(&'a self, scope: &'a Scope) -> Cow<'a, Value> {
+        // the code was checked when the template was compiled
+        match self.parsed.get_or_init(|| expr::parse(self.source).ok()) {
+            Some(code) => code.evaluate(scope),
+            None => Cow::Owned(Value::Null),
+        }
+    }
+}
+
+/// Writes a value from a context as text, escaped when `escape` is set.
+pub fn write_json(out: &mut dyn Write, value: &Value, escape: bool) -> fmt::Result {
+    let value = expr::to_text(value);
+    match escape {
+        true => crate::formatter::write_escaped(out, &value),
+        false => out.write_str(&value),
+    }
+}
+
+/// A value from a context as it is written in a template.
+pub fn to_text(value: &Value) -> Cow<'_, str> {
+    expr::to_text(value)
+}
+
+/// A value written with HTML escaped.
+pub fn escaped<T: Display + ?Sized>(value: &T) -> String {
+    crate::formatter::escape_html(&value.to_string())
+}
+
+/// Writes what one of the standard filters renders from `body` in `format`
+/// with `attr_wrapper`, which are the only options they read. A filter that
+/// fails stops the render.
+pub fn write_filter(
+    out: &mut dyn Write,
+    name: &str,
+    body: &str,
+    format: Format,
+    attr_wrapper: char,
+) -> fmt::Result {
+    let options = HamlOptions::new().format(format).attr_wrapper(attr_wrapper);
+    match crate::filters::render(name, body, &options) {
+        Ok(html) => out.write_str(&html),
+        Err(_) => Err(fmt::Error),
+    }
+}
@@ -0,0 +1,254 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::context::{Context, Scope};
+use crate::error::{Error, ErrorKind, Result};
+use crate::formatter::{self, HtmlFormatter, Precomputed, Siblings};
+use crate::loader::{not_found, Loader};
+use crate::options::HamlOptions;
+use crate::parser::Haml;
+use crate::{parse_with_options, Document};
+
+/// A template compiled once and rendered any number of times.
+///
+/// Compiling parses the template and writes the HTML of everything that
+/// does not depend on the context, so rendering only evaluates the code and
+/// copies the rest. A `Template` is `Send` and `Sync` and can be shared
+/// between threads, e.g. in an `Arc`.
+///
+/// ```
+/// use haml::{Context, HamlOptions, Template};
+///
+/// let template = Template::compile("%h1 Users\n%p= name", &HamlOptions::new()).unwrap();
+/// let mut context = Context::new();
+/// context.insert("name", "Jon");
+/// assert_eq!("<h1>Users</h1>\n<p>Jon</p>", template.render(&context));
+/// ```
+#[derive(Debug)]
+pub struct Template {
+    document: Document,
+    formatter: Precomputed,
+}
+
+impl Template {
+    /// Compiles a template that renders no partials.
+    pub fn compile(haml: &str, options: &HamlOptions) -> Result<Template> {
+        Template::build(haml, options, None, &mut Build::default())
+    }
+
+    /// Compiles a template along with the partials it renders, which are
+    /// looked up in `loader`. Each partial is compiled once, however many
+    /// times it is rendered.
+    ///
+    /// Partials are compiled along with the template rather than when they
+    /// are rendered, so a partial can't render itself, not even behind an
+    /// `- if`. That fails with [`ErrorKind::PartialCycle`].
+    ///
+    /// ```
+    /// use haml::{Context, HamlOptions, Template};
+    /// use std::collections::HashMap;
+    ///
+    /// let mut partials = HashMap::new();
+    /// partials.insert("shared/title", "%h1= title");
+    /// let haml = "= render 'shared/title', title: 'Users'";
+    /// let template = Template::compile_with_loader(haml, &HamlOptions::new(), &partials).unwrap();
+    /// assert_eq!("<h1>Users</h1>", template.render(&Context::new()));
+    /// ```
+    pub fn compile_with_loader(
+        haml: &str,
+        options: &HamlOptions,
+        loader: &dyn Loader,
+    ) -> Result<Template> {
+        Template::build(haml, options, Some(loader), &mut Build::default())
+    }
+
+    /// Compiles a template and, one by one, the partials it renders that
+    /// `build` has not compiled yet.
+    pub(crate) fn build(
+        haml: &str,
+        options: &HamlOptions,
+        loader: Option<&dyn Loader>,
+        build: &mut Build,
+    ) -> Result<Template> {
+        let document = parse_with_options(haml, options)?;
+        let mut partials: HashMap<String, Arc<Template>> = HashMap::new();
+        for index in 0..document.arena.len() {
+            let item = document.arena.item(index);
+            let name = match &item.value {
+                Haml::Partial(partial) if !partials.contains_key(&partial.name) => &partial.name,
+                _ => continue,
+            };
+            if let Some(partial) = build.partials.get(name) {
+                partials.insert(name.to_string(), Arc::clone(partial));
+                continue;
+            }
+            let snippet = haml.lines().nth(item.span.line.saturating_sub(1));
+            let error = |kind: ErrorKind| Error::new(kind, item.span, snippet.unwrap_or_default());
+            if build.chain.contains(name) {
+                let mut cycle = build.chain.clone();
+                cycle.push(name.to_string());
+                return Err(error(ErrorKind::PartialCycle(cycle)));
+            }
+            let source = match loader {
+                Some(loader) => loader.load(name),
+                None => Err(not_found(name)),
+            };
+            // errors about the partial as a whole point at the render line
+            let source =
+                source.map_err(|err| match err.line() == 0 && err.template().is_none() {
+                    true => error(err.kind().clone()),
+                    false => err,
+                })?;
+            build.chain.push(name.to_string());
+            let partial = Template::build(&source, options, loader, build);
+            build.chain.pop();
+            let partial = Arc::new(partial.map_err(|err| err.in_template(name))?);
+            build
+                .partials
+                .insert(name.to_string(), Arc::clone(&partial));
+            partials.insert(name.to_string(), partial);
+        }
+        let generator = formatter::get_formatter(&document.options);
+        let formatter = Precomputed::new(generator, &document.arena, partials)?;
+        Ok(Template {
+            document,
+            formatter,
+        })
+    }
+
+    /// The parsed document the template was compiled from.
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    /// Renders the template with the variables of `context`.
+    pub fn render(&self, context: &Context) -> String {
+        self.formatter.generate(&self.document.arena, context)
+    }
+
+    /// Renders the template with the variables of `context`, failing when
+    /// one of its filters does. [`render`](Template::render) leaves out what
+    /// a failing filter would have written instead.
+    pub fn try_render(&self, context: &Context) -> Result<String> {
+        try_render_with(Scope::new(context), |scope, html| {
+            self.formatter
+                .render_scope(&self.document.arena, scope, html)
+        })
+    }
+
+    /// Renders the template into `out` as it goes.
+    pub fn render_to<W: fmt::Write>(&self, context: &Context, out: &mut W) -> fmt::Result {
+        self.formatter.render(&self.document.arena, context, out)
+    }
+
+    /// Renders the template into a byte sink such as a file or a response
+    /// body.
+    pub fn write_to<W: io::Write>(&self, context: &Context, out: &mut W) -> io::Result<()> {
+        formatter::write_io(out, |out| {
+            self.formatter.render(&self.document.arena, context, out)
+        })
+    }
+
+    /// Renders the template as a page inside `layout`, whose `= yield`
+    /// renders the page and whose `= yield :name` renders the
+    /// `content_for :name` blocks of the page.
+    ///
+    /// ```
+    /// use haml::{Context, HamlOptions, Template};
+    ///
+    /// let options = HamlOptions::new();
+    /// let layout = Template::compile("%title= yield :title\n%main= yield", &options);
+    /// let page = Template::compile("- content_for :title do\n  Users\n%p Jon", &options);
+    /// let html = page.unwrap().render_in_layout(&layout.unwrap(), &Context::new());
+    /// assert_eq!("<title>Users</title>\n<main><p>Jon</p></main>", html);
+    /// ```
+    pub fn render_in_layout(&self, layout: &Template, context: &Context) -> String {
+        let mut html = String::new();
+        // writing to a String never fails
+        let _ = self.render_in_layout_to(layout, context, &mut html);
+        html
+    }
+
+    /// Renders the template as a page inside `layout`, failing when one of
+    /// the filters of either does.
+    pub fn try_render_in_layout(&self, layout: &Template, context: &Context) -> Result<String> {
+        try_render_with(Scope::with_page(context, self), |scope, html| {
+            layout
+                .formatter
+                .render_scope(&layout.document.arena, scope, html)
+        })
+    }
+
+    /// Renders the template as a page inside `layout` into `out` as it goes.
+    pub fn render_in_layout_to<W: fmt::Write>(
+        &self,
+        layout: &Template,
+        context: &Context,
+        out: &mut W,
+    ) -> fmt::Result {
+        let scope = Scope::with_page(context, self);
+        layout
+            .formatter
+            .render_scope(&layout.document.arena, &scope, out)
+    }
+
+    /// Writes the content of the template, or of its `content_for` blocks
+    /// called `name`, flowing into the lines around the `= render` or
+    /// `= yield` line.
+    pub(crate) fn content_to_html(
+        &self,
+        name: Option<&str>,
+        scope: &Scope,
+        siblings: &mut Siblings,
+        out: &mut dyn fmt::Write,
+    ) -> fmt::Result {
+        let arena = &self.document.arena;
+        let name = match name {
+            Some(name) => name,
+            None => {
+                return self
+                    .formatter
+                    .children_to_html(arena.root(), arena, scope, siblings, out)
+            }
+        };
+        for index in &arena.root().children {
+            let item = arena.item(*index);
+            if let Haml::ContentFor(content) = &item.value {
+                if content == name {
+                    self.formatter
+                        .children_to_html(item, arena, scope, siblings, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the templates compiled for one [`Template::build`] share.
+#[derive(Default)]
+pub(crate) struct Build {
+    /// The partials compiled so far, by name.
+    partials: HashMap<String, Arc<Template>>,
+    /// The names of the partials being compiled around the current one, so
+    /// that a partial rendering itself is reported instead of recursing
+    /// forever.
+    chain: Vec<String>,
+}
+
+/// Renders into a string through `render` with `scope`, returning the
+/// failure that stopped it if there was one.
+fn try_render_with(
+    scope: Scope,
+    render: impl FnOnce(&Scope, &mut String) -> fmt::Result,
+) -> Result<String> {
+    let failure = RefCell::new(None);
+    let scope = scope.failing_into(&failure);
+    let mut html = String::new();
+    match (render(&scope, &mut html), failure.take()) {
+        (Err(_), Some(error)) => Err(error),
+        _ => Ok(html),
+    }
+}
@@ -1,6 +1,5 @@
-use haml;
 // use haml::HtmlFormat;
-use haml::Format;
+use haml::{Format, HamlOptions};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -14,7 +13,7 @@ pub trait TestCollection {
 
 impl TestCollection for Tests {
     fn run(&self) {
-        for (_, value) in self {
+        for value in self.values() {
             for (name, test) in value {
                 test.run(name);
             }
@@ -22,7 +21,7 @@ impl TestCollection for Tests {
     }
 
     fn run_test_by_name(&self, name: &str) {
-        for (_, value) in self {
+        for value in self.values() {
             for (test_name, test) in value {
                 if name == test_name {
                     test.run(name);
@@ -46,32 +45,48 @@ pub struct Test {
     pub haml: String,
     pub html: String,
     pub optional: Option<bool>,
+    pub locals: Option<serde_json::Value>,
 }
 
 impl Test {
+    pub fn options(&self) -> HamlOptions {
+        let mut options = HamlOptions::new();
+        if let Some(config) = &self.config {
+            if let Some(config_format) = &config.format {
+                options.format = match config_format.as_str() {
+                    "xhtml" => Format::XHtml(),
+                    "html4" => Format::Html4(),
+                    "xml" => Format::Xml(),
+                    _ => Format::Html5(),
+                };
+            }
+            if let Some(escape_html) = &config.escape_html {
+                options.escape_html = escape_html == "true";
+            }
+        }
+        options
+    }
+
     pub fn run(&self, name: &str) {
         println!("Running test: {}", name);
         println!("Input Haml:\n {}", self.haml);
         match self.optional {
-            Some(true) => return,
-            _ => {
-                let mut format: Format = Format::Html5();
-                if let Some(config) = &self.config {
-                    if let Some(config_format) = &config.format {
-                        match config_format.as_str() {
-                            "xhtml" => format = Format::XHtml(),
-                            "html4" => format = Format::Html4(),
-                            "html5" => format = Format::Html5(),
-                            _ => format = Format::Html5(),
-                        }
-                    }
-                }
-                println!("Format: {}", format);
-                let actual_html = haml::to_html(&self.haml, &format);
-                assert_eq!(self.html, actual_html);
-            }
+            Some(true) => (),
+            _ => self.check(),
         }
     }
+
+    /// Checks the output of the test even when it is optional.
+    pub fn check(&self) {
+        let options = self.options();
+        println!("Format: {}", options.format);
+        let actual_html = haml::render_with_options(&self.haml, &self.locals, &options).unwrap();
+        assert_eq!(self.html, actual_html);
+
+        let template = haml::Template::compile(&self.haml, &options).unwrap();
+        let context = haml::Context::from_serialize(&self.locals).unwrap();
+        assert_eq!(self.html, template.render(&context));
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -1,181 +1,1010 @@
-// extern crate haml;
-// extern crate serde;
-// extern crate serde_derive;
-// extern crate serde_json;
+extern crate haml;
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
 
-// use serde_json::Error;
+use serde_json::Error;
 
-// mod common;
-// use common::{TestCollection, Tests};
+mod common;
+use common::{TestCollection, Tests};
 
-// fn load_json() -> Result<Tests, Error> {
-//     let json = include_str!("tests.json");
-//     let tests: Tests = serde_json::from_str(&json)?;
-//     Ok(tests)
-// }
+fn load_json() -> Result<Tests, Error> {
+    let json = include_str!("tests.json");
+    let tests: Tests = serde_json::from_str(json)?;
+    Ok(tests)
+}
+
+/*
+ * Run all non-optional tests in the json file
+ */
+#[test]
+fn all() -> Result<(), Error> {
+    let tests = load_json()?;
+    tests.run();
+    Ok(())
+}
+
+#[test]
+fn section() -> Result<(), Error> {
+    let tests = load_json()?;
+    tests.run_test_section("tags with HTML-style attributes");
+    tests.run_test_section("whitespace removal");
+    tests.run_test_section("conditional comments");
+    tests.run_test_section("whitespace preservation");
+    tests.run_test_section("boolean attributes");
+    tests.run_test_section("HTML escaping");
+    tests.run_test_section("Ruby-style interpolation");
+    tests.run_test_section("internal filters");
+
+    tests.run_test_section("markup comments");
+    tests.run_test_section("silent comments");
+    tests.run_test_section("tags with Ruby-style attributes");
 
-// /*
-//  * Run all non-optional tests in the json file
-//  */
+    tests.run_test_section("tags with nested content");
+    tests.run_test_section("tags with inline content");
+    tests.run_test_section("tags with unusual CSS identifiers");
+    tests.run_test_section("tags with unusual HTML characters");
+    tests.run_test_section("basic Haml tags and CSS");
+    tests.run_test_section("headers");
+    Ok(())
+}
+
+#[test]
+fn double() -> Result<(), Error> {
+    let tests = load_json()?;
+    tests.run_test_by_name("a tag with '>' appended and nested content");
+    tests.run_test_by_name("Inline content multiple simple tags");
+    Ok(())
+}
+
+/*
+ * This is used for testing one specific test from the JSON file at a time.
+ * Pass in the key for the test data to run_test_by_name and it will execute
+ * that given test
+ */
+#[test]
+fn single() -> Result<(), Error> {
+    let tests = load_json()?;
+    // tests.run_test_by_name("a self-closing tag (HTML4)");
+    tests.run_test_by_name("HTML-style attributes separated with newlines");
+    Ok(())
+}
+
+#[test]
+fn completed() -> Result<(), Error> {
+    let tests = load_json()?;
+    tests.run_test_by_name("HTML-style 'class' as an attribute");
+    tests.run_test_by_name("an HTML 4 frameset doctype");
+    tests.run_test_by_name("HTML-style tag with a CSS id and 'id' as an attribute");
+    tests.run_test_by_name("an HTML 5 XML prolog (silent)");
+    tests.run_test_by_name("an HTML 5 doctype");
+    tests.run_test_by_name("an XHTML 1.1 doctype");
+    tests.run_test_by_name("HTML-style multiple attributes");
+    tests.run_test_by_name("an XHTML default (transitional) doctype");
+    tests.run_test_by_name("HTML-style tag with an atomic attribute");
+    tests.run_test_by_name("boolean attribute with XHTML");
+    tests.run_test_by_name("a self-closing tag ('/' modifier + HTML5)");
+    tests.run_test_by_name("a class with underscores");
+    tests.run_test_by_name("inside a textarea tag");
+    tests.run_test_by_name("boolean attribute with HTML");
+    tests.run_test_by_name("a multiply nested silent comment");
+    tests.run_test_by_name("a nested markup comment nested markup comment");
+    tests.run_test_by_name("Inline content multiple simple tags");
+    tests.run_test_by_name("Inline content tag with CSS");
+    tests.run_test_by_name("Inline content simple tag");
+    tests.run_test_by_name("a class with dashes");
+    tests.run_test_by_name("a class with underscores");
+    tests.run_test_by_name("an all-numeric class");
+    tests.run_test_by_name("a tag with PascalCase");
+    tests.run_test_by_name("Ruby-style attributes separated with newlines");
+    tests.run_test_by_name("a tag with colons");
+    tests.run_test_by_name("inside a pre tag");
+    tests.run_test_by_name("a tag with underscores");
+    tests.run_test_by_name("an inline markup comment");
+    tests.run_test_by_name("a simple Haml tag");
+    tests.run_test_by_name("a tag with a CSS class");
+    tests.run_test_by_name("a tag with multiple CSS classes");
+    tests.run_test_by_name("a tag with a CSS id");
+    tests.run_test_by_name("a tag with multiple CSS id's");
+    tests.run_test_by_name("a tag with a class followed by an id");
+    tests.run_test_by_name("a tag with an id followed by a class");
+    tests.run_test_by_name("an implicit div with a CSS id");
+    tests.run_test_by_name("an implicit div with a CSS class");
+    tests.run_test_by_name("multiple simple Haml tags");
+    tests.run_test_by_name("a tag with dashes");
+    tests.run_test_by_name("a tag with camelCase");
+    tests.run_test_by_name("code following '&='");
+    tests.run_test_by_name("an XHTML 1.1 basic doctype");
+    Ok(())
+}
 // #[test]
-// fn all() -> Result<(), Error> {
+// fn completed_nested_content() -> Result<(), Error> {
 //     let tests = load_json()?;
-//     tests.run();
+//     tests.run_test_by_name("Nested content tag with CSS");
 //     Ok(())
 // }
-
 // #[test]
-// fn section() -> Result<(), Error> {
+// fn completed_comments() -> Result<(), Error> {
 //     let tests = load_json()?;
-//     tests.run_test_section("tags with HTML-style attributes");
-//     tests.run_test_section("whitespace removal");
-//     tests.run_test_section("conditional comments");
-//     tests.run_test_section("whitespace preservation");
-//     tests.run_test_section("boolean attributes");
-//     tests.run_test_section("HTML escaping");
-//     tests.run_test_section("Ruby-style interpolation");
-//     tests.run_test_section("internal filters");
-
-//     tests.run_test_section("markup comments");
-//     tests.run_test_section("silent comments");
-//     tests.run_test_section("tags with Ruby-style attributes");
-
-//     tests.run_test_section("tags with nested content");
-//     tests.run_test_section("tags with inline content");
-//     tests.run_test_section("tags with unusual CSS identifiers");
-//     tests.run_test_section("tags with unusual HTML characters");
-//     tests.run_test_section("basic Haml tags and CSS");
-//     tests.run_test_section("headers");
+
+//     tests.run_test_by_name("a nested markup comment nested markup comment");
+//     tests.run_test_by_name("an inline markup comment");
+//     tests.run_test_by_name("a multiply nested silent comment with inconsistent indents");
+
 //     Ok(())
 // }
 
+// #[test]
+// fn completed_text() -> Result<(), Error> {
+//     let tests = load_json()?;
+
+//     tests.run_test_by_name("inside a textarea tag");
+
+//     Ok(())
+// }
 
 // #[test]
-// fn double() -> Result<(), Error> {
+// fn completed_tags() -> Result<(), Error> {
 //     let tests = load_json()?;
-//     tests.run_test_by_name("a tag with '>' appended and nested content");
-//     tests.run_test_by_name("Inline content multiple simple tags");
+
+//     tests.run_test_by_name("a self-closing tag (XHTML)");
+//     tests.run_test_by_name("a tag with multiple CSS classes");
+
 //     Ok(())
 // }
 
-// /*
-//  * This is used for testing one specific test from the JSON file at a time.
-//  * Pass in the key for the test data to run_test_by_name and it will execute
-//  * that given test
-//  */
 // #[test]
-// fn single() -> Result<(), Error> {
+// fn completed_boolean_attributes() -> Result<(), Error> {
 //     let tests = load_json()?;
-//     // tests.run_test_by_name("a self-closing tag (HTML4)");
-//     tests.run_test_by_name("HTML-style attributes separated with newlines");
+
+//     tests.run_test_by_name("boolean attribute with HTML");
+//     tests.run_test_by_name("boolean attribute with XHTML");
 //     Ok(())
 // }
 
 // #[test]
-// fn completed() -> Result<(), Error> {
+// fn completed_html_style_attributes() -> Result<(), Error> {
 //     let tests = load_json()?;
-//     tests.run_test_by_name("HTML-style 'class' as an attribute");
-//     tests.run_test_by_name("an HTML 4 frameset doctype");
-//     tests.run_test_by_name("HTML-style tag with a CSS id and 'id' as an attribute");
-//     tests.run_test_by_name("an HTML 5 XML prolog (silent)");
-//     tests.run_test_by_name("an HTML 5 doctype");
-//     tests.run_test_by_name("an XHTML 1.1 doctype");
+
 //     tests.run_test_by_name("HTML-style multiple attributes");
-//     tests.run_test_by_name("an XHTML default (transitional) doctype");
-//     tests.run_test_by_name("HTML-style tag with an atomic attribute");
-//     tests.run_test_by_name("boolean attribute with XHTML");
-//     tests.run_test_by_name("a self-closing tag ('/' modifier + HTML5)");
-//     tests.run_test_by_name("a class with underscores");
-//     tests.run_test_by_name("inside a textarea tag");
-//     tests.run_test_by_name("boolean attribute with HTML");
-//     tests.run_test_by_name("a multiply nested silent comment");
-//     tests.run_test_by_name("a nested markup comment nested markup comment");
-//     tests.run_test_by_name("Inline content multiple simple tags");
-//     tests.run_test_by_name("Inline content tag with CSS");
-//     tests.run_test_by_name("Inline content simple tag");
-//     tests.run_test_by_name("a class with dashes");
-//     tests.run_test_by_name("a class with underscores");
-//     tests.run_test_by_name("an all-numeric class");
-//     tests.run_test_by_name("a tag with PascalCase");
-//     tests.run_test_by_name("Ruby-style attributes separated with newlines");
-//     tests.run_test_by_name("a tag with colons");
-//     tests.run_test_by_name("inside a pre tag");
-//     tests.run_test_by_name("a tag with underscores");
-//     tests.run_test_by_name("an inline markup comment");
-//     tests.run_test_by_name("a simple Haml tag");
-//     tests.run_test_by_name("a tag with a CSS class");
-//     tests.run_test_by_name("a tag with multiple CSS classes");
-//     tests.run_test_by_name("a tag with a CSS id");
-//     tests.run_test_by_name("a tag with multiple CSS id's");
-//     tests.run_test_by_name("a tag with a class followed by an id");
-//     tests.run_test_by_name("a tag with an id followed by a class");
-//     tests.run_test_by_name("an implicit div with a CSS id");
-//     tests.run_test_by_name("an implicit div with a CSS class");
-//     tests.run_test_by_name("multiple simple Haml tags");
-//     tests.run_test_by_name("a tag with dashes");
-//     tests.run_test_by_name("a tag with camelCase");
-//     tests.run_test_by_name("code following '&='");
-//     tests.run_test_by_name("an XHTML 1.1 basic doctype");
 //     Ok(())
 // }
-// // #[test]
-// // fn completed_nested_content() -> Result<(), Error> {
-// //     let tests = load_json()?;
-// //     tests.run_test_by_name("Nested content tag with CSS");
-// //     Ok(())
-// // }
-// // #[test]
-// // fn completed_comments() -> Result<(), Error> {
-// //     let tests = load_json()?;
 
-// //     tests.run_test_by_name("a nested markup comment nested markup comment");
-// //     tests.run_test_by_name("an inline markup comment");
-// //     tests.run_test_by_name("a multiply nested silent comment with inconsistent indents");
+// #[test]
+// fn completed_filters() -> Result<(), Error> {
+//     let tests = load_json()?;
+
+//     tests.run_test_by_name("content in a 'css' filter (HTML)");
+
+//     Ok(())
+// }
+
+#[test]
+fn invalid_template() {
+    let err = haml::try_to_html("%p\n  %", &haml::Format::Html5()).unwrap_err();
+    assert_eq!(2, err.line());
+    assert_eq!(4, err.column());
+    assert_eq!("  %", err.snippet());
+}
+
+#[test]
+fn tab_indentation() {
+    let html = |haml: &str| haml::try_to_html(haml, &haml::Format::Html5());
+    assert_eq!("<p>\n<a>b</a>\n</p>", html("%p\n\t%a b").unwrap());
+    let err = html("%p\n\t%a\n  %b").unwrap_err();
+    assert_eq!(&haml::ErrorKind::BadIndentation, err.kind());
+    assert_eq!(3, err.line());
+}
+
+#[test]
+fn non_ascii_code() {
+    let html = |haml: &str| haml::try_to_html(haml, &haml::Format::Html5());
+    assert_eq!("&é", html("=\"&é\"").unwrap());
+    for haml in &["= a🎉", "%p{a: 🎉}", "- if a 🎉\n  %p", "%p(a=🎉)"] {
+        let err = html(haml).unwrap_err();
+        assert!(matches!(err.kind(), haml::ErrorKind::InvalidExpression(_)));
+    }
+}
+
+#[test]
+fn invalid_interpolation() {
+    let html = |haml: &str| haml::try_to_html(haml, &haml::Format::Html5());
+    let cases = [
+        ("%p #{1 +}", 1, 4),
+        ("%p\n  a #{b", 2, 5),
+        ("%p(a=\"#{a b}\")", 1, 3),
+        ("%p{a: \"#{a b}\"}", 1, 3),
+        ("= \"#{a b}\"", 1, 3),
+        (":plain\n  a\n    b #{a b}", 3, 7),
+    ];
+    for (haml, line, column) in &cases {
+        let err = html(haml).unwrap_err();
+        assert!(matches!(err.kind(), haml::ErrorKind::InvalidExpression(_)));
+        assert_eq!((*line, *column), (err.line(), err.column()), "{}", haml);
+    }
+    assert_eq!("<p>#{a b}</p>", html("%p \\#{a b}").unwrap());
+}
+
+#[test]
+fn parse_document() {
+    let document = haml::parse("!!!\n%p.a hello\n-# note").unwrap();
+    let arena = document.arena();
+    let children = &document.root().children;
+    assert_eq!(3, children.len());
+    assert_eq!(haml::Haml::Doctype(None), arena.item(children[0]).value);
+    match &arena.item(children[1]).value {
+        haml::Haml::Element(el) => {
+            assert_eq!(Some("p".to_string()), el.name());
+            assert_eq!(Some("hello"), el.inline_text.as_ref().map(|t| t.text()));
+        }
+        _ => panic!("expected an element"),
+    }
+    assert_eq!(haml::Haml::SilentComment(0), arena.item(children[2]).value);
+}
+
+#[test]
+fn options() {
+    use haml::{Format, HamlOptions, Newline};
+
+    let render =
+        |haml: &str, options: HamlOptions| haml::to_html_with_options(haml, &options).unwrap();
+
+    let options = HamlOptions::new().attr_wrapper('"');
+    assert_eq!("<p a=\"b&quot;\"></p>", render("%p(a='b\"')", options));
+
+    let options = HamlOptions::new().escape_attrs(false);
+    assert_eq!("<p a='<b>'></p>", render("%p(a='<b>')", options));
+    assert_eq!(
+        "<p a='&lt;b&gt;'></p>",
+        render("%p(a='<b>')", HamlOptions::new())
+    );
+
+    let options = HamlOptions::new().autoclose(&["foo"]);
+    assert_eq!("<foo>\n<br></br>", render("%foo\n%br", options));
+
+    let options = HamlOptions::new().preserve(&["div"]);
+    assert_eq!(
+        "<div>a\nb</div>\n<pre>\na\n</pre>",
+        render("%div\n  a\n  b\n%pre\n  a", options)
+    );
+
+    let options = HamlOptions::new().remove_whitespace(true);
+    assert_eq!(
+        "<div><p>a</p><p>b</p></div>",
+        render("%div\n  %p a\n  %p b", options)
+    );
+
+    let options = HamlOptions::new().newline(Newline::CrLf());
+    assert_eq!("<div>\r\n<p></p>\r\n</div>", render("%div\n  %p", options));
+
+    let options = HamlOptions::new()
+        .mime_type("text/xml")
+        .format(Format::Html5());
+    assert_eq!("<br />", render("%br", options));
+}
+
+#[test]
+fn render_to() {
+    use haml::{Context, HamlOptions, Newline};
+
+    let haml = "%div\n  -# skipped\n  %p> a\n  %p b\n%br";
+    let document = haml::parse(haml).unwrap();
+
+    let mut html = String::new();
+    document.render_to(&Context::new(), &mut html).unwrap();
+    assert_eq!(document.html(), html);
+    assert_eq!("<div><p>a</p><p>b</p>\n</div>\n<br>", html);
+
+    let mut bytes: Vec<u8> = vec![];
+    document.write_to(&Context::new(), &mut bytes).unwrap();
+    assert_eq!(html.as_bytes(), bytes.as_slice());
+
+    let options = HamlOptions::new().newline(Newline::CrLf());
+    let document = haml::parse_with_options(haml, &options).unwrap();
+    let mut bytes: Vec<u8> = vec![];
+    document.write_to(&Context::new(), &mut bytes).unwrap();
+    assert_eq!(
+        "<div><p>a</p><p>b</p>\r\n</div>\r\n<br>",
+        String::from_utf8(bytes).unwrap()
+    );
+}
+
+#[test]
+fn render() {
+    use serde_derive::Serialize;
+
+    #[derive(Serialize)]
+    struct User {
+        name: String,
+        admin: bool,
+    }
+
+    #[derive(Serialize)]
+    struct Page {
+        title: &'static str,
+        user: User,
+    }
+
+    let page = Page {
+        title: "<Home>",
+        user: User {
+            name: "Jon".to_string(),
+            admin: true,
+        },
+    };
+    let haml = "%h1= title\n%p= user.name\n%p Admin: #{user.admin}\n&= title";
+    assert_eq!(
+        "<h1><Home></h1>\n<p>Jon</p>\n<p>Admin: true</p>\n&lt;Home&gt;",
+        haml::render(haml, &page).unwrap()
+    );
+    assert_eq!("<p></p>", haml::render("%p= missing", &page).unwrap());
+
+    let err = haml::render("%p", &vec![1, 2]).unwrap_err();
+    assert_eq!(
+        &haml::ErrorKind::InvalidContext("the context must serialize to a map".to_string()),
+        err.kind()
+    );
+
+    let err = haml::render("%p\n  = user +", &page).unwrap_err();
+    assert_eq!((2, 5), (err.line(), err.column()));
+}
+
+#[test]
+fn interpolation() {
+    use haml::HamlOptions;
+    use serde_json::json;
+
+    let context = json!({ "name": "<Jon>", "id": 3 });
+    let render = |haml: &str, options: &HamlOptions| {
+        haml::render_with_options(haml, &context, options).unwrap()
+    };
+    let options = HamlOptions::new();
+    assert_eq!(
+        "<a href='/users/3' title='&lt;Jon&gt;'>Hi <Jon></a>",
+        render(
+            "%a(href=\"/users/#{id}\" title=\"#{name}\") Hi #{name}",
+            &options
+        )
+    );
+    assert_eq!("Hi <Jon>", render("= \"Hi #{name}\"", &options));
+    assert_eq!("#{name}", render("= '#{name}'", &options));
+    assert_eq!("\\<Jon>", render("= \"\\\\#{name}\"", &options));
+    assert_eq!("a\\#b", render("= \"a\\\\#b\"", &options));
+
+    let options = HamlOptions::new().escape_html(true);
+    assert_eq!("<p>Hi &lt;Jon&gt;</p>", render("%p Hi #{name}", &options));
+    assert_eq!("&lt;Jon&gt;!", render(":plain\n  #{name}!", &options));
+}
+
+#[test]
+fn conditionals() {
+    use serde_json::json;
+
+    let haml = "%ul\n  - if user.admin\n    %li Admin\n  - elsif user.name == 'Jon'\n    %li Jon\n  - else\n    %li Guest\n  - unless user.tags.empty?\n    %li Tagged\n  - else\n    %li Untagged";
+    let render = |context| haml::render(haml, &context).unwrap();
+    assert_eq!(
+        "<ul>\n<li>Admin</li>\n<li>Tagged</li>\n</ul>",
+        render(json!({ "user": { "admin": true, "tags": ["a"] } }))
+    );
+    assert_eq!(
+        "<ul>\n<li>Jon</li>\n<li>Untagged</li>\n</ul>",
+        render(json!({ "user": { "name": "Jon", "tags": [] } }))
+    );
+    assert_eq!(
+        "<ul>\n<li>Guest</li>\n<li>Untagged</li>\n</ul>",
+        render(json!({ "user": { "admin": false, "tags": [] } }))
+    );
+
+    let haml = "%p\n  - if hidden\n    hidden\n%p<\n  - if shown\n    %b> shown";
+    assert_eq!(
+        "<p></p>\n<p><b>shown</b></p>",
+        haml::render(haml, &json!({ "shown": 0 })).unwrap()
+    );
+}
+
+#[test]
+fn loops() {
+    use serde_json::json;
+
+    let context = json!({
+        "items": ["a", "b"],
+        "pairs": [["x", 1], ["y", 2]],
+        "prices": { "tea": 2, "cake": 3 },
+        "item": "outer"
+    });
+    let render = |haml: &str| haml::render(haml, &context).unwrap();
+
+    assert_eq!(
+        "<ul>\n<li>a</li>\n<li>b</li>\n</ul>\n<p>outer</p>",
+        render("%ul\n  - items.each do |item|\n    %li= item\n%p= item")
+    );
+    assert_eq!(
+        "<li>0: a</li>\n<li>1: b</li>",
+        render("- items.each_with_index do |item, i|\n  %li #{i}: #{item}")
+    );
+    assert_eq!(
+        "<i>x=1</i><i>y=2</i>",
+        render("- for name, n in pairs\n  %i>= \"#{name}=#{n}\"")
+    );
+    assert_eq!(
+        "cake 3\ntea 2\n[\"cake\",3]\n[\"tea\",2]",
+        render(
+            "- prices.each do |name, price|\n  #{name} #{price}\n- for pair in prices\n  = pair"
+        )
+    );
+    assert_eq!(
+        "<p>b</p>",
+        render("- items.each do |item|\n  - if item == 'b'\n    %p= item")
+    );
+    assert_eq!("", render("- missing.each do |item|\n  %p= item"));
+}
+
+#[test]
+fn template() {
+    use haml::{Context, HamlOptions, Newline, Template};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Template>();
+
+    let haml = "!!!\n%ul.nav\n  %li Home\n  - items.each do |item|\n    %li= item\n    %li.sep< |\n%p(title='#{title}')\n  %a{:href => \"/\"} Back";
+    let template = Arc::new(Template::compile(haml, &HamlOptions::new()).unwrap());
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let template = Arc::clone(&template);
+            thread::spawn(move || {
+                let mut context = Context::new();
+                context.insert("items", vec![i.to_string(), "x".to_string()]);
+                context.insert("title", i);
+                template.render(&context)
+            })
+        })
+        .collect();
+    for (i, handle) in handles.into_iter().enumerate() {
+        assert_eq!(
+            format!(
+                "<!DOCTYPE html>\n<ul class='nav'>\n<li>Home</li>\n<li>{}</li>\n<li class='sep'>|</li>\n<li>x</li>\n<li class='sep'>|</li>\n</ul>\n<p title='{}'>\n<a href='/'>Back</a>\n</p>",
+                i, i
+            ),
+            handle.join().unwrap()
+        );
+    }
+
+    let options = HamlOptions::new().newline(Newline::CrLf());
+    let static_haml = "%div\n  %p a\n  %p b";
+    let template = Template::compile(static_haml, &options).unwrap();
+    assert_eq!(
+        haml::to_html_with_options(static_haml, &options).unwrap(),
+        template.render(&Context::new())
+    );
+    let mut bytes = vec![];
+    template.write_to(&Context::new(), &mut bytes).unwrap();
+    assert_eq!(b"<div>\r\n<p>a</p>\r\n<p>b</p>\r\n</div>".to_vec(), bytes);
+    assert!(Template::compile("%p= 1 +", &options).is_err());
+}
+
+#[test]
+fn registry() {
+    use haml::{Context, ErrorKind, HamlOptions, TemplateRegistry};
+    use std::fs::{self, File};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    let root = std::env::temp_dir().join(format!("haml-registry-{}", std::process::id()));
+    fs::create_dir_all(root.join("users")).unwrap();
+    fs::write(root.join("users/show.haml"), "%h1= name").unwrap();
+    fs::write(root.join("users/_form.haml"), "%form").unwrap();
+    fs::write(root.join("broken.haml"), "%p\n%p= 1 +").unwrap();
+
+    let registry = TemplateRegistry::new(&root, HamlOptions::new());
+    let mut context = Context::new();
+    context.insert("name", "Jon");
+    assert_eq!(
+        "<h1>Jon</h1>",
+        registry.render("users/show", &context).unwrap()
+    );
+    let first = registry.get("users/show").unwrap();
+    assert!(Arc::ptr_eq(&first, &registry.get("users/show").unwrap()));
+    assert_eq!(
+        "<form></form>",
+        registry.partial("users/form").unwrap().render(&context)
+    );
+    assert_eq!(
+        "<h1>Jon</h1>",
+        registry.partial("users/show").unwrap().render(&context)
+    );
+
+    let err = registry.get("users/missing").unwrap_err();
+    assert_eq!(
+        &ErrorKind::TemplateNotFound("users/missing".to_string()),
+        err.kind()
+    );
+    let err = registry.get("../etc/passwd").unwrap_err();
+    assert_eq!(
+        &ErrorKind::TemplateNotFound("../etc/passwd".to_string()),
+        err.kind()
+    );
+    let err = registry.get("broken").unwrap_err();
+    assert_eq!(Some("broken"), err.template());
+    assert_eq!(2, err.line());
+    assert!(err.to_string().starts_with("In template 'broken': "));
+
+    // without reload a cached template is kept even when its file changes
+    fs::write(root.join("users/show.haml"), "%h2= name").unwrap();
+    let later = SystemTime::now() + Duration::from_secs(10);
+    File::options()
+        .write(true)
+        .open(root.join("users/show.haml"))
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+    assert_eq!(
+        "<h1>Jon</h1>",
+        registry.render("users/show", &context).unwrap()
+    );
+    let registry = TemplateRegistry::new(&root, HamlOptions::new()).reload(true);
+    assert_eq!(
+        "<h2>Jon</h2>",
+        registry.render("users/show", &context).unwrap()
+    );
+    let first = registry.get("users/show").unwrap();
+    fs::write(root.join("users/show.haml"), "%h3= name").unwrap();
+    File::options()
+        .write(true)
+        .open(root.join("users/show.haml"))
+        .unwrap()
+        .set_modified(later + Duration::from_secs(10))
+        .unwrap();
+    let second = registry.get("users/show").unwrap();
+    assert!(!Arc::ptr_eq(&first, &second));
+    assert_eq!("<h3>Jon</h3>", second.render(&context));
+    fs::remove_file(root.join("users/show.haml")).unwrap();
+    assert!(registry.get("users/show").is_err());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn partials() {
+    use haml::{Context, ErrorKind, HamlOptions, Template, TemplateRegistry};
+    use std::collections::HashMap;
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    let mut partials = HashMap::new();
+    partials.insert("shared/item", "%li= label\n%li= missing");
+    partials.insert("shared/title", "%h1= title || site");
+    partials.insert("a", "= render 'b'");
+    partials.insert("b", "%p\n= render 'a'");
+    partials.insert("broken", "%p\n%p= 1 +");
+    let options = HamlOptions::new();
+    let compile = |haml: &str| Template::compile_with_loader(haml, &options, &partials);
+    let mut context = Context::new();
+    context.insert("site", "Shop");
+    context.insert("items", vec!["a", "b"]);
+
+    let haml = "%ul\n  - items.each do |missing|\n    = render 'shared/item', label: missing.upcase\n= render('shared/title')\n= render partial: 'shared/title', locals: { title: 'Cart' }";
+    assert_eq!(
+        "<ul>\n<li>A</li>\n<li></li>\n<li>B</li>\n<li></li>\n</ul>\n<h1>Shop</h1>\n<h1>Cart</h1>",
+        compile(haml).unwrap().render(&context)
+    );
+
+    let err = compile("%div\n= render 'a'").unwrap_err();
+    assert_eq!(
+        &ErrorKind::PartialCycle(vec!["a".to_string(), "b".to_string(), "a".to_string()]),
+        err.kind()
+    );
+    assert_eq!(Some("b"), err.template());
+    assert_eq!(2, err.line());
+    assert!(err
+        .to_string()
+        .contains("Partial renders itself: a -> b -> a"));
+
+    // partials are compiled once each, however many times they are rendered
+    let mut diamond = HashMap::new();
+    for n in 0..40 {
+        let haml = format!("= render 'p{0}'\n= render 'p{0}'", n + 1);
+        diamond.insert(format!("p{}", n), haml);
+    }
+    diamond.insert("p40".to_string(), "%i".to_string());
+    assert!(Template::compile_with_loader("= render 'p0'", &options, &diamond).is_ok());
+
+    let mut tree = HashMap::new();
+    tree.insert(
+        "node",
+        "%li= node.name\n- if node.children\n  = render 'node'",
+    );
+    let err = Template::compile_with_loader("= render 'node'", &options, &tree).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::PartialCycle(_)));
+
+    let err = compile("%p\n  = render 'nope'").unwrap_err();
+    assert_eq!(&ErrorKind::TemplateNotFound("nope".to_string()), err.kind());
+    assert_eq!((2, 3, None), (err.line(), err.column(), err.template()));
+    let err = compile("= render 'broken'").unwrap_err();
+    assert_eq!((Some("broken"), 2), (err.template(), err.line()));
+    let err = haml::render("= render 'shared/title'", &serde_json::json!({})).unwrap_err();
+    assert_eq!(
+        &ErrorKind::TemplateNotFound("shared/title".to_string()),
+        err.kind()
+    );
+
+    let root = std::env::temp_dir().join(format!("haml-partials-{}", std::process::id()));
+    fs::create_dir_all(root.join("users")).unwrap();
+    fs::write(root.join("users/index.haml"), "= render 'users/row'").unwrap();
+    fs::write(root.join("users/_row.haml"), "%tr").unwrap();
+    let registry = TemplateRegistry::new(&root, HamlOptions::new()).reload(true);
+    assert_eq!(
+        "<tr></tr>",
+        registry.render("users/index", &context).unwrap()
+    );
+    fs::write(root.join("users/_row.haml"), "%td").unwrap();
+    File::options()
+        .write(true)
+        .open(root.join("users/_row.haml"))
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(
+        "<td></td>",
+        registry.render("users/index", &context).unwrap()
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn layouts() {
+    use haml::{Context, HamlOptions, Template, TemplateRegistry};
+    use std::collections::HashMap;
+    use std::fs;
+
+    let mut partials = HashMap::new();
+    partials.insert("shared/nav", "%nav= yield :nav");
+    let options = HamlOptions::new();
+    let layout = Template::compile_with_loader(
+        "!!!\n%html\n  %head\n    %title= yield :title\n  %body\n    = render 'shared/nav'\n    = yield\n    %aside\n      = yield :sidebar",
+        &options,
+        &partials,
+    )
+    .unwrap();
+    let page = Template::compile(
+        "- content_for :title do\n  #{title}\n%h1= title\n- content_for :sidebar do\n  %p One\n- items.each do |item|\n  %p= item\n- content_for(:sidebar) do\n  %p Two",
+        &options,
+    )
+    .unwrap();
+    let mut context = Context::new();
+    context.insert("title", "Users");
+    context.insert("items", vec!["a", "b"]);
+
+    assert_eq!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<title>Users</title>\n</head>\n<body>\n<nav></nav>\n<h1>Users</h1>\n<p>a</p>\n<p>b</p>\n<aside>\n<p>One</p>\n<p>Two</p>\n</aside>\n</body>\n</html>",
+        page.render_in_layout(&layout, &context)
+    );
+    assert_eq!("<h1>Users</h1>\n<p>a</p>\n<p>b</p>", page.render(&context));
+    assert!(layout.render(&context).contains("<title></title>"));
+
+    let root = std::env::temp_dir().join(format!("haml-layouts-{}", std::process::id()));
+    fs::create_dir_all(root.join("layouts")).unwrap();
+    fs::write(root.join("layouts/application.haml"), "%main= yield").unwrap();
+    fs::write(root.join("index.haml"), "%p= title").unwrap();
+    let registry = TemplateRegistry::new(&root, options);
+    assert_eq!(
+        "<main><p>Users</p></main>",
+        registry
+            .render_in_layout("index", "layouts/application", &context)
+            .unwrap()
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn build() {
+    use haml::ErrorKind;
+    use std::fs;
+
+    let root = std::env::temp_dir().join(format!("haml-build-{}", std::process::id()));
+    let templates = root.join("templates");
+    fs::create_dir_all(templates.join("users")).unwrap();
+    fs::write(templates.join("index.haml"), "%p Hello").unwrap();
+    fs::write(templates.join("users/show-all.haml"), "%p= user.name").unwrap();
+    fs::write(templates.join("users/notes.txt"), "not a template").unwrap();
+
+    let path = haml::build::compile_dir(&templates, &root).unwrap();
+    assert_eq!(root.join("templates.rs"), path);
+    let code = fs::read_to_string(&path).unwrap();
+    assert!(code.contains("pub fn index(context: &::haml::Context)"));
+    assert!(code.contains("pub mod users {"));
+    assert!(code.contains("pub fn show_all(context: &::haml::Context)"));
+    assert!(code.contains(r#"::std::fmt::Write::write_str(__haml_out, "<p>Hello</p>")?;"#));
+    assert!(code.contains(r#"::haml::runtime::Code::new("user.name")"#));
+    assert!(!code.contains("notes"));
+
+    fs::write(templates.join("users/edit.haml"), "%p\n= render 'form'").unwrap();
+    let err = haml::build::compile_dir(&templates, &root).unwrap_err();
+    assert_eq!(Some("users/edit"), err.template());
+    assert_eq!(2, err.line());
+    fs::remove_file(templates.join("users/edit.haml")).unwrap();
+
+    fs::write(templates.join("users/show_all.haml"), "%p").unwrap();
+    let err = haml::build::compile_dir(&templates, &root).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidTemplateName(_)));
+    assert!(err.to_string().contains("show_all"));
+    fs::remove_file(templates.join("users/show_all.haml")).unwrap();
+
+    fs::write(templates.join("self.haml"), "%p").unwrap();
+    let err = haml::build::compile_dir(&templates, &root).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidTemplateName(_)));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn filters() -> Result<(), Error> {
+    use haml::filters::{Filter, FilterContext};
+    use haml::{ErrorKind, HamlOptions};
+
+    let tests = load_json()?;
+    let section = &tests["internal filters"];
+    for name in &[
+        "content in a 'plain' filter",
+        "content in an 'escaped' filter",
+        "content in a 'preserve' filter",
+        "content in a 'css' filter (HTML)",
+        "content in a 'javascript' filter (HTML)",
+        "content in a 'css' filter (XHTML)",
+        "content in a 'javascript' filter (XHTML)",
+    ] {
+        section[*name].check();
+    }
+
+    struct Upcase;
+
+    impl Filter for Upcase {
+        fn name(&self) -> &str {
+            "upcase"
+        }
+
+        fn render(&self, body: &str, context: &FilterContext) -> Result<String, String> {
+            match body.is_empty() {
+                true => Err("nothing to shout".to_string()),
+                false => Ok(format!("{}:{}", context.format(), body.to_uppercase())),
+            }
+        }
+    }
+
+    let options = HamlOptions::new().filter(Upcase);
+    let mut context = haml::Context::new();
+    context.insert("name", "jon");
+    let template = haml::Template::compile("%p\n  :upcase\n    hi #{name}", &options).unwrap();
+    assert_eq!("<p>\nhtml5:HI JON\n</p>", template.render(&context));
+
+    let err = haml::Template::compile("%p\n  :upcase\n%p", &options).unwrap_err();
+    assert_eq!(
+        &ErrorKind::FilterFailed("nothing to shout".to_string()),
+        err.kind()
+    );
+    assert_eq!(2, err.line());
+
+    let template = haml::Template::compile("%p\n  :upcase\n    #{missing}", &options).unwrap();
+    assert_eq!("<p>\n\n</p>", template.render(&context));
+    let err = template.try_render(&context).unwrap_err();
+    assert_eq!(
+        &ErrorKind::FilterFailed("nothing to shout".to_string()),
+        err.kind()
+    );
+    assert_eq!((2, 3), (err.line(), err.column()));
+    let mut partials = std::collections::HashMap::new();
+    partials.insert("shout", ":upcase\n  #{missing}");
+    let template =
+        haml::Template::compile_with_loader("%p= render 'shout'", &options, &partials).unwrap();
+    let err = template.try_render(&context).unwrap_err();
+    assert_eq!((Some("shout"), 1), (err.template(), err.line()));
+    assert!(haml::to_html_with_options(":upcase\n  #{missing}", &options).is_err());
+
+    let err = haml::try_to_html("%p\n:shout\n  hi", &haml::Format::Html5()).unwrap_err();
+    assert_eq!(&ErrorKind::UnknownFilter("shout".to_string()), err.kind());
+    assert_eq!((2, 1), (err.line(), err.column()));
+
+    let haml = "%div\n  :markdown\n    # Hi #{name}\n\n    *one*  \n    two\n\n    ---";
+    let options = HamlOptions::new().format(haml::Format::XHtml());
+    let template = haml::Template::compile(haml, &options).unwrap();
+    assert_eq!(
+        "<div>\n<h1>Hi jon</h1>\n<p><em>one</em><br />\ntwo</p>\n<hr />\n</div>",
+        template.render(&context)
+    );
+
+    for haml in &[
+        ":markdown\n  - a\n   \u{3000}x",
+        ":markdown\n   ```\n  \u{3000}x\n   ```",
+    ] {
+        assert!(haml::Template::compile(haml, &options).is_ok());
+    }
+    Ok(())
+}
+
+#[test]
+fn html_attributes() -> Result<(), Error> {
+    let tests = load_json()?;
+    tests["tags with HTML-style attributes"]["HTML-style tag with a variable attribute"].check();
+
+    let mut context = haml::Context::new();
+    context.insert("q", "a b");
+    let haml = "%a(href=\"/search?q=#{q}&page=2\"\n   title='Say \"hi\"' hidden)";
+    let template = haml::Template::compile(haml, &haml::HamlOptions::new()).unwrap();
+    assert_eq!(
+        "<a hidden href='/search?q=a b&amp;page=2' title='Say &quot;hi&quot;'></a>",
+        template.render(&context)
+    );
+
+    let html = |haml| haml::to_html(haml, &haml::Format::Html5());
+    assert_eq!(
+        "<p a='c:\\dir' b='x\\&quot;y' c='it&#39;s'></p>",
+        html("%p(a='c:\\\\dir' b=\"x\\\\\\\"y\" c='it\\'s')")
+    );
+    assert_eq!("<p class='a true'></p>", html("%p.a(class)"));
+    Ok(())
+}
 
-// //     Ok(())
-// // }
+#[test]
+fn ruby_attributes() -> Result<(), Error> {
+    let tests = load_json()?;
+    let section = &tests["tags with Ruby-style attributes"];
+    for name in &[
+        "Ruby-style one attribute",
+        "Ruby-style attributes hash with whitespace",
+        "Ruby-style multiple attributes",
+        "Ruby-style attributes separated with newlines",
+        "Ruby-style 'class' as an attribute",
+        "Ruby-style tag with 'id' as an attribute",
+        "Ruby-style tag with a variable attribute",
+    ] {
+        section[*name].check();
+    }
+    Ok(())
+}
 
-// // #[test]
-// // fn completed_text() -> Result<(), Error> {
-// //     let tests = load_json()?;
+#[test]
+fn class_and_id_merging() -> Result<(), Error> {
+    let tests = load_json()?;
+    let html = &tests["tags with HTML-style attributes"];
+    let ruby = &tests["tags with Ruby-style attributes"];
+    for name in &[
+        "HTML-style tag with a CSS class and 'class' as an attribute",
+        "HTML-style tag with a CSS id and 'id' as an attribute",
+        "HTML-style tag with a CSS class and 'class' as a variable attribute",
+        "HTML-style tag multiple CSS classes (sorted correctly)",
+    ] {
+        html[*name].check();
+    }
+    for name in &[
+        "Ruby-style tag with a CSS class and 'class' as an attribute",
+        "Ruby-style tag with a CSS id and 'id' as an attribute",
+        "Ruby-style tag with a CSS id and a numeric 'id' as an attribute",
+        "Ruby-style tag with a CSS class and 'class' as a variable attribute",
+        "Ruby-style tag multiple CSS classes (sorted correctly)",
+    ] {
+        ruby[*name].check();
+    }
 
-// //     tests.run_test_by_name("inside a textarea tag");
+    let mut context = haml::Context::new();
+    context.insert("classes", vec!["c", "a"]);
+    context.insert("ids", vec!["x", "y"]);
+    let haml = "%p.b{class: ['a', nil, 'b']}\n%p.b.a{class: classes}\n%p#a{id: ['b', nil]}(id=ids)";
+    let template = haml::Template::compile(haml, &haml::HamlOptions::new()).unwrap();
+    assert_eq!(
+        "<p class='a b'></p>\n<p class='a b c'></p>\n<p id='a_b_x_y'></p>",
+        template.render(&context)
+    );
 
-// //     Ok(())
-// // }
+    // the shorthand alone keeps its order, and an attribute list given a
+    // key twice takes the last value
+    let haml = "%p.z.a\n%p{class: ['z', 'a', 'z']}\n%p{id: 'x', id: 'y'}\n%p#a(id='x' id='y')\n%p.z{class: 'x', class: 'y'}";
+    let template = haml::Template::compile(haml, &haml::HamlOptions::new()).unwrap();
+    assert_eq!(
+        "<p class='z a'></p>\n<p class='z a'></p>\n<p id='y'></p>\n<p id='a_y'></p>\n<p class='y z'></p>",
+        template.render(&context)
+    );
 
-// // #[test]
-// // fn completed_tags() -> Result<(), Error> {
-// //     let tests = load_json()?;
+    // other attributes given more than once take the last value
+    let haml = "%p{a: 'x', a: 'y'}\n%p(a='x'){a: 'y'}\n%p{a: 'x'}(a=b)\n%p(a='x' a)";
+    context.insert("b", "z");
+    let template = haml::Template::compile(haml, &haml::HamlOptions::new()).unwrap();
+    assert_eq!(
+        "<p a='y'></p>\n<p a='y'></p>\n<p a='z'></p>\n<p a></p>",
+        template.render(&context)
+    );
+    Ok(())
+}
 
-// //     tests.run_test_by_name("a self-closing tag (XHTML)");
-// //     tests.run_test_by_name("a tag with multiple CSS classes");
+#[test]
+fn data_attributes() {
+    let mut context = haml::Context::new();
+    context.insert("id", 5);
+    let haml = "%div{data: {user_id: id, role: \"admin\"}}";
+    let template = haml::Template::compile(haml, &haml::HamlOptions::new()).unwrap();
+    assert_eq!(
+        "<div data-role='admin' data-user-id='5'></div>",
+        template.render(&context)
+    );
+}
 
-// //     Ok(())
-// // }
+#[test]
+fn typed_attributes() {
+    use haml::{Format, HamlOptions};
 
-// // #[test]
-// // fn completed_boolean_attributes() -> Result<(), Error> {
-// //     let tests = load_json()?;
+    let mut context = haml::Context::new();
+    context.insert("on", true);
+    context.insert("off", false);
+    context.insert("tags", vec!["a", "b"]);
+    let haml = "%input{checked: true, disabled: false, title: nil, max: 10, step: 0.5}\n%input(checked=on disabled=off value=tags title=missing)";
+    let render = |format: Format| {
+        let options = HamlOptions::new().format(format);
+        let template = haml::Template::compile(haml, &options).unwrap();
+        template.render(&context)
+    };
+    assert_eq!(
+        "<input checked max='10' step='0.5'>\n<input checked value='a b'>",
+        render(Format::Html5())
+    );
+    assert_eq!(
+        "<input checked='checked' max='10' step='0.5' />\n<input checked='checked' value='a b' />",
+        render(Format::XHtml())
+    );
 
-// //     tests.run_test_by_name("boolean attribute with HTML");
-// //     tests.run_test_by_name("boolean attribute with XHTML");
-// //     Ok(())
-// // }
+    // false and nil given after another value still leave the attribute out
+    let haml = "%p(a){a: false}\n%p(a='x'){a: nil}\n%p{a: 'x'}(a=off)\n%p{a: false}(a)";
+    let template = haml::Template::compile(haml, &HamlOptions::new()).unwrap();
+    assert_eq!(
+        "<p></p>\n<p></p>\n<p></p>\n<p a></p>",
+        template.render(&context)
+    );
+    assert_eq!(
+        "<p></p>\n<p></p>\n<p></p>\n<p a></p>",
+        haml::parse(haml).unwrap().render(&context)
+    );
+}
 
-// // #[test]
-// // fn completed_html_style_attributes() -> Result<(), Error> {
-// //     let tests = load_json()?;
+#[cfg(unix)]
+#[test]
+fn command_filters() {
+    use haml::filters::Command;
+    use haml::{ErrorKind, HamlOptions};
 
-// //     tests.run_test_by_name("HTML-style multiple attributes");
-// //     Ok(())
-// // }
+    let options = HamlOptions::new()
+        .filter(Command::new("upcase", "tr").args(&["a-z", "A-Z"]))
+        .filter(Command::new("fail", "sh").args(&["-c", "exit 2"]));
+    let mut context = haml::Context::new();
+    context.insert("name", "jon");
+    let template = haml::Template::compile("%p\n  :upcase\n    hi #{name}", &options).unwrap();
+    assert_eq!("<p>\nHI JON\n</p>", template.render(&context));
 
-// // #[test]
-// // fn completed_filters() -> Result<(), Error> {
-// //     let tests = load_json()?;
+    let err = haml::Template::compile("%p\n:fail\n  body", &options).unwrap_err();
+    assert_eq!(
+        &ErrorKind::FilterFailed("sh exit status: 2".to_string()),
+        err.kind()
+    );
+    assert_eq!(2, err.line());
+    let template = haml::Template::compile("%p\n  :fail\n    #{name}", &options).unwrap();
+    let err = template.try_render(&context).unwrap_err();
+    assert_eq!(
+        &ErrorKind::FilterFailed("sh exit status: 2".to_string()),
+        err.kind()
+    );
 
-// //     tests.run_test_by_name("content in a 'css' filter (HTML)");
+    // a static body is run once, when the template is compiled
+    let log = std::env::temp_dir().join(format!("haml-command-{}", std::process::id()));
+    let script = format!("echo run >> '{}'; cat", log.display());
+    let logged = options
+        .clone()
+        .filter(Command::new("logged", "sh").args(&["-c", &script]));
+    let template = haml::Template::compile("%p\n  :logged\n    hi", &logged).unwrap();
+    assert_eq!("<p>\nhi\n</p>", template.render(&context));
+    assert_eq!("<p>\nhi\n</p>", template.render(&context));
+    let runs = std::fs::read_to_string(&log).unwrap();
+    let _ = std::fs::remove_file(&log);
+    assert_eq!("run\n", runs);
 
-// //     Ok(())
-// // }
+    let options = options.allow_commands(false);
+    let err = haml::Template::compile("%p\n  :upcase\n    hi", &options).unwrap_err();
+    assert_eq!(&ErrorKind::FilterDisabled("upcase".to_string()), err.kind());
+    assert_eq!((2, 3), (err.line(), err.column()));
+}